@@ -0,0 +1,532 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{Expr, FunctionArgs},
+    common::Error,
+    evaluator::RowContext,
+    record::Record,
+    schema::Collation,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AggregateFunction {
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl AggregateFunction {
+    /// The aggregate function called by `expr`, if it is a call to one.
+    pub(crate) fn of(expr: &Expr) -> Option<Self> {
+        let Expr::Function { name, args } = expr else {
+            return None;
+        };
+        let arg_count = match args {
+            FunctionArgs::Star => return name.eq_ignore_ascii_case("count").then_some(Self::Count),
+            FunctionArgs::List { args, .. } => args.len(),
+        };
+
+        match (name.to_lowercase().as_str(), arg_count) {
+            ("count", 1) => Some(Self::Count),
+            ("sum", 1) => Some(Self::Sum),
+            ("total", 1) => Some(Self::Total),
+            ("avg", 1) => Some(Self::Avg),
+            // With several arguments min() and max() are scalar functions.
+            ("min", 1) => Some(Self::Min),
+            ("max", 1) => Some(Self::Max),
+            ("group_concat", 1 | 2) => Some(Self::GroupConcat),
+            _ => None,
+        }
+    }
+
+    /// Every distinct aggregate call in the expressions, in order of first appearance.
+    pub(crate) fn collect_calls<'a>(exprs: impl IntoIterator<Item = &'a Expr>) -> Vec<Expr> {
+        let mut calls: Vec<Expr> = vec![];
+        for expr in exprs {
+            expr.walk(&mut |expr| {
+                if Self::of(expr).is_some() && !calls.contains(expr) {
+                    calls.push(expr.clone());
+                }
+            });
+        }
+        calls
+    }
+}
+
+/// Hashable form of a record, with numerically equal integers and reals mapping to the same key
+/// and text that the collation deems equal mapping to the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum KeyValue {
+    Null,
+    Int(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl KeyValue {
+    fn from(record: &Record, collation: &Collation) -> Self {
+        match record {
+            Record::Null => Self::Null,
+            Record::String(s) => Self::Text(match collation {
+                Collation::NoCase => s.to_ascii_lowercase(),
+                Collation::RTrim => s.trim_end_matches(' ').to_string(),
+                _ => s.clone(),
+            }),
+            Record::Blob(b) => Self::Blob(b.clone()),
+            Record::Real(v) if v.fract() == 0.0 && v.abs() < 9.2e18 => Self::Int(*v as i64),
+            Record::Real(v) => Self::Real(v.to_bits()),
+            other => Self::Int(other.as_int().expect("Integer record")),
+        }
+    }
+}
+
+/// Running state of one aggregate call within one group.
+#[derive(Debug)]
+enum Accumulator {
+    Count(i64),
+    Sum {
+        int_sum: Option<i64>,
+        real_sum: f64,
+        has_value: bool,
+        all_int: bool,
+    },
+    Avg {
+        sum: f64,
+        count: i64,
+    },
+    Min(Option<Record>),
+    Max(Option<Record>),
+    GroupConcat(Option<String>),
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Self::Count(0),
+            AggregateFunction::Sum | AggregateFunction::Total => Self::Sum {
+                int_sum: Some(0),
+                real_sum: 0.0,
+                has_value: false,
+                all_int: true,
+            },
+            AggregateFunction::Avg => Self::Avg { sum: 0.0, count: 0 },
+            AggregateFunction::Min => Self::Min(None),
+            AggregateFunction::Max => Self::Max(None),
+            AggregateFunction::GroupConcat => Self::GroupConcat(None),
+        }
+    }
+
+    /// Folds a value into the call. Returns whether a MIN or MAX call took it as its new extreme.
+    fn add(&mut self, value: &Record, separator: Option<Record>, collation: &Collation) -> bool {
+        match self {
+            Self::Count(n) => *n += 1,
+            Self::Sum {
+                int_sum,
+                real_sum,
+                has_value,
+                all_int,
+            } => {
+                *has_value = true;
+                let value = value.to_numeric();
+                match value.as_int() {
+                    Some(v) if !matches!(value, Record::Real(_)) => {
+                        *int_sum = int_sum.and_then(|sum| sum.checked_add(v));
+                    }
+                    _ => *all_int = false,
+                }
+                *real_sum += value.as_real().unwrap_or(0.0);
+            }
+            Self::Avg { sum, count } => {
+                *sum += value.to_numeric().as_real().unwrap_or(0.0);
+                *count += 1;
+            }
+            Self::Min(min) => {
+                if min
+                    .as_ref()
+                    .is_none_or(|min| collation.compare(value, min).is_lt())
+                {
+                    *min = Some(value.clone());
+                    return true;
+                }
+            }
+            Self::Max(max) => {
+                if max
+                    .as_ref()
+                    .is_none_or(|max| collation.compare(value, max).is_gt())
+                {
+                    *max = Some(value.clone());
+                    return true;
+                }
+            }
+            Self::GroupConcat(acc) => {
+                match acc {
+                    Some(acc) => {
+                        let separator = separator.map_or(String::from(","), |s| {
+                            if s.is_null() {
+                                String::new()
+                            } else {
+                                s.to_string()
+                            }
+                        });
+                        acc.push_str(&separator);
+                        acc.push_str(&value.to_string());
+                    }
+                    None => *acc = Some(value.to_string()),
+                };
+            }
+        }
+        false
+    }
+
+    /// The value of the call. SUM fails when its inputs were all integers and their total
+    /// overflowed, where TOTAL always returns a real.
+    fn finish(&self, function: AggregateFunction) -> Result<Record, Error> {
+        Ok(match self {
+            Self::Count(n) => Record::I64(*n),
+            Self::Sum {
+                int_sum,
+                real_sum,
+                has_value,
+                all_int,
+            } => match function {
+                AggregateFunction::Total => Record::Real(*real_sum),
+                _ if !has_value => Record::Null,
+                _ => match (all_int, int_sum) {
+                    (true, Some(sum)) => Record::I64(*sum),
+                    (true, None) => return Err(Error::from("integer overflow")),
+                    (false, _) => Record::Real(*real_sum),
+                },
+            },
+            Self::Avg { sum, count } => {
+                if *count == 0 {
+                    Record::Null
+                } else {
+                    Record::Real(sum / *count as f64)
+                }
+            }
+            Self::Min(value) | Self::Max(value) => value.clone().unwrap_or(Record::Null),
+            Self::GroupConcat(value) => value.clone().map_or(Record::Null, Record::String),
+        })
+    }
+}
+
+/// State of a single aggregate call (such as `SUM(DISTINCT price)`) within one group.
+#[derive(Debug)]
+struct AggregateState {
+    accumulator: Accumulator,
+    seen: Option<HashSet<KeyValue>>,
+}
+
+#[derive(Debug)]
+struct Group {
+    key: Vec<Record>,
+    /// Values of the source row used for bare column references: the first row of the group,
+    /// or the last row a MIN or MAX call took as its new extreme.
+    representative: Vec<Record>,
+    states: Vec<AggregateState>,
+}
+
+/// A finished group: the source row representing it and the value of each aggregate call.
+pub(crate) struct AggregateRow {
+    pub(crate) representative: Vec<Record>,
+    pub(crate) values: Vec<Record>,
+}
+
+/// Hash aggregation operator: partitions rows by the GROUP BY expressions and folds each
+/// aggregate call over the rows of every group.
+pub(crate) struct Aggregator {
+    group_by: Vec<Expr>,
+    calls: Vec<(AggregateFunction, Expr)>,
+    group_index: HashMap<Vec<KeyValue>, usize>,
+    groups: Vec<Group>,
+    /// Collations of the GROUP BY expressions and of the first argument of each call, resolved
+    /// against the first row.
+    collations: Option<(Vec<Collation>, Vec<Collation>)>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(group_by: Vec<Expr>, calls: Vec<Expr>) -> Self {
+        let calls = calls
+            .into_iter()
+            .map(|call| (AggregateFunction::of(&call).expect("Aggregate call"), call))
+            .collect();
+
+        Self {
+            group_by,
+            calls,
+            group_index: HashMap::new(),
+            groups: vec![],
+            collations: None,
+        }
+    }
+
+    pub(crate) fn add_row(
+        &mut self,
+        row: &dyn RowContext,
+        representative: &[Record],
+    ) -> Result<(), Error> {
+        if self.collations.is_none() {
+            let key_collations = self
+                .group_by
+                .iter()
+                .map(|expr| expr.collation(row))
+                .collect::<Result<_, _>>()?;
+            let call_collations = self
+                .calls
+                .iter()
+                .map(|(_, call)| match call {
+                    Expr::Function {
+                        args: FunctionArgs::List { args, .. },
+                        ..
+                    } => args[0].collation(row),
+                    _ => Ok(Collation::Binary),
+                })
+                .collect::<Result<_, _>>()?;
+            self.collations = Some((key_collations, call_collations));
+        }
+        let (key_collations, call_collations) = self.collations.as_ref().expect("Set above");
+
+        let key = self
+            .group_by
+            .iter()
+            .map(|expr| expr.eval(row))
+            .collect::<Result<Vec<_>, _>>()?;
+        let hash_key = key
+            .iter()
+            .zip(key_collations)
+            .map(|(value, collation)| KeyValue::from(value, collation))
+            .collect::<Vec<_>>();
+
+        let group_index = match self.group_index.get(&hash_key) {
+            Some(i) => *i,
+            None => {
+                let states = self
+                    .calls
+                    .iter()
+                    .map(|(function, call)| AggregateState {
+                        accumulator: Accumulator::new(*function),
+                        seen: matches!(
+                            call,
+                            Expr::Function {
+                                args: FunctionArgs::List { distinct: true, .. },
+                                ..
+                            }
+                        )
+                        .then(HashSet::new),
+                    })
+                    .collect();
+                self.groups.push(Group {
+                    key,
+                    representative: vec![],
+                    states,
+                });
+                self.group_index.insert(hash_key, self.groups.len() - 1);
+                self.groups.len() - 1
+            }
+        };
+
+        let group = &mut self.groups[group_index];
+        if group.representative.is_empty() {
+            group.representative = representative.to_vec();
+        }
+
+        for (((_, call), state), collation) in self
+            .calls
+            .iter()
+            .zip(&mut group.states)
+            .zip(call_collations)
+        {
+            let Expr::Function { args, .. } = call else {
+                unreachable!()
+            };
+            let args = match args {
+                FunctionArgs::Star => {
+                    state.accumulator.add(&Record::Null, None, collation);
+                    continue;
+                }
+                FunctionArgs::List { args, .. } => args,
+            };
+
+            // Aggregates skip NULL inputs.
+            let value = args[0].eval(row)?;
+            if value.is_null() {
+                continue;
+            }
+            if let Some(seen) = &mut state.seen
+                && !seen.insert(KeyValue::from(&value, collation))
+            {
+                continue;
+            }
+
+            let separator = args
+                .get(1)
+                .map(|separator| separator.eval(row))
+                .transpose()?;
+            if state.accumulator.add(&value, separator, collation) {
+                group.representative = representative.to_vec();
+            }
+        }
+        Ok(())
+    }
+
+    /// Final rows, one per group ordered by the group key. Without GROUP BY there is always
+    /// exactly one group, even when no rows were added.
+    pub(crate) fn finish(mut self) -> Result<Vec<AggregateRow>, Error> {
+        if self.group_by.is_empty() && self.groups.is_empty() {
+            self.groups.push(Group {
+                key: vec![],
+                representative: vec![],
+                states: self
+                    .calls
+                    .iter()
+                    .map(|(function, _)| AggregateState {
+                        accumulator: Accumulator::new(*function),
+                        seen: None,
+                    })
+                    .collect(),
+            });
+        }
+
+        let key_collations = self.collations.map(|(keys, _)| keys).unwrap_or_default();
+        self.groups.sort_by(|a, b| {
+            a.key
+                .iter()
+                .zip(&b.key)
+                .zip(&key_collations)
+                .map(|((a, b), collation)| collation.compare(a, b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let calls = self.calls;
+        self.groups
+            .into_iter()
+            .map(|group| {
+                Ok(AggregateRow {
+                    representative: group.representative,
+                    values: calls
+                        .iter()
+                        .zip(&group.states)
+                        .map(|((function, _), state)| state.accumulator.finish(*function))
+                        .collect::<Result<_, Error>>()?,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn calls(&self) -> impl Iterator<Item = &Expr> {
+        self.calls.iter().map(|(_, call)| call)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        aggregate::{AggregateFunction, Aggregator},
+        common::Error,
+        evaluator::RowContext,
+        parser::Parser,
+        record::Record,
+        schema::{Affinity, Collation},
+    };
+
+    struct TestRow(Record, Record);
+
+    impl RowContext for TestRow {
+        fn column_value(&self, _table: Option<&str>, name: &str) -> Result<Record, Error> {
+            Ok(match name {
+                "k" => self.0.clone(),
+                _ => self.1.clone(),
+            })
+        }
+
+        fn column_affinity(
+            &self,
+            _table: Option<&str>,
+            _name: &str,
+        ) -> Result<Option<Affinity>, Error> {
+            Ok(None)
+        }
+
+        fn column_collation(&self, _table: Option<&str>, _name: &str) -> Result<Collation, Error> {
+            Ok(Collation::Binary)
+        }
+    }
+
+    #[test]
+    fn test_aggregate_functions() {
+        let stmt = Parser::parse_select(
+            "SELECT COUNT(*), COUNT(v), COUNT(DISTINCT v), SUM(v), TOTAL(v), AVG(v), MIN(v), \
+             MAX(v), GROUP_CONCAT(v, '-') FROM t GROUP BY k",
+        )
+        .unwrap();
+        let calls =
+            AggregateFunction::collect_calls(stmt.columns.iter().map(|column| match column {
+                crate::ast::ResultColumn::Expr { expr, .. } => expr,
+                _ => unreachable!(),
+            }));
+        assert_eq!(9, calls.len());
+
+        let mut aggregator = Aggregator::new(stmt.group_by, calls);
+        for (k, v) in [
+            (Record::I64(1), Record::I64(3)),
+            (Record::I64(2), Record::Null),
+            (Record::I64(1), Record::I64(3)),
+            (Record::Real(1.0), Record::I64(6)),
+            (Record::I64(1), Record::Null),
+        ] {
+            aggregator.add_row(&TestRow(k, v), &[]).unwrap();
+        }
+
+        let rows = aggregator.finish().unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(
+            "4|3|2|12|12.0|4.0|3|6|3-3-6",
+            rows[0]
+                .values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("|")
+        );
+        assert_eq!(
+            "1|0|0|NULL|0.0|NULL|NULL|NULL|NULL",
+            rows[1]
+                .values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("|")
+        );
+
+        // SUM of integers fails on overflow, while TOTAL and mixed inputs fall back to reals.
+        let stmt = Parser::parse_select("SELECT SUM(v), TOTAL(v) FROM t GROUP BY k").unwrap();
+        let calls =
+            AggregateFunction::collect_calls(stmt.columns.iter().map(|column| match column {
+                crate::ast::ResultColumn::Expr { expr, .. } => expr,
+                _ => unreachable!(),
+            }));
+        let sum = |values: &[Record]| {
+            let mut aggregator = Aggregator::new(stmt.group_by.clone(), calls.clone());
+            for v in values {
+                aggregator
+                    .add_row(&TestRow(Record::I64(1), v.clone()), &[])
+                    .unwrap();
+            }
+            aggregator
+                .finish()
+                .map(|rows| rows[0].values[1].to_string())
+        };
+        let err = sum(&[Record::I64(i64::MAX), Record::I64(1)]).unwrap_err();
+        assert_eq!("integer overflow", err.to_string());
+        assert_eq!(
+            "9.22337203685478e+18",
+            sum(&[Record::I64(i64::MAX), Record::I64(1), Record::Real(0.5)]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use crate::{record::Record, schema::Collation};
+
+#[derive(Debug, Clone)]
+pub(crate) enum Statement {
+    Select(SelectStmt),
+    /// `EXPLAIN QUERY PLAN <select>`
+    ExplainQueryPlan(SelectStmt),
+    Insert(InsertStmt),
+    Update(UpdateStmt),
+    Delete(DeleteStmt),
+    /// `BEGIN [DEFERRED | IMMEDIATE | EXCLUSIVE] [TRANSACTION]`
+    Begin,
+    /// `COMMIT [TRANSACTION]` or `END [TRANSACTION]`
+    Commit,
+    /// `ROLLBACK [TRANSACTION]`
+    Rollback,
+}
+
+impl Statement {
+    /// Calls `f` on every expression of the statement and their sub-expressions, those of
+    /// subqueries included.
+    pub(crate) fn walk_exprs_mut(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        match self {
+            Statement::Select(select) | Statement::ExplainQueryPlan(select) => {
+                select.walk_exprs_mut(f)
+            }
+            Statement::Insert(insert) => match &mut insert.source {
+                InsertSource::Values(rows) => {
+                    rows.iter_mut().flatten().for_each(|expr| expr.walk_mut(f))
+                }
+                InsertSource::Select(select) => select.walk_exprs_mut(f),
+            },
+            Statement::Update(update) => {
+                for (_, expr) in &mut update.assignments {
+                    expr.walk_mut(f);
+                }
+                if let Some(expr) = &mut update.where_clause {
+                    expr.walk_mut(f);
+                }
+            }
+            Statement::Delete(delete) => {
+                if let Some(expr) = &mut delete.where_clause {
+                    expr.walk_mut(f);
+                }
+            }
+            Statement::Begin | Statement::Commit | Statement::Rollback => {}
+        }
+    }
+}
+
+/// `INSERT INTO table [(columns)] VALUES (...), ...` or `INSERT INTO table [(columns)] SELECT ...`
+#[derive(Debug, Clone)]
+pub(crate) struct InsertStmt {
+    pub(crate) table: String,
+    /// The columns given values, every column of the table when empty.
+    pub(crate) columns: Vec<String>,
+    pub(crate) source: InsertSource,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Select(Box<SelectStmt>),
+}
+
+/// `UPDATE table SET column = expr, ... [WHERE expr]`
+#[derive(Debug, Clone)]
+pub(crate) struct UpdateStmt {
+    pub(crate) table: String,
+    pub(crate) assignments: Vec<(String, Expr)>,
+    pub(crate) where_clause: Option<Expr>,
+}
+
+/// `DELETE FROM table [WHERE expr]`
+#[derive(Debug, Clone)]
+pub(crate) struct DeleteStmt {
+    pub(crate) table: String,
+    pub(crate) where_clause: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SelectStmt {
+    pub(crate) columns: Vec<ResultColumn>,
+    pub(crate) from: TableRef,
+    pub(crate) joins: Vec<Join>,
+    pub(crate) where_clause: Option<Expr>,
+    pub(crate) group_by: Vec<Expr>,
+    pub(crate) having: Option<Expr>,
+    pub(crate) order_by: Vec<OrderingTerm>,
+    pub(crate) limit: Option<Expr>,
+    pub(crate) offset: Option<Expr>,
+}
+
+impl SelectStmt {
+    /// Calls `f` on every expression of the select and their sub-expressions, those of
+    /// subqueries and derived tables included.
+    pub(crate) fn walk_exprs_mut(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        for column in &mut self.columns {
+            if let ResultColumn::Expr { expr, .. } = column {
+                expr.walk_mut(f);
+            }
+        }
+        if let Some(select) = &mut self.from.select {
+            select.walk_exprs_mut(f);
+        }
+        for join in &mut self.joins {
+            if let Some(select) = &mut join.table.select {
+                select.walk_exprs_mut(f);
+            }
+            if let Some(on) = &mut join.on {
+                on.walk_mut(f);
+            }
+        }
+        let clauses = self
+            .where_clause
+            .iter_mut()
+            .chain(&mut self.group_by)
+            .chain(&mut self.having)
+            .chain(self.order_by.iter_mut().map(|term| &mut term.expr))
+            .chain(&mut self.limit)
+            .chain(&mut self.offset);
+        for expr in clauses {
+            expr.walk_mut(f);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OrderingTerm {
+    pub(crate) expr: Expr,
+    pub(crate) descending: bool,
+    /// Explicit `NULLS FIRST` / `NULLS LAST`, otherwise NULLs sort as the smallest value.
+    pub(crate) nulls_first: Option<bool>,
+}
+
+impl OrderingTerm {
+    pub(crate) fn nulls_first(&self) -> bool {
+        self.nulls_first.unwrap_or(!self.descending)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ResultColumn {
+    /// `*`
+    Star,
+    /// `table.*`
+    TableStar(String),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TableRef {
+    /// The table name, or `(subquery-N)` for an unnamed derived table.
+    pub(crate) name: String,
+    pub(crate) alias: Option<String>,
+    /// `(SELECT ...)`, a derived table read like a table.
+    pub(crate) select: Option<Box<SelectStmt>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum JoinKind {
+    /// `[INNER] JOIN`, `CROSS JOIN` or a comma.
+    Inner,
+    /// `LEFT [OUTER] JOIN`
+    Left,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Join {
+    pub(crate) kind: JoinKind,
+    pub(crate) table: TableRef,
+    pub(crate) on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    /// `IS`: equality where NULL equals NULL.
+    Is,
+    IsNot,
+    And,
+    Or,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LikeOp {
+    Like,
+    Glob,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnaryOp {
+    Not,
+    Negate,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Literal(Record),
+    /// A placeholder for a value bound when the statement runs, numbered from 1. Named
+    /// parameters and `?NNN` keep their name as written.
+    Parameter {
+        index: usize,
+        name: Option<String>,
+    },
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Function {
+        name: String,
+        args: FunctionArgs,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Like {
+        op: LikeOp,
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+        negated: bool,
+    },
+    /// `(SELECT ...)`, the first column of the first row or NULL.
+    Subquery(Box<SelectStmt>),
+    /// `expr [NOT] IN (SELECT ...)`
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<SelectStmt>,
+        negated: bool,
+    },
+    /// `EXISTS (SELECT ...)`
+    Exists(Box<SelectStmt>),
+    /// `expr COLLATE name`, the value of `expr` compared by the named collation.
+    Collate {
+        expr: Box<Expr>,
+        collation: Collation,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FunctionArgs {
+    /// `f(*)`, as in `COUNT(*)`.
+    Star,
+    List {
+        distinct: bool,
+        args: Vec<Expr>,
+    },
+}
+
+impl Expr {
+    /// The top-level `AND`-ed terms of the expression.
+    pub(crate) fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOp::And,
+                lhs,
+                rhs,
+            } => {
+                let mut conjuncts = lhs.conjuncts();
+                conjuncts.extend(rhs.conjuncts());
+                conjuncts
+            }
+            other => vec![other],
+        }
+    }
+
+    /// Calls `f` on this expression and every sub-expression, parents before children.
+    /// Subqueries are visited but not entered, as they are queries of their own.
+    pub(crate) fn walk(&self, f: &mut dyn FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::Literal(_)
+            | Expr::Parameter { .. }
+            | Expr::Column { .. }
+            | Expr::Subquery(_)
+            | Expr::Exists(_) => {}
+            Expr::Function { args, .. } => {
+                if let FunctionArgs::List { args, .. } = args {
+                    args.iter().for_each(|arg| arg.walk(f));
+                }
+            }
+            Expr::Unary { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::InSelect { expr, .. }
+            | Expr::Collate { expr, .. } => expr.walk(f),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk(f);
+                low.walk(f);
+                high.walk(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk(f);
+                list.iter().for_each(|item| item.walk(f));
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.walk(f);
+                pattern.walk(f);
+                if let Some(escape) = escape {
+                    escape.walk(f);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` on this expression and every sub-expression, parents before children, entering
+    /// subqueries too.
+    pub(crate) fn walk_mut(&mut self, f: &mut dyn FnMut(&mut Expr)) {
+        f(self);
+        match self {
+            Expr::Literal(_) | Expr::Parameter { .. } | Expr::Column { .. } => {}
+            Expr::Subquery(select) | Expr::Exists(select) => select.walk_exprs_mut(f),
+            Expr::Function { args, .. } => {
+                if let FunctionArgs::List { args, .. } = args {
+                    args.iter_mut().for_each(|arg| arg.walk_mut(f));
+                }
+            }
+            Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Collate { expr, .. } => {
+                expr.walk_mut(f)
+            }
+            Expr::InSelect { expr, select, .. } => {
+                expr.walk_mut(f);
+                select.walk_exprs_mut(f);
+            }
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.walk_mut(f);
+                rhs.walk_mut(f);
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                expr.walk_mut(f);
+                low.walk_mut(f);
+                high.walk_mut(f);
+            }
+            Expr::InList { expr, list, .. } => {
+                expr.walk_mut(f);
+                list.iter_mut().for_each(|item| item.walk_mut(f));
+            }
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.walk_mut(f);
+                pattern.walk_mut(f);
+                if let Some(escape) = escape {
+                    escape.walk_mut(f);
+                }
+            }
+        }
+    }
+
+    /// Whether the expression runs a subquery.
+    pub(crate) fn has_subquery(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| {
+            found |= matches!(
+                expr,
+                Expr::Subquery(_) | Expr::InSelect { .. } | Expr::Exists(_)
+            );
+        });
+        found
+    }
+}
@@ -0,0 +1,393 @@
+use std::cmp::Ordering;
+
+use crate::{
+    btree_page_header::BTreePageHeader,
+    cell::{CellPayload, IndexBTreeInteriorCell, IndexBTreeLeafCell, TableBTreeLeafCell},
+    common::{BTreePageType, Error},
+    pager::{DATABASE_HEADER_SIZE, Page, Pager},
+    reader::Reader,
+};
+
+/// A page on the cursor's path from the root, with the cell the cursor is at. On a leaf, or on
+/// an interior page of an index b-tree whose own entry the cursor is at, `index` is the cell.
+/// Otherwise it is the child the path continues into, `cell_count` standing for the rightmost.
+struct Frame {
+    number: usize,
+    page: Page,
+    header: BTreePageHeader,
+    index: usize,
+}
+
+impl Frame {
+    fn count(&self) -> usize {
+        self.header.cell_count as usize
+    }
+
+    fn is_leaf(&self) -> bool {
+        !self.header.kind.is_interior()
+    }
+
+    fn cell(&self, i: usize) -> Result<Reader<'_, u8>, Error> {
+        Reader::new(&self.page[..]).at(self.header.cell_offsets[i])
+    }
+
+    /// The page number of the `i`th child, the rightmost one for `i == count`.
+    fn child(&self, i: usize) -> Result<usize, Error> {
+        let child = match self.header.rightmost_pointer {
+            Some(rightmost) if i == self.count() => rightmost,
+            _ => self.cell(i)?.peek_i32()? as u32 as usize,
+        };
+        Ok(child)
+    }
+
+    /// The rowid of a table b-tree cell, read without decoding the payload.
+    fn rowid(&self, i: usize) -> Result<i64, Error> {
+        let mut cell = self.cell(i)?;
+        match &self.header.kind {
+            BTreePageType::LeafTable => {
+                cell.pop_varint()?; // Payload size
+            }
+            BTreePageType::InteriorTable => {
+                cell.pop_i32()?; // Left child pointer
+            }
+            _ => return Err(Error::corrupt("index page in a table b-tree")),
+        }
+        cell.pop_varint()
+    }
+
+    fn payload(&self, i: usize, pager: &Pager) -> Result<CellPayload, Error> {
+        let cell = self.cell(i)?;
+        Ok(match self.header.kind {
+            BTreePageType::LeafTable => TableBTreeLeafCell::from(&cell, pager)?.payload,
+            BTreePageType::LeafIndex => IndexBTreeLeafCell::from(&cell, pager)?.payload,
+            BTreePageType::InteriorIndex => IndexBTreeInteriorCell::from(&cell, pager)?.payload,
+            BTreePageType::InteriorTable => {
+                return Err(Error::corrupt("table page in an index b-tree"));
+            }
+        })
+    }
+}
+
+/// Walks the entries of a table or index b-tree in key order, in both directions, keeping the
+/// path from the root as a stack of (page, cell index).
+///
+/// Rows of a table b-tree are only stored on its leaves, while the cells of interior index
+/// pages are entries of their own, ordered between the subtrees on each side of them.
+///
+/// Errors found decoding a page are reported with its number.
+pub(crate) struct BTreeCursor<'a> {
+    pager: &'a Pager,
+    root_page: usize,
+    stack: Vec<Frame>,
+}
+
+impl<'a> BTreeCursor<'a> {
+    pub(crate) fn new(pager: &'a Pager, root_page: usize) -> Self {
+        Self {
+            pager,
+            root_page,
+            stack: vec![],
+        }
+    }
+
+    /// Moves to the first entry. Returns false when the b-tree is empty.
+    pub(crate) fn first(&mut self) -> Result<bool, Error> {
+        self.stack.clear();
+        self.push(self.root_page, |_| Ok(0))?;
+        self.descend_first()
+    }
+
+    /// Moves to the last entry. Returns false when the b-tree is empty.
+    pub(crate) fn last(&mut self) -> Result<bool, Error> {
+        self.stack.clear();
+        self.push(self.root_page, |frame| Ok(frame.count()))?;
+        self.descend_last()
+    }
+
+    /// Moves to the next entry. Returns false, leaving the cursor unpositioned, past the last.
+    pub(crate) fn next(&mut self) -> Result<bool, Error> {
+        let Some(top) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        top.index += 1;
+        if top.is_leaf() {
+            if top.index < top.count() {
+                return Ok(true);
+            }
+            self.ascend_forward()
+        } else {
+            // From an interior index entry, on to the first entry of the subtree after it.
+            self.descend_first()
+        }
+    }
+
+    /// Moves to the previous entry. Returns false, leaving the cursor unpositioned, before the
+    /// first.
+    pub(crate) fn prev(&mut self) -> Result<bool, Error> {
+        let Some(top) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if !top.is_leaf() {
+            // From an interior index entry, on to the last entry of the subtree before it.
+            return self.descend_last();
+        }
+        if top.index == 0 {
+            return self.ascend_backward();
+        }
+        top.index -= 1;
+        Ok(true)
+    }
+
+    /// Moves to the first row of a table b-tree whose rowid is at least `rowid`. Returns false
+    /// when there is none.
+    pub(crate) fn seek_rowid(&mut self, rowid: i64) -> Result<bool, Error> {
+        self.seek(&mut |frame, i| Ok(frame.rowid(i)? >= rowid))
+    }
+
+    /// Moves to the first entry of an index b-tree that `position` doesn't order before the key
+    /// sought. Returns false when there is none.
+    pub(crate) fn seek_key(
+        &mut self,
+        position: &mut dyn FnMut(&CellPayload) -> Result<Ordering, Error>,
+    ) -> Result<bool, Error> {
+        let pager = self.pager;
+        self.seek(&mut |frame, i| Ok(position(&frame.payload(i, pager)?)?.is_ge()))
+    }
+
+    /// The rowid of the current row of a table b-tree.
+    pub(crate) fn rowid(&self) -> Result<i64, Error> {
+        let top = self.stack.last().expect("Cursor is positioned");
+        top.rowid(top.index).map_err(|err| err.on_page(top.number))
+    }
+
+    /// The payload of the current entry.
+    pub(crate) fn payload(&self) -> Result<CellPayload, Error> {
+        let top = self.stack.last().expect("Cursor is positioned");
+        top.payload(top.index, self.pager)
+            .map_err(|err| err.on_page(top.number))
+    }
+
+    /// The number of the leaf page the current entry is on, `None` for an entry of an interior
+    /// index page.
+    #[cfg(test)]
+    pub(crate) fn leaf_page(&self) -> Option<usize> {
+        let top = self.stack.last().expect("Cursor is positioned");
+        top.is_leaf().then_some(top.number)
+    }
+
+    /// Descends from the root, on every page to the first cell `at_or_after` accepts (cells are
+    /// ordered, so a binary search finds it), then on to the next entry if the leaf has none.
+    fn seek(
+        &mut self,
+        at_or_after: &mut dyn FnMut(&Frame, usize) -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        self.stack.clear();
+        let mut page = self.root_page;
+        loop {
+            self.push(page, |frame| {
+                let (mut low, mut high) = (0, frame.count());
+                while low < high {
+                    let middle = (low + high) / 2;
+                    if at_or_after(frame, middle)? {
+                        high = middle;
+                    } else {
+                        low = middle + 1;
+                    }
+                }
+                Ok(low)
+            })?;
+            let top = self.stack.last().unwrap();
+            if top.is_leaf() {
+                if top.index < top.count() {
+                    return Ok(true);
+                }
+                return self.ascend_forward();
+            }
+            page = top
+                .child(top.index)
+                .map_err(|err| err.on_page(top.number))?;
+        }
+    }
+
+    /// Reads a page onto the stack, at the cell `index` picks.
+    fn push(
+        &mut self,
+        page_number: usize,
+        index: impl FnOnce(&Frame) -> Result<usize, Error>,
+    ) -> Result<(), Error> {
+        // A page met twice on the way down makes a loop, that would never reach a leaf.
+        if self.stack.iter().any(|frame| frame.number == page_number) {
+            return Err(Error::Corrupt {
+                page: Some(page_number),
+                reason: String::from("b-tree page is its own descendant"),
+            });
+        }
+        let page = self.pager.page(page_number)?;
+        // Page 1 starts with the database header, its cell offsets still count from the page start.
+        let header_offset = if page_number == 1 {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+        let header = Reader::new(&page[..])
+            .at(header_offset)
+            .and_then(|reader| BTreePageHeader::from(&reader))
+            .map_err(|err| err.on_page(page_number))?;
+        let mut frame = Frame {
+            number: page_number,
+            page,
+            header,
+            index: 0,
+        };
+        frame.index = index(&frame).map_err(|err| err.on_page(page_number))?;
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    /// Descends from the child the top frame is at to the first entry of that subtree.
+    fn descend_first(&mut self) -> Result<bool, Error> {
+        loop {
+            let top = self.stack.last().unwrap();
+            if top.is_leaf() {
+                return Ok(top.index < top.count());
+            }
+            let child = top
+                .child(top.index)
+                .map_err(|err| err.on_page(top.number))?;
+            self.push(child, |_| Ok(0))?;
+        }
+    }
+
+    /// Descends from the child the top frame is at to the last entry of that subtree.
+    fn descend_last(&mut self) -> Result<bool, Error> {
+        loop {
+            let top = self.stack.last_mut().unwrap();
+            if top.is_leaf() {
+                let Some(last) = top.count().checked_sub(1) else {
+                    return Ok(false);
+                };
+                top.index = last;
+                return Ok(true);
+            }
+            let child = top
+                .child(top.index)
+                .map_err(|err| err.on_page(top.number))?;
+            self.push(child, |frame| Ok(frame.count()))?;
+        }
+    }
+
+    /// Leaves the exhausted subtree on top of the stack for the next entry after it.
+    fn ascend_forward(&mut self) -> Result<bool, Error> {
+        loop {
+            self.stack.pop();
+            let Some(parent) = self.stack.last_mut() else {
+                return Ok(false);
+            };
+            if parent.index < parent.count() {
+                if parent.header.kind == BTreePageType::InteriorIndex {
+                    // The interior entry after the subtree.
+                    return Ok(true);
+                }
+                parent.index += 1;
+                return self.descend_first();
+            }
+        }
+    }
+
+    /// Leaves the exhausted subtree on top of the stack for the last entry before it.
+    fn ascend_backward(&mut self) -> Result<bool, Error> {
+        loop {
+            self.stack.pop();
+            let Some(parent) = self.stack.last_mut() else {
+                return Ok(false);
+            };
+            if parent.index > 0 {
+                parent.index -= 1;
+                if parent.header.kind == BTreePageType::InteriorIndex {
+                    // The interior entry before the subtree.
+                    return Ok(true);
+                }
+                return self.descend_last();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        btree_cursor::BTreeCursor,
+        common::{Error, Schema},
+        database::Database,
+        pager::Pager,
+    };
+
+    #[test]
+    fn test_cursor_walks_and_seeks() {
+        let pager = Pager::open("sample.db").unwrap();
+        let db = Database::from(pager).unwrap();
+        let root_page = db.schema().tables["apples"].root_page;
+        let mut cursor = BTreeCursor::new(&db.pager, root_page);
+
+        let mut rowids = vec![];
+        let mut valid = cursor.first().unwrap();
+        while valid {
+            rowids.push(cursor.rowid().unwrap());
+            valid = cursor.next().unwrap();
+        }
+        assert_eq!(vec![1, 2, 3, 4], rowids);
+
+        let mut reversed = vec![];
+        let mut valid = cursor.last().unwrap();
+        while valid {
+            reversed.push(cursor.rowid().unwrap());
+            valid = cursor.prev().unwrap();
+        }
+        rowids.reverse();
+        assert_eq!(rowids, reversed);
+
+        assert!(cursor.seek_rowid(3).unwrap());
+        assert_eq!(3, cursor.rowid().unwrap());
+        assert!(cursor.prev().unwrap());
+        assert_eq!(2, cursor.rowid().unwrap());
+        assert!(!cursor.seek_rowid(5).unwrap());
+
+        // The schema table is rooted at page 1, after the database header.
+        let mut schema = BTreeCursor::new(&db.pager, 1);
+        assert!(schema.first().unwrap());
+        assert!(matches!(
+            schema.payload().unwrap().read_as_schema_definition(),
+            Ok(Schema::Table(_))
+        ));
+    }
+
+    #[test]
+    fn test_cursor_reports_corrupt_pages() {
+        let path = std::env::temp_dir().join(format!("corrupt-test-{}.db", std::process::id()));
+        fs::copy("sample.db", &path).unwrap();
+        let (root_page, page_size) = {
+            let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+            (
+                db.schema().tables["apples"].root_page,
+                db.pager.header.page_size,
+            )
+        };
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[(root_page - 1) * page_size] = 0x07;
+        fs::write(&path, bytes).unwrap();
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let err = BTreeCursor::new(&db.pager, root_page).first().unwrap_err();
+        assert!(matches!(err, Error::Corrupt { page: Some(page), .. } if page == root_page));
+        assert_eq!(
+            format!(
+                "database disk image is malformed (page {}: unknown b-tree page type 7)",
+                root_page
+            ),
+            err.to_string()
+        );
+    }
+}
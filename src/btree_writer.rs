@@ -0,0 +1,674 @@
+use std::{cmp::Ordering, ops::Range};
+
+use crate::{
+    btree_page_header::BTreePageHeader,
+    cell::{
+        CellPayload, IndexBTreeInteriorCell, IndexBTreeLeafCell, PayloadKind, local_payload_size,
+    },
+    common::{BTreePageType, Error},
+    pager::{DATABASE_HEADER_SIZE, Pager},
+    reader::Reader,
+    record::encode_varint,
+};
+
+/// The cells of a b-tree page, as read from the page or about to be laid out on one.
+struct Node {
+    kind: BTreePageType,
+    cells: Vec<Vec<u8>>,
+    rightmost: Option<usize>,
+}
+
+impl Node {
+    /// The page number of the `i`th child, the rightmost one for `i == cells.len()`.
+    fn child(&self, i: usize) -> Result<usize, Error> {
+        match self.cells.get(i) {
+            Some(cell) => Ok(Reader::new(&cell[..]).peek_i32()? as u32 as usize),
+            None => Ok(self.rightmost.unwrap()),
+        }
+    }
+
+    fn set_child(&mut self, i: usize, page: usize) {
+        match self.cells.get_mut(i) {
+            Some(cell) => cell[..4].copy_from_slice(&(page as u32).to_be_bytes()),
+            None => self.rightmost = Some(page),
+        }
+    }
+
+    /// The rowid of a table b-tree cell.
+    fn rowid(&self, i: usize) -> Result<i64, Error> {
+        let mut cell = Reader::new(&self.cells[i][..]);
+        match self.kind {
+            BTreePageType::LeafTable => {
+                cell.pop_varint()?; // Payload size
+            }
+            BTreePageType::InteriorTable => {
+                cell.pop_i32()?; // Left child pointer
+            }
+            _ => return Err(Error::corrupt("index page in a table b-tree")),
+        }
+        cell.pop_varint()
+    }
+
+    fn payload(&self, i: usize, pager: &Pager) -> Result<CellPayload, Error> {
+        let cell = Reader::new(&self.cells[i][..]);
+        Ok(match self.kind {
+            BTreePageType::LeafIndex => IndexBTreeLeafCell::from(&cell, pager)?.payload,
+            BTreePageType::InteriorIndex => IndexBTreeInteriorCell::from(&cell, pager)?.payload,
+            _ => return Err(Error::corrupt("table page in an index b-tree")),
+        })
+    }
+
+    /// Index of the first cell `after` accepts, cells being ordered.
+    fn search(&self, mut after: impl FnMut(usize) -> Result<bool, Error>) -> Result<usize, Error> {
+        let (mut low, mut high) = (0, self.cells.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if after(middle)? {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Ok(low)
+    }
+}
+
+/// The interior pages descended through from the root, with the child followed on each, then
+/// the page number of the leaf reached and the leaf.
+type Descent = (Vec<(usize, usize)>, usize, Node);
+
+/// Inserts into and deletes from table and index b-trees, splitting the pages that overflow and
+/// merging the ones left underfull.
+///
+/// The cells of a page are laid out anew on every write, leaving no free blocks behind. When
+/// they no longer fit, they are spread over the page and new siblings, and a divider for each
+/// new sibling is added to the parent, which may split in turn. A root that splits keeps its
+/// page number: its cells move to new children and it becomes an interior page over them.
+///
+/// A page less than a third full is merged with a sibling, the divider between them coming
+/// down from the parent, or shares its cells evenly with it when both don't fit on one page.
+/// Pages no longer used go to the freelist.
+pub(crate) struct BTreeWriter<'a> {
+    pager: &'a Pager,
+}
+
+impl<'a> BTreeWriter<'a> {
+    pub(crate) fn new(pager: &'a Pager) -> Self {
+        Self { pager }
+    }
+
+    /// Inserts a row into a table b-tree, replacing the row with the same rowid if there is one.
+    pub(crate) fn insert_row(
+        &self,
+        root_page: usize,
+        rowid: i64,
+        record: &[u8],
+    ) -> Result<(), Error> {
+        let mut prefix = vec![];
+        encode_varint(record.len() as u64, &mut prefix);
+        encode_varint(rowid as u64, &mut prefix);
+        let cell = self.payload_cell(prefix, record, PayloadKind::TableLeaf)?;
+
+        let (mut path, number, mut node) = self.descend_to_rowid(root_page, rowid)?;
+        let i = node.search(|i| Ok(node.rowid(i)? >= rowid))?;
+        if i < node.cells.len() && node.rowid(i)? == rowid {
+            let replaced = std::mem::replace(&mut node.cells[i], cell);
+            self.free_overflow(&replaced, &node.kind)?;
+        } else {
+            node.cells.insert(i, cell);
+        }
+        let appending = i + 1 == node.cells.len();
+        self.store(&mut path, number, node, appending)
+    }
+
+    /// Deletes the row with the given rowid from a table b-tree. Returns false when there is
+    /// no such row.
+    pub(crate) fn delete_row(&self, root_page: usize, rowid: i64) -> Result<bool, Error> {
+        let (mut path, number, mut node) = self.descend_to_rowid(root_page, rowid)?;
+        let i = node.search(|i| Ok(node.rowid(i)? >= rowid))?;
+        if i == node.cells.len() || node.rowid(i)? != rowid {
+            return Ok(false);
+        }
+
+        let cell = node.cells.remove(i);
+        self.free_overflow(&cell, &node.kind)?;
+        self.balance(&mut path, number, node)?;
+        Ok(true)
+    }
+
+    /// Descends from the root to the leaf of a table b-tree that holds, or would hold, a rowid.
+    fn descend_to_rowid(&self, root_page: usize, rowid: i64) -> Result<Descent, Error> {
+        // Cells hold the rows, or lead to the rows, with rowids up to their own.
+        self.descend(root_page, &mut |node| {
+            node.search(|i| Ok(node.rowid(i)? >= rowid))
+        })
+    }
+
+    /// Descends from `number` to a leaf, into the child `child` picks on every interior page.
+    /// Returns the path taken, as the interior pages with the child followed on each, and the
+    /// leaf.
+    fn descend(
+        &self,
+        mut number: usize,
+        child: &mut dyn FnMut(&Node) -> Result<usize, Error>,
+    ) -> Result<Descent, Error> {
+        let mut path: Vec<(usize, usize)> = vec![];
+        loop {
+            if path.iter().any(|&(page, _)| page == number) {
+                return Err(Error::Corrupt {
+                    page: Some(number),
+                    reason: String::from("b-tree page is its own descendant"),
+                });
+            }
+            let node = self.read_node(number)?;
+            if !node.kind.is_interior() {
+                return Ok((path, number, node));
+            }
+            let i = child(&node).map_err(|err| err.on_page(number))?;
+            path.push((number, i));
+            number = node.child(i).map_err(|err| err.on_page(number))?;
+        }
+    }
+
+    /// Inserts an entry into an index b-tree. `compare` orders an entry of the b-tree against
+    /// the one inserted, which goes before the first entry ordered after it.
+    pub(crate) fn insert_entry(
+        &self,
+        root_page: usize,
+        record: &[u8],
+        compare: &mut dyn FnMut(&CellPayload) -> Result<Ordering, Error>,
+    ) -> Result<(), Error> {
+        let mut prefix = vec![];
+        encode_varint(record.len() as u64, &mut prefix);
+        let cell = self.payload_cell(prefix, record, PayloadKind::Index)?;
+
+        let pager = self.pager;
+        let mut position =
+            |node: &Node| node.search(|i| Ok(compare(&node.payload(i, pager)?)?.is_gt()));
+        let (mut path, number, mut node) = self.descend(root_page, &mut position)?;
+        let i = position(&node)?;
+        node.cells.insert(i, cell);
+        let appending = i + 1 == node.cells.len();
+        self.store(&mut path, number, node, appending)
+    }
+
+    /// Deletes an entry from an index b-tree, the one `compare` orders as equal. Returns false
+    /// when there is no such entry.
+    pub(crate) fn delete_entry(
+        &self,
+        root_page: usize,
+        compare: &mut dyn FnMut(&CellPayload) -> Result<Ordering, Error>,
+    ) -> Result<bool, Error> {
+        let pager = self.pager;
+        let mut path = vec![];
+        let mut number = root_page;
+        loop {
+            let mut node = self.read_node(number)?;
+            let i = node.search(|i| Ok(compare(&node.payload(i, pager)?)?.is_ge()))?;
+            let found = i < node.cells.len() && compare(&node.payload(i, pager)?)?.is_eq();
+
+            if !node.kind.is_interior() {
+                if !found {
+                    return Ok(false);
+                }
+                let cell = node.cells.remove(i);
+                self.free_overflow(&cell, &node.kind)?;
+                self.balance(&mut path, number, node)?;
+                return Ok(true);
+            }
+            if !found {
+                path.push((number, i));
+                number = node.child(i)?;
+                continue;
+            }
+
+            // An entry of an interior page is replaced by the entry before it, the last one of
+            // the subtree on its left, taken off its leaf.
+            let (_, leaf_number, mut leaf) =
+                self.descend(node.child(i)?, &mut |node| Ok(node.cells.len()))?;
+            let Some(predecessor) = leaf.cells.pop() else {
+                return Err(Error::Corrupt {
+                    page: Some(leaf_number),
+                    reason: String::from("empty leaf under an interior index entry"),
+                });
+            };
+            self.write_node(leaf_number, &leaf)?;
+
+            let mut cell = node.cells[i][..4].to_vec();
+            cell.extend(predecessor);
+            let removed = std::mem::replace(&mut node.cells[i], cell);
+            self.free_overflow(&removed, &node.kind)?;
+            let moved = node.payload(i, pager)?;
+            self.balance(&mut path, number, node)?;
+
+            // Back to the leaf the entry was taken from, which the new interior entry leads to
+            // as the rightmost leaf of the subtree on its left.
+            let (mut path, leaf_number, leaf) = self.descend(root_page, &mut |node| {
+                let i = node.search(|i| Ok(compare(&node.payload(i, pager)?)?.is_ge()))?;
+                if i > 0 && node.payload(i - 1, pager)? == moved {
+                    Ok(i - 1)
+                } else {
+                    Ok(i)
+                }
+            })?;
+            self.balance(&mut path, leaf_number, leaf)?;
+            return Ok(true);
+        }
+    }
+
+    /// Puts the overflow pages of a cell removed from its page on the freelist.
+    fn free_overflow(&self, cell: &[u8], kind: &BTreePageType) -> Result<(), Error> {
+        let usable_size = self.pager.header.usable_size();
+        let (size, spills) = Self::cell_layout(cell, kind, usable_size)?;
+        if !spills {
+            return Ok(());
+        }
+
+        let mut page = Reader::new(&cell[size - 4..]).peek_i32()? as u32 as usize;
+        while page != 0 {
+            let next = Reader::new(&self.pager.page(page)?[..]).peek_i32()? as u32 as usize;
+            self.pager.free_page(page)?;
+            page = next;
+        }
+        Ok(())
+    }
+
+    /// Builds a cell holding a payload after `prefix`, spilling what doesn't fit on the b-tree
+    /// page to a chain of overflow pages.
+    fn payload_cell(
+        &self,
+        prefix: Vec<u8>,
+        payload: &[u8],
+        kind: PayloadKind,
+    ) -> Result<Vec<u8>, Error> {
+        let usable_size = self.pager.header.usable_size();
+        let local_size = local_payload_size(payload.len(), kind, usable_size);
+
+        let mut cell = prefix;
+        cell.extend_from_slice(&payload[..local_size]);
+        if local_size < payload.len() {
+            let chunks = payload[local_size..]
+                .chunks(usable_size - 4)
+                .collect::<Vec<_>>();
+            let pages = chunks
+                .iter()
+                .map(|_| self.pager.allocate_page())
+                .collect::<Result<Vec<_>, _>>()?;
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut page = vec![0; self.pager.header.page_size];
+                let next = pages.get(i + 1).copied().unwrap_or(0) as u32;
+                page[..4].copy_from_slice(&next.to_be_bytes());
+                page[4..4 + chunk.len()].copy_from_slice(chunk);
+                self.pager.write(pages[i], page);
+            }
+            cell.extend_from_slice(&(pages[0] as u32).to_be_bytes());
+        }
+        Ok(cell)
+    }
+
+    /// Writes a page, first splitting it if its cells don't fit. `path` leads from the root to
+    /// the page, as the parent pages with the child followed on each.
+    fn store(
+        &self,
+        path: &mut Vec<(usize, usize)>,
+        number: usize,
+        node: Node,
+        appending: bool,
+    ) -> Result<(), Error> {
+        if self.fits(number, &node) {
+            return self.write_node(number, &node);
+        }
+
+        let (groups, dividers) = self.split(node, appending)?;
+        let is_root = path.is_empty();
+        // The page keeps the first group, unless it is the root, which moves every group down.
+        let pages = groups
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                if i == 0 && !is_root {
+                    Ok(number)
+                } else {
+                    self.pager.allocate_page()
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (page, group) in pages.iter().zip(&groups) {
+            self.write_node(*page, group)?;
+        }
+
+        // Each group but the last is reached through a divider of its own.
+        let up_cells = dividers.into_iter().zip(&pages).map(|(divider, page)| {
+            let mut cell = (*page as u32).to_be_bytes().to_vec();
+            cell.extend(divider);
+            cell
+        });
+        let last_page = *pages.last().unwrap();
+
+        let Some((parent_number, child)) = path.pop() else {
+            let kind = match groups[0].kind {
+                BTreePageType::LeafTable | BTreePageType::InteriorTable => {
+                    BTreePageType::InteriorTable
+                }
+                BTreePageType::LeafIndex | BTreePageType::InteriorIndex => {
+                    BTreePageType::InteriorIndex
+                }
+            };
+            let root = Node {
+                kind,
+                cells: up_cells.collect(),
+                rightmost: Some(last_page),
+            };
+            return self.store(path, number, root, false);
+        };
+
+        let mut parent = self.read_node(parent_number)?;
+        let appending = appending && child == parent.cells.len();
+        parent.set_child(child, last_page);
+        parent.cells.splice(child..child, up_cells);
+        self.store(path, parent_number, parent, appending)
+    }
+
+    /// Writes a page whose cells changed, first splitting it if they no longer fit, or merging
+    /// it with a sibling if it is left underfull.
+    fn balance(
+        &self,
+        path: &mut Vec<(usize, usize)>,
+        number: usize,
+        node: Node,
+    ) -> Result<(), Error> {
+        if !self.fits(number, &node) {
+            return self.store(path, number, node, false);
+        }
+        let Some(&(parent_number, child)) = path.last() else {
+            return self.balance_root(number, node);
+        };
+        let mut parent = self.read_node(parent_number)?;
+        if self.used(number, &node) >= self.pager.header.usable_size() / 3
+            || parent.cells.is_empty()
+        {
+            return self.write_node(number, &node);
+        }
+
+        // Merge with the next sibling, or the previous one for the rightmost child.
+        let divider = if child < parent.cells.len() {
+            child
+        } else {
+            child - 1
+        };
+        let (left_number, right_number) = (parent.child(divider)?, parent.child(divider + 1)?);
+        let (left, right) = if divider == child {
+            (node, self.read_node(right_number)?)
+        } else {
+            (self.read_node(left_number)?, node)
+        };
+        let divider_cell = parent.cells.remove(divider);
+        parent.set_child(divider, left_number);
+        self.write_node(parent_number, &parent)?;
+        self.pager.free_page(right_number)?;
+
+        // The merged cells are split again when they don't fit on one page.
+        path.pop();
+        let mut merged_path = path.clone();
+        merged_path.push((parent_number, divider));
+        let merged = Self::merge(left, &divider_cell, right);
+        self.store(&mut merged_path, left_number, merged, false)?;
+
+        let parent = self.read_node(parent_number)?;
+        self.balance(path, parent_number, parent)
+    }
+
+    /// Writes the root page. An interior root left without cells is replaced by its only
+    /// child, making the b-tree one level shallower, unless the child doesn't fit on page 1.
+    fn balance_root(&self, number: usize, node: Node) -> Result<(), Error> {
+        if node.kind.is_interior() && node.cells.is_empty() {
+            let child_number = node.rightmost.unwrap();
+            let child = self.read_node(child_number)?;
+            if self.fits(number, &child) {
+                self.write_node(number, &child)?;
+                return self.pager.free_page(child_number);
+            }
+        }
+        self.write_node(number, &node)
+    }
+
+    /// Joins the cells of two sibling pages and of the divider between them in the parent.
+    /// Table leaves drop the divider, which only repeats a rowid of the left page.
+    fn merge(left: Node, divider: &[u8], right: Node) -> Node {
+        let mut cells = left.cells;
+        match left.kind {
+            BTreePageType::LeafTable => {}
+            BTreePageType::LeafIndex => cells.push(divider[4..].to_vec()),
+            BTreePageType::InteriorTable | BTreePageType::InteriorIndex => {
+                // The divider now leads to the rightmost child of the left page.
+                let mut cell = (left.rightmost.unwrap() as u32).to_be_bytes().to_vec();
+                cell.extend_from_slice(&divider[4..]);
+                cells.push(cell);
+            }
+        }
+        cells.extend(right.cells);
+
+        Node {
+            kind: left.kind,
+            cells,
+            rightmost: right.rightmost,
+        }
+    }
+
+    /// Spreads the cells of an overflowing page over as few pages as hold them, evenly, or
+    /// filling the first pages up when appending so that in-order inserts leave full pages.
+    ///
+    /// Returns the groups of cells with the dividers between them, which for every kind of
+    /// page but table leaves are cells taken out from between the groups. Their left child
+    /// pointer is left out, a divider of an interior page instead becoming the rightmost child
+    /// of the group before it. Table leaves keep all their cells and are divided by the rowid
+    /// of the last row of each group.
+    fn split(&self, node: Node, appending: bool) -> Result<(Vec<Node>, Vec<Vec<u8>>), Error> {
+        let kind = &node.kind;
+        let capacity = self.pager.header.usable_size() - Self::header_size(kind);
+        let sizes = node
+            .cells
+            .iter()
+            .map(|cell| cell.len() + 2)
+            .collect::<Vec<_>>();
+        let separated = *kind != BTreePageType::LeafTable;
+
+        let ranges = (2..=node.cells.len())
+            .find_map(|count| Self::partition(&sizes, capacity, separated, count, appending))
+            .expect("Cells fit on pages of their own");
+
+        let mut groups = vec![];
+        let mut dividers = vec![];
+        for (i, range) in ranges.iter().enumerate() {
+            let last = i + 1 == ranges.len();
+            let mut group = Node {
+                kind: node.kind.clone(),
+                cells: node.cells[range.clone()].to_vec(),
+                rightmost: node.rightmost,
+            };
+            if !last {
+                let divider = if separated {
+                    let separator = &node.cells[range.end];
+                    if kind.is_interior() {
+                        group.rightmost =
+                            Some(Reader::new(&separator[..]).peek_i32()? as u32 as usize);
+                        separator[4..].to_vec()
+                    } else {
+                        separator.clone()
+                    }
+                } else {
+                    let mut key = vec![];
+                    encode_varint(group.rowid(group.cells.len() - 1)? as u64, &mut key);
+                    key
+                };
+                dividers.push(divider);
+            }
+            groups.push(group);
+        }
+        Ok((groups, dividers))
+    }
+
+    /// Divides cells of the given sizes into `count` groups that each fit in `capacity`, with
+    /// one cell between consecutive groups taken out when `separated`. Returns None when they
+    /// don't fit.
+    fn partition(
+        sizes: &[usize],
+        capacity: usize,
+        separated: bool,
+        count: usize,
+        fill: bool,
+    ) -> Option<Vec<Range<usize>>> {
+        let mut ranges = vec![];
+        let mut start = 0;
+        for group in 0..count {
+            let groups_left = count - group;
+            let rest = &sizes[start..];
+            if groups_left == 1 {
+                if rest.is_empty() || rest.iter().sum::<usize>() > capacity {
+                    return None;
+                }
+                ranges.push(start..sizes.len());
+                return Some(ranges);
+            }
+
+            let target = if fill {
+                capacity
+            } else {
+                rest.iter().sum::<usize>() / groups_left
+            };
+            // Leave a cell for each later group, and one between each two when separated.
+            let reserved = (groups_left - 1) * if separated { 2 } else { 1 };
+            let mut end = start;
+            let mut used = 0;
+            while end + reserved < sizes.len()
+                && used + sizes[end] <= capacity
+                && (end == start || used + sizes[end] / 2 <= target)
+            {
+                used += sizes[end];
+                end += 1;
+            }
+            if end == start {
+                return None;
+            }
+            ranges.push(start..end);
+            start = if separated { end + 1 } else { end };
+        }
+        unreachable!()
+    }
+
+    fn header_size(kind: &BTreePageType) -> usize {
+        if kind.is_interior() { 12 } else { 8 }
+    }
+
+    /// Page 1 starts with the database header, its cell offsets still count from the page
+    /// start.
+    fn header_offset(number: usize) -> usize {
+        if number == 1 { DATABASE_HEADER_SIZE } else { 0 }
+    }
+
+    /// Bytes of the page taken by its headers, cell pointers and cells.
+    fn used(&self, number: usize, node: &Node) -> usize {
+        Self::header_offset(number)
+            + Self::header_size(&node.kind)
+            + node.cells.iter().map(|cell| cell.len() + 2).sum::<usize>()
+    }
+
+    fn fits(&self, number: usize, node: &Node) -> bool {
+        self.used(number, node) <= self.pager.header.usable_size()
+    }
+
+    fn read_node(&self, number: usize) -> Result<Node, Error> {
+        let page = self.pager.page(number)?;
+        let read = || {
+            let page = Reader::new(&page[..]);
+            let header = BTreePageHeader::from(&page.at(Self::header_offset(number))?)?;
+            let usable_size = self.pager.header.usable_size();
+
+            let cells = header
+                .cell_offsets
+                .iter()
+                .map(|offset| {
+                    let cell = page.at(*offset)?;
+                    let size =
+                        Self::cell_layout(cell.peek(cell.len())?, &header.kind, usable_size)?.0;
+                    Ok(cell.peek(size)?.to_vec())
+                })
+                .collect::<Result<_, Error>>()?;
+
+            Ok(Node {
+                kind: header.kind,
+                cells,
+                rightmost: header.rightmost_pointer,
+            })
+        };
+        read().map_err(|err: Error| err.on_page(number))
+    }
+
+    /// The size of the cell at the start of `cell`, including the overflow page number of a
+    /// payload that spills over, its last four bytes, and whether it does.
+    fn cell_layout(
+        cell: &[u8],
+        kind: &BTreePageType,
+        usable_size: usize,
+    ) -> Result<(usize, bool), Error> {
+        let mut reader = Reader::new(cell);
+        if kind.is_interior() {
+            reader.pop_i32()?; // Left child pointer
+        }
+        if *kind == BTreePageType::InteriorTable {
+            reader.pop_varint()?; // Rowid
+            return Ok((cell.len() - reader.len(), false));
+        }
+
+        let payload_size = reader.pop_varint()? as usize;
+        let payload_kind = if *kind == BTreePageType::LeafTable {
+            reader.pop_varint()?; // Rowid
+            PayloadKind::TableLeaf
+        } else {
+            PayloadKind::Index
+        };
+        let local_size = local_payload_size(payload_size, payload_kind, usable_size);
+        let spills = local_size < payload_size;
+        let overflow_pointer = if spills { 4 } else { 0 };
+        Ok((
+            cell.len() - reader.len() + local_size + overflow_pointer,
+            spills,
+        ))
+    }
+
+    /// Lays the cells out on the page from its end backwards, the cell pointer array following
+    /// the page header in cell order.
+    fn write_node(&self, number: usize, node: &Node) -> Result<(), Error> {
+        assert!(self.fits(number, node), "Cells fit on page {}", number);
+
+        let header_offset = Self::header_offset(number);
+        let mut page = vec![0; self.pager.header.page_size];
+        page[..header_offset].copy_from_slice(&self.pager.page(number)?[..header_offset]);
+
+        let mut content_start = self.pager.header.usable_size();
+        let mut pointer = header_offset + Self::header_size(&node.kind);
+        for cell in &node.cells {
+            content_start -= cell.len();
+            page[content_start..content_start + cell.len()].copy_from_slice(cell);
+            page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+            pointer += 2;
+        }
+
+        let header = &mut page[header_offset..];
+        header[0] = match node.kind {
+            BTreePageType::InteriorIndex => 2,
+            BTreePageType::InteriorTable => 5,
+            BTreePageType::LeafIndex => 10,
+            BTreePageType::LeafTable => 13,
+        };
+        header[3..5].copy_from_slice(&(node.cells.len() as u16).to_be_bytes());
+        // A content area starting at 65536 is recorded as 0.
+        header[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+        if let Some(rightmost) = node.rightmost {
+            header[8..12].copy_from_slice(&(rightmost as u32).to_be_bytes());
+        }
+
+        self.pager.write(number, page);
+        Ok(())
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
-    common::{Index, Schema, Table},
+    common::{Error, Index, Schema, Table, Trigger, View},
+    pager::Pager,
     reader::Reader,
     record::{Record, RecordFormat},
-    schema::{IndexSchema, TableSchema},
+    schema::{IndexSchema, TableFieldKind, TableSchema, ViewSchema},
 };
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) struct CellPayload {
     bytes: Vec<u8>,
 }
@@ -15,141 +16,207 @@ impl CellPayload {
         Self { bytes }
     }
 
-    pub(crate) fn read_as_schema_definition(&self) -> Schema {
-        let mut reader = Reader::new(&self.bytes[..]);
-
-        reader.pop_varint(); // Size of record header (varint)
-
-        let schema_type_header = RecordFormat::from(reader.pop_varint());
-        let schema_name_header = RecordFormat::from(reader.pop_varint());
-        let table_name_header = RecordFormat::from(reader.pop_varint());
-        let root_page_header = RecordFormat::from(reader.pop_varint()); // Serial type for sqlite_schema.rootpage (varint)
-        let sql_schema_header = RecordFormat::from(reader.pop_varint()); // Serial type for sqlite_schema.sql (varint)
-
-        let Record::String(schema_type_header) = schema_type_header.pop_value(&mut reader) else {
-            panic!();
-        };
-        let Record::String(schema_name_header) = schema_name_header.pop_value(&mut reader) else {
-            panic!();
+    /// Reads a row of `sqlite_schema`. The indexes backing UNIQUE and PRIMARY KEY constraints
+    /// have no SQL to learn their columns from, which are left for the table to tell.
+    pub(crate) fn read_as_schema_definition(&self) -> Result<Schema, Error> {
+        let mut values = self.read_record()?.into_iter();
+        let mut next = || values.next().unwrap_or(Record::Null);
+        let (Record::String(schema_type), Record::String(name), Record::String(table_name)) =
+            (next(), next(), next())
+        else {
+            return Err(Error::corrupt("sqlite_schema row without a type and names"));
         };
-        let Record::String(table_name) = table_name_header.pop_value(&mut reader) else {
-            panic!();
+        let root_page = match next().as_int() {
+            Some(root_page) => usize::try_from(root_page).unwrap_or(0),
+            None => 0,
         };
+        let malformed =
+            |err: Error| Error::Sql(format!("malformed database schema ({}) - {}", name, err));
 
-        let root_page = root_page_header.pop_value(&mut reader).unwrap_usize();
-        let sql_schema_raw = sql_schema_header.pop_value(&mut reader);
+        let Record::String(sql) = next() else {
+            if schema_type != "index" {
+                return Err(malformed(Error::corrupt("only indexes may have no SQL")));
+            }
+            let sql_schema = IndexSchema::automatic(name.clone(), &[]);
+            return Ok(Schema::AutoIndex(Index::new(
+                table_name, name, root_page, sql_schema,
+            )));
+        };
 
-        match schema_type_header.as_str() {
+        Ok(match schema_type.as_str() {
             "index" => {
-                let sql_schema = IndexSchema::from(sql_schema_raw.unwrap_string());
-                Schema::Index(Index::new(
-                    table_name,
-                    schema_name_header,
-                    root_page,
-                    sql_schema,
-                ))
+                let sql_schema = IndexSchema::from(&sql).map_err(malformed)?;
+                Schema::Index(Index::new(table_name, name, root_page, sql_schema))
             }
             "table" => {
-                let sql_schema = TableSchema::from(sql_schema_raw.unwrap_string());
+                let sql_schema = TableSchema::from(&sql).map_err(malformed)?;
                 Schema::Table(Table::new(table_name, root_page, sql_schema))
             }
-            other => unimplemented!("Schema type {} not implemented", other),
-        }
+            "view" => {
+                let sql_schema = ViewSchema::from(&sql).map_err(malformed)?;
+                Schema::View(View::new(name, sql_schema))
+            }
+            "trigger" => Schema::Trigger(Trigger {
+                trigger_name: name,
+                table_name,
+                sql,
+            }),
+            other => {
+                return Err(malformed(Error::corrupt(format!(
+                    "unknown schema type {}",
+                    other
+                ))));
+            }
+        })
     }
 
-    pub(crate) fn read_as_table_row(&self, schema: &TableSchema) -> Vec<Record> {
-        let mut reader = Reader::new(&self.bytes[..]);
-        // dbg!(&self.bytes);
-
-        reader.pop_varint(); // Size of record header (varint)
-
-        let record_formats = &schema
-            .fields
-            .iter()
-            .map(|_| RecordFormat::from(reader.pop_varint()))
-            .collect::<Vec<_>>();
+    pub(crate) fn read_as_table_row(&self, schema: &TableSchema) -> Result<Vec<Record>, Error> {
+        let mut values = self.read_record()?;
+        // Rows written before a column was added end early, the missing columns being NULL.
+        values.resize(schema.fields.len(), Record::Null);
 
-        record_formats
-            .iter()
-            .map(|format| format.pop_value(&mut reader))
-            .collect::<Vec<_>>()
+        Ok(values
+            .into_iter()
+            .zip(&schema.fields)
+            .map(|(value, field)| {
+                // REAL columns may store integral values as integers on disk.
+                match (&field.kind, value.as_int()) {
+                    (TableFieldKind::Real, Some(v)) => Record::Real(v as f64),
+                    _ => value,
+                }
+            })
+            .collect())
     }
 
+    /// Reads an index entry: the indexed column values followed by the rowid of the table row.
     pub(crate) fn read_as_index_row(
         &self,
         index_schema: &IndexSchema,
-    ) -> (
-        Vec<Record>, /* table fields */
-        Vec<Record>, /* index fields */
-    ) {
-        // dbg!(&self.bytes);
+    ) -> Result<(Vec<Record> /* index fields */, i64 /* rowid */), Error> {
+        let mut values = self.read_record()?;
+        if values.len() != index_schema.fields.len() + 1 {
+            return Err(Error::corrupt(format!(
+                "entry of index {} with {} values",
+                index_schema.name,
+                values.len()
+            )));
+        }
+        let Some(rowid) = values.pop().and_then(|rowid| rowid.as_int()) else {
+            return Err(Error::corrupt(format!(
+                "entry of index {} without a rowid",
+                index_schema.name
+            )));
+        };
+
+        Ok((values, rowid))
+    }
 
+    /// Reads every value of the record, as many as the record header describes.
+    pub(crate) fn read_record(&self) -> Result<Vec<Record>, Error> {
         let mut reader = Reader::new(&self.bytes[..]);
-        reader.pop_varint(); // Size of record header (varint)
+        let header_start_len = reader.len();
+        let header_size = reader.pop_varint()? as usize;
 
-        let value_record_formats = &index_schema
-            .fields
-            .iter()
-            .map(|_| RecordFormat::from(reader.pop_varint()))
-            .collect::<Vec<_>>();
+        let mut record_formats = vec![];
+        while header_start_len - reader.len() < header_size {
+            record_formats.push(RecordFormat::from(reader.pop_varint()?)?);
+        }
 
-        let position_record_formats = &index_schema
-            .fields
+        record_formats
             .iter()
-            .map(|_| RecordFormat::from(reader.pop_varint()))
-            .collect::<Vec<_>>();
-
-        (
-            value_record_formats
-                .iter()
-                .map(|format| format.pop_value(&mut reader))
-                .collect(),
-            position_record_formats
-                .iter()
-                .map(|format| format.pop_value(&mut reader))
-                .collect(),
-        )
+            .map(|format| format.pop_value(&mut reader))
+            .collect()
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct TableBTreeLeafCell {
-    pub(crate) rowid: i64,
-    pub(crate) payload: CellPayload,
+/// Which kind of b-tree a payload is stored in. Table leaves and index pages have different
+/// thresholds for how much of the payload is kept on the b-tree page itself.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PayloadKind {
+    TableLeaf,
+    Index,
 }
 
-impl TableBTreeLeafCell {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Self {
-        let mut reader = reader.clone();
-        let payload_size = reader.pop_varint() as usize;
-        let rowid = reader.pop_varint();
-        let payload_bytes = reader.pop(payload_size).to_vec();
-        // There is a 4 byte overflow page - but it feels like only should be loaded strictly when the content is not fitting on the page.
+/// Reads a cell payload of `payload_size` bytes starting at the reader position, following the
+/// overflow page chain when the payload does not fit on the page.
+///
+/// See "Cell Payload Overflow Pages" in https://www.sqlite.org/fileformat.html.
+fn read_payload(
+    reader: &mut Reader<'_, u8>,
+    payload_size: usize,
+    kind: PayloadKind,
+    pager: &Pager,
+) -> Result<Vec<u8>, Error> {
+    let usable_size = pager.header.usable_size();
+    let local_size = local_payload_size(payload_size, kind, usable_size);
+    if payload_size == local_size {
+        return Ok(reader.pop(payload_size)?.to_vec());
+    }
 
-        Self {
-            rowid,
-            payload: CellPayload::new(payload_bytes),
+    let mut payload = Vec::with_capacity(payload_size);
+    payload.extend_from_slice(reader.pop(local_size)?);
+    let mut overflow_page = reader.pop_i32()? as u32 as usize;
+
+    while payload.len() < payload_size {
+        if overflow_page == 0 {
+            return Err(Error::corrupt(
+                "overflow chain ended before the payload was complete",
+            ));
         }
+
+        let page = pager.page(overflow_page)?;
+        let mut page_reader = Reader::new(&page[..]);
+        let next_page = page_reader
+            .pop_i32()
+            .map_err(|err| err.on_page(overflow_page))?;
+        let chunk_len = (payload_size - payload.len()).min(usable_size - 4);
+        payload.extend_from_slice(
+            page_reader
+                .pop(chunk_len)
+                .map_err(|err| err.on_page(overflow_page))?,
+        );
+
+        overflow_page = next_page as u32 as usize;
     }
+
+    Ok(payload)
+}
+
+/// How many bytes of a payload are stored on the b-tree page, the rest going to overflow pages.
+pub(crate) fn local_payload_size(
+    payload_size: usize,
+    kind: PayloadKind,
+    usable_size: usize,
+) -> usize {
+    let max_local = match kind {
+        PayloadKind::TableLeaf => usable_size - 35,
+        PayloadKind::Index => ((usable_size - 12) * 64 / 255) - 23,
+    };
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let k = min_local + ((payload_size - min_local) % (usable_size - 4));
+    if k <= max_local { k } else { min_local }
 }
 
 #[derive(Debug)]
-pub(crate) struct TableBTreeInteriorCell {
-    pub(crate) left_child_pointer: usize,
-    pub(crate) rowid: i64,
+pub(crate) struct TableBTreeLeafCell {
+    pub(crate) payload: CellPayload,
 }
 
-impl TableBTreeInteriorCell {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Self {
+impl TableBTreeLeafCell {
+    pub(crate) fn from(reader: &Reader<'_, u8>, pager: &Pager) -> Result<Self, Error> {
         let mut reader = reader.clone();
+        let payload_size = reader.pop_varint()? as usize;
+        // The rowid, which the cursor reads from the cell on its own.
+        reader.pop_varint()?;
+        let payload_bytes = read_payload(&mut reader, payload_size, PayloadKind::TableLeaf, pager)?;
 
-        let left_child_pointer = reader.pop_i32() as usize;
-        let rowid = reader.pop_varint();
-
-        Self {
-            left_child_pointer,
-            rowid,
-        }
+        Ok(Self {
+            payload: CellPayload::new(payload_bytes),
+        })
     }
 }
 
@@ -159,35 +226,67 @@ pub(crate) struct IndexBTreeLeafCell {
 }
 
 impl IndexBTreeLeafCell {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Self {
+    pub(crate) fn from(reader: &Reader<'_, u8>, pager: &Pager) -> Result<Self, Error> {
         let mut reader = reader.clone();
-        let payload_size = reader.pop_varint() as usize;
-        let payload_bytes = reader.pop(payload_size).to_vec();
-        // There is a 4 byte overflow page - but it feels like only should be loaded strictly when the content is not fitting on the page.
+        let payload_size = reader.pop_varint()? as usize;
+        let payload_bytes = read_payload(&mut reader, payload_size, PayloadKind::Index, pager)?;
 
-        Self {
+        Ok(Self {
             payload: CellPayload::new(payload_bytes),
-        }
+        })
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct IndexBTreeInteriorCell {
-    pub(crate) left_child_pointer: usize,
     pub(crate) payload: CellPayload,
 }
 
 impl IndexBTreeInteriorCell {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Self {
+    pub(crate) fn from(reader: &Reader<'_, u8>, pager: &Pager) -> Result<Self, Error> {
         let mut reader = reader.clone();
-        let left_child_pointer = reader.pop_i32() as usize;
-        let payload_size = reader.pop_varint() as usize;
-        let payload_bytes = reader.pop(payload_size).to_vec();
-        // There is a 4 byte overflow page - but it feels like only should be loaded strictly when the content is not fitting on the page.
+        // The left child pointer, which the page header reads for every cell at once.
+        reader.pop_i32()?;
+        let payload_size = reader.pop_varint()? as usize;
+        let payload_bytes = read_payload(&mut reader, payload_size, PayloadKind::Index, pager)?;
 
-        Self {
-            left_child_pointer,
+        Ok(Self {
             payload: CellPayload::new(payload_bytes),
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{cell::TableBTreeLeafCell, pager::Pager, reader::Reader};
+
+    #[test]
+    fn test_table_leaf_cell_with_overflow_pages() {
+        let page_size = 512;
+        let payload = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut file = vec![0u8; page_size * 3];
+        file[16..18].copy_from_slice(&(page_size as u16).to_be_bytes());
+
+        // Usable size 512: 39 bytes stay local, 508 go to page 2 and the remaining 453 to page 3.
+        let cell_offset = 200;
+        file[cell_offset..cell_offset + 3].copy_from_slice(&[0x87, 0x68, 0x01]);
+        file[cell_offset + 3..cell_offset + 42].copy_from_slice(&payload[..39]);
+        file[cell_offset + 42..cell_offset + 46].copy_from_slice(&2i32.to_be_bytes());
+        file[512..516].copy_from_slice(&3i32.to_be_bytes());
+        file[516..1024].copy_from_slice(&payload[39..547]);
+        file[1024..1028].copy_from_slice(&0i32.to_be_bytes());
+        file[1028..1481].copy_from_slice(&payload[547..]);
+
+        let path = std::env::temp_dir().join(format!("cell-test-{}.db", std::process::id()));
+        fs::write(&path, &file).unwrap();
+        let pager = Pager::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let page = pager.page(1).unwrap();
+        let cell = TableBTreeLeafCell::from(&Reader::new(&page[cell_offset..]), &pager).unwrap();
+
+        assert_eq!(payload, cell.payload.bytes);
     }
 }
@@ -1,8 +1,82 @@
-use crate::schema::{IndexSchema, TableSchema};
+use crate::schema::{IndexSchema, TableSchema, ViewSchema};
 
-pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
+/// Everything that can go wrong opening a database or running a statement, worded the way
+/// sqlite3 reports it.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The file doesn't hold what the format says it should, found on `page` when known.
+    #[error("database disk image is malformed ({})", corrupt_details(*.page, .reason))]
+    Corrupt { page: Option<usize>, reason: String },
+    #[error("{msg} at line {line}, column {column}")]
+    Parse {
+        line: usize,
+        column: usize,
+        msg: String,
+    },
+    #[error("no such table: {0}")]
+    NoSuchTable(String),
+    #[error("no such column: {0}")]
+    NoSuchColumn(String),
+    #[error("{0} is not supported")]
+    Unsupported(String),
+    #[error("column index out of range: {0}")]
+    ColumnIndex(usize),
+    /// A value read from a row as a type it doesn't convert to.
+    #[error("invalid type for column {column}: {found}")]
+    ColumnType { column: String, found: &'static str },
+    #[error("attempt to write a readonly database")]
+    ReadOnly,
+    /// A lock on the database file another connection kept holding.
+    #[error("database is locked")]
+    Busy,
+    #[error("disk I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Any other error, by its sqlite3 message.
+    #[error("{0}")]
+    Sql(String),
+}
+
+impl Error {
+    /// A corruption found while decoding, before knowing which page it is on.
+    pub(crate) fn corrupt(reason: impl Into<String>) -> Self {
+        Self::Corrupt {
+            page: None,
+            reason: reason.into(),
+        }
+    }
+
+    /// Records the page a corruption was found on, unless already known.
+    pub(crate) fn on_page(self, number: usize) -> Self {
+        match self {
+            Self::Corrupt { page: None, reason } => Self::Corrupt {
+                page: Some(number),
+                reason,
+            },
+            other => other,
+        }
+    }
+}
+
+fn corrupt_details(page: Option<usize>, reason: &str) -> String {
+    match page {
+        Some(page) => format!("page {}: {}", page, reason),
+        None => reason.to_string(),
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Self::Sql(msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Self {
+        Self::Sql(msg.to_string())
+    }
+}
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) enum BTreePageType {
     InteriorIndex,
     InteriorTable,
@@ -63,20 +137,66 @@ impl Index {
 pub(crate) enum Schema {
     Table(Table),
     Index(Index),
+    /// An index backing a UNIQUE or PRIMARY KEY constraint, its columns not known yet.
+    AutoIndex(Index),
+    View(View),
+    Trigger(Trigger),
 }
 
 #[derive(Debug)]
-pub(crate) struct Incrementer {
-    value: u64,
+pub(crate) struct View {
+    pub(crate) view_name: String,
+    pub(crate) sql_schema: ViewSchema,
 }
 
-impl Incrementer {
-    pub(crate) fn new(value: u64) -> Self {
-        Self { value }
+impl View {
+    pub(crate) fn new(view_name: String, sql_schema: ViewSchema) -> Self {
+        Self {
+            view_name,
+            sql_schema,
+        }
     }
+}
 
-    pub(crate) fn next_value(&mut self) -> u64 {
-        self.value += 1;
-        self.value - 1
+/// A trigger, kept only to know the schema has it: statements that would fire it aren't run.
+#[derive(Debug)]
+pub(crate) struct Trigger {
+    pub(crate) trigger_name: String,
+    pub(crate) table_name: String,
+    pub(crate) sql: String,
+}
+
+impl Trigger {
+    /// Whether the trigger fires on statements of a kind, such as `INSERT`, including
+    /// `UPDATE OF column, ...` for an update.
+    pub(crate) fn fires_on(&self, event: &str) -> bool {
+        let sql = self
+            .sql
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_uppercase();
+        sql.contains(&format!("{} ON ", event)) || sql.contains(&format!("{} OF ", event))
     }
 }
+
+/// Runs SQL on a database file through the `sqlite3` shell, to check our files against sqlite
+/// itself. Tests depending on it pass without checking when it isn't installed.
+#[cfg(test)]
+pub(crate) fn sqlite3(path: &std::path::Path, sql: &str) -> Option<String> {
+    let output = match std::process::Command::new("sqlite3")
+        .arg(path)
+        .arg(sql)
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => panic!("sqlite3 failed to run: {}", err),
+    };
+    assert!(
+        output.status.success(),
+        "sqlite3 failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(String::from_utf8(output.stdout).unwrap())
+}
@@ -0,0 +1,626 @@
+use std::{fmt, path::Path, rc::Rc, vec};
+
+use crate::{
+    ast::{self, Expr},
+    common::Error,
+    database::Database,
+    pager::{Pager, ReadLock},
+    parser::Parser,
+    query::Query,
+    query_executor::{QueryExecutor, QueryRows},
+    record::Record,
+    write_executor::WriteExecutor,
+};
+
+/// An open database file. A transaction still open when it is dropped is rolled back.
+pub struct Connection {
+    db: Database,
+}
+
+impl Connection {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let pager = Pager::open(path)?;
+        Ok(Self {
+            db: Database::from(pager)?,
+        })
+    }
+
+    /// Parses a single statement, optionally ending with `;`.
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>, Error> {
+        Ok(Statement::new(self, Parser::parse_statement(sql)?))
+    }
+
+    /// Parses a script of statements separated by `;`, to be run one after the other.
+    pub fn prepare_all(&self, sql: &str) -> Result<Vec<Statement<'_>>, Error> {
+        Ok(Parser::parse_statements(sql)?
+            .into_iter()
+            .map(|stmt| Statement::new(self, stmt))
+            .collect())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.db.pager.header.page_size
+    }
+
+    /// Number of tables in the schema, counting the internal `sqlite_` ones.
+    pub fn table_count(&self) -> usize {
+        self.db.schema().tables.len()
+    }
+
+    /// Names of the tables and views, sorted.
+    pub fn table_names(&self) -> Vec<String> {
+        self.db.table_names_sorted()
+    }
+}
+
+/// A parsed statement, which can be run any number of times with different values bound to
+/// its parameters.
+pub struct Statement<'conn> {
+    connection: &'conn Connection,
+    stmt: ast::Statement,
+    /// Names of the parameters by index, `None` for those only written `?`.
+    parameters: Vec<Option<String>>,
+}
+
+impl<'conn> Statement<'conn> {
+    fn new(connection: &'conn Connection, mut stmt: ast::Statement) -> Self {
+        let mut parameters = vec![];
+        stmt.walk_exprs_mut(&mut |expr| {
+            if let Expr::Parameter { index, name } = expr {
+                if parameters.len() < *index {
+                    parameters.resize(*index, None);
+                }
+                if name.is_some() {
+                    parameters[*index - 1] = name.clone();
+                }
+            }
+        });
+
+        Self {
+            connection,
+            stmt,
+            parameters,
+        }
+    }
+
+    /// Number of values the statement takes: the largest parameter index.
+    pub fn parameter_count(&self) -> usize {
+        self.parameters.len()
+    }
+
+    /// Index, from 1, of the parameter written `name`, prefix included as in `:id` or `?2`.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.parameters
+            .iter()
+            .position(|other| other.as_deref() == Some(name))
+            .map(|i| i + 1)
+    }
+
+    /// Runs the statement with a value for each parameter, in index order. Queries yield their
+    /// rows, every other statement yields none once it is done.
+    pub fn query(&self, params: &[Record]) -> Result<Rows<'conn>, Error> {
+        if params.len() != self.parameters.len() {
+            return Err(format!(
+                "{} values for {} parameters",
+                params.len(),
+                self.parameters.len()
+            )
+            .into());
+        }
+        self.run(params)
+    }
+
+    /// Runs the statement with values given to its parameters by name, which every parameter
+    /// must have.
+    pub fn query_named(&self, params: &[(&str, Record)]) -> Result<Rows<'conn>, Error> {
+        let mut values = vec![None; self.parameters.len()];
+        for (name, value) in params {
+            let index = self
+                .parameter_index(name)
+                .ok_or_else(|| format!("no such parameter: {}", name))?;
+            values[index - 1] = Some(value.clone());
+        }
+
+        let values = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                value.ok_or_else(|| {
+                    let name = self.parameters[i].clone();
+                    let name = name.unwrap_or_else(|| format!("?{}", i + 1));
+                    format!("no value bound to parameter {}", name).into()
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.run(&values)
+    }
+
+    fn run(&self, values: &[Record]) -> Result<Rows<'conn>, Error> {
+        // Values are bound as literals into a copy of the statement, letting the planner use
+        // them as it would constants written in the SQL.
+        let bound;
+        let stmt = if values.is_empty() {
+            &self.stmt
+        } else {
+            let mut stmt = self.stmt.clone();
+            stmt.walk_exprs_mut(&mut |expr| {
+                if let Expr::Parameter { index, .. } = expr {
+                    *expr = Expr::Literal(values[*index - 1].clone());
+                }
+            });
+            bound = stmt;
+            &bound
+        };
+
+        let db = &self.connection.db;
+        // Ending a transaction takes the locks it needs, anything else reads under a shared
+        // lock, held by the rows of a query until they are dropped.
+        let lock = match stmt {
+            ast::Statement::Begin | ast::Statement::Commit | ast::Statement::Rollback => None,
+            _ => Some(db.read()?),
+        };
+        let result = match stmt {
+            ast::Statement::Insert(insert) => WriteExecutor::execute_insert(insert, db),
+            ast::Statement::Update(update) => WriteExecutor::execute_update(update, db),
+            ast::Statement::Delete(delete) => WriteExecutor::execute_delete(delete, db),
+            ast::Statement::Begin => WriteExecutor::execute_begin(db),
+            ast::Statement::Commit => WriteExecutor::execute_commit(db),
+            ast::Statement::Rollback => WriteExecutor::execute_rollback(db),
+            stmt => {
+                let query = Query::from_statement(stmt.clone())?;
+                if query.explain {
+                    let lines = QueryExecutor::explain_query(&query, db)?;
+                    return Ok(Rows::read(vec![String::from("detail")], lines));
+                }
+                let rows = QueryExecutor::execute_query(&query, db)?;
+                return Ok(Rows {
+                    columns: rows.columns().into(),
+                    source: RowSource::Query(Box::new(rows)),
+                    _lock: lock,
+                });
+            }
+        };
+        result?;
+        Ok(Rows::read(vec![], vec![]))
+    }
+}
+
+/// The rows of a statement, in order. Those of a query are read from the database as they are
+/// pulled, so dropping the rows early skips reading the rest.
+pub struct Rows<'conn> {
+    columns: Rc<[String]>,
+    source: RowSource<'conn>,
+    /// Keeps other connections from committing until the rows are read, dropped after them.
+    _lock: Option<ReadLock<'conn>>,
+}
+
+enum RowSource<'conn> {
+    Query(Box<QueryRows<'conn>>),
+    /// Rows known in advance, such as the lines of a query plan.
+    Read(vec::IntoIter<Vec<Record>>),
+}
+
+impl Rows<'_> {
+    fn read(columns: Vec<String>, rows: Vec<Vec<Record>>) -> Self {
+        Self {
+            columns: columns.into(),
+            source: RowSource::Read(rows.into_iter()),
+            _lock: None,
+        }
+    }
+
+    /// Names of the result columns.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+impl fmt::Debug for Rows<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rows")
+            .field("columns", &self.columns)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = match &mut self.source {
+            RowSource::Query(rows) => rows.next()?,
+            RowSource::Read(rows) => Ok(rows.next()?),
+        };
+        Some(values.map(|values| Row {
+            columns: Rc::clone(&self.columns),
+            values,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Row {
+    columns: Rc<[String]>,
+    values: Vec<Record>,
+}
+
+impl Row {
+    /// The value of a column, by position or case-insensitive name, converted to `T`.
+    pub fn get<T: FromRecord>(&self, index: impl RowIndex) -> Result<T, Error> {
+        let i = index.position(&self.columns)?;
+        T::from_record(&self.values[i]).ok_or_else(|| Error::ColumnType {
+            column: self.columns[i].clone(),
+            found: self.values[i].type_name(),
+        })
+    }
+
+    pub fn values(&self) -> &[Record] {
+        &self.values
+    }
+}
+
+/// What a column of a row is picked by: its position or its name.
+pub trait RowIndex {
+    fn position(&self, columns: &[String]) -> Result<usize, Error>;
+}
+
+impl RowIndex for usize {
+    fn position(&self, columns: &[String]) -> Result<usize, Error> {
+        if *self >= columns.len() {
+            return Err(Error::ColumnIndex(*self));
+        }
+        Ok(*self)
+    }
+}
+
+impl RowIndex for &str {
+    fn position(&self, columns: &[String]) -> Result<usize, Error> {
+        columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(self))
+            .ok_or_else(|| Error::NoSuchColumn(self.to_string()))
+    }
+}
+
+/// A type a value read from a row converts to. Only integers convert to integer types and
+/// `bool`, while either integers or reals convert to `f64`.
+pub trait FromRecord: Sized {
+    fn from_record(record: &Record) -> Option<Self>;
+}
+
+impl FromRecord for Record {
+    fn from_record(record: &Record) -> Option<Self> {
+        Some(record.clone())
+    }
+}
+
+impl FromRecord for i64 {
+    fn from_record(record: &Record) -> Option<Self> {
+        record.as_int()
+    }
+}
+
+impl FromRecord for i32 {
+    fn from_record(record: &Record) -> Option<Self> {
+        i32::try_from(record.as_int()?).ok()
+    }
+}
+
+impl FromRecord for usize {
+    fn from_record(record: &Record) -> Option<Self> {
+        usize::try_from(record.as_int()?).ok()
+    }
+}
+
+impl FromRecord for bool {
+    fn from_record(record: &Record) -> Option<Self> {
+        Some(record.as_int()? != 0)
+    }
+}
+
+impl FromRecord for f64 {
+    fn from_record(record: &Record) -> Option<Self> {
+        record.as_real()
+    }
+}
+
+impl FromRecord for String {
+    fn from_record(record: &Record) -> Option<Self> {
+        record.as_str().map(str::to_string)
+    }
+}
+
+impl FromRecord for Vec<u8> {
+    fn from_record(record: &Record) -> Option<Self> {
+        record.as_blob().map(<[u8]>::to_vec)
+    }
+}
+
+/// NULL reads as `None`, any other value as a `T`.
+impl<T: FromRecord> FromRecord for Option<T> {
+    fn from_record(record: &Record) -> Option<Self> {
+        match record {
+            Record::Null => Some(None),
+            other => T::from_record(other).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        process::{Command, Stdio},
+    };
+
+    use crate::{
+        common::{Error, sqlite3},
+        connection::{Connection, Row, Rows},
+        record::Record,
+    };
+
+    #[test]
+    fn test_connection_query() {
+        let connection = Connection::open("sample.db").unwrap();
+        let stmt = connection
+            .prepare("SELECT id, name AS apple, color FROM apples WHERE id < 3 ORDER BY id")
+            .unwrap();
+        let rows = stmt.query(&[]).unwrap();
+        assert_eq!(["id", "apple", "color"], rows.columns());
+
+        let rows = rows.collect::<Result<Vec<Row>, _>>().unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(1, rows[0].get::<i64>(0).unwrap());
+        assert_eq!("Fuji", rows[1].get::<String>("Apple").unwrap());
+        assert_eq!(Some(2.0), rows[1].get::<Option<f64>>("id").unwrap());
+
+        let err = rows[0].get::<i64>("apple").unwrap_err();
+        assert!(matches!(err, Error::ColumnType { found: "text", .. }));
+        assert!(matches!(rows[0].get::<i64>(3), Err(Error::ColumnIndex(3))));
+        assert!(matches!(
+            rows[0].get::<i64>("size"),
+            Err(Error::NoSuchColumn(_))
+        ));
+
+        // A statement runs again from the start each time.
+        assert_eq!(2, stmt.query(&[]).unwrap().count());
+        assert!(matches!(
+            connection.prepare("SELECT * FROM nope").unwrap().query(&[]),
+            Err(Error::NoSuchTable(_))
+        ));
+
+        // Names are matched regardless of case.
+        let stmt = connection
+            .prepare("SELECT Name FROM APPLES WHERE ID = 2")
+            .unwrap();
+        let row = stmt.query(&[]).unwrap().next().unwrap().unwrap();
+        assert_eq!("Fuji", row.get::<String>("name").unwrap());
+
+        let out_of_range = |sql| {
+            let stmt = connection.prepare(sql).unwrap();
+            stmt.query(&[]).unwrap_err().to_string()
+        };
+        assert_eq!(
+            "2nd ORDER BY term out of range - should be between 1 and 2",
+            out_of_range("SELECT id, name FROM apples ORDER BY 1, 3")
+        );
+        assert_eq!(
+            "1st ORDER BY term out of range - should be between 1 and 1",
+            out_of_range("SELECT id FROM apples ORDER BY 0")
+        );
+    }
+
+    #[test]
+    fn test_connection_parameters() {
+        let connection = Connection::open("sample.db").unwrap();
+        let names = |rows: Rows| {
+            rows.map(|row| row.unwrap().get::<String>(0).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let stmt = connection
+            .prepare("SELECT name FROM apples WHERE id > ? AND id <= ?3 ORDER BY id")
+            .unwrap();
+        assert_eq!(3, stmt.parameter_count());
+        let rows = stmt
+            .query(&[Record::I64(1), Record::Null, Record::I64(3)])
+            .unwrap();
+        assert_eq!(vec!["Fuji", "Honeycrisp"], names(rows));
+        assert_eq!(
+            "1 values for 3 parameters",
+            stmt.query(&[Record::I64(1)]).unwrap_err().to_string()
+        );
+
+        // A bound value is never read as SQL.
+        let stmt = connection
+            .prepare("SELECT count(*) FROM apples WHERE name = :name OR :name = 'x'")
+            .unwrap();
+        assert_eq!(Some(1), stmt.parameter_index(":name"));
+        let injected = Record::String(String::from("' OR '1' = '1"));
+        let mut rows = stmt.query_named(&[(":name", injected)]).unwrap();
+        assert_eq!(0, rows.next().unwrap().unwrap().get::<i64>(0).unwrap());
+        let err = stmt.query_named(&[(":other", Record::Null)]).unwrap_err();
+        assert_eq!("no such parameter: :other", err.to_string());
+    }
+
+    #[test]
+    fn test_locks_shared_with_sqlite3() {
+        let path = std::env::temp_dir().join(format!("lock-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        if sqlite3(&path, "CREATE TABLE t(x); INSERT INTO t VALUES (1);").is_none() {
+            return;
+        }
+        let connection = Connection::open(&path).unwrap();
+        let run = |sql: &str| {
+            connection
+                .prepare(sql)?
+                .query(&[])?
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let count = || {
+            run("SELECT count(*) FROM t").unwrap()[0]
+                .get::<i64>(0)
+                .unwrap()
+        };
+        assert_eq!(1, count());
+
+        // What sqlite commits between our statements is read by the next one.
+        sqlite3(&path, "INSERT INTO t VALUES (2)").unwrap();
+        assert_eq!(2, count());
+
+        // Rows being read keep sqlite from committing until they are dropped.
+        let rows = connection
+            .prepare("SELECT x FROM t")
+            .unwrap()
+            .query(&[])
+            .unwrap();
+        let insert = || {
+            Command::new("sqlite3")
+                .arg(&path)
+                .arg("INSERT INTO t VALUES (3)")
+                .output()
+                .unwrap()
+        };
+        let blocked = insert();
+        assert!(!blocked.status.success());
+        assert!(String::from_utf8_lossy(&blocked.stderr).contains("database is locked"));
+        drop(rows);
+        assert!(insert().status.success());
+        assert_eq!(3, count());
+
+        // While sqlite writes a transaction, we can read but not write.
+        let mut writer = Command::new("sqlite3")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut input = writer.stdin.take().unwrap();
+        writeln!(
+            input,
+            "BEGIN IMMEDIATE; INSERT INTO t VALUES (4); SELECT 'reserved';"
+        )
+        .unwrap();
+        let mut line = String::new();
+        let mut output = BufReader::new(writer.stdout.take().unwrap());
+        output.read_line(&mut line).unwrap();
+        assert_eq!("reserved\n", line);
+        assert!(matches!(run("INSERT INTO t VALUES (5)"), Err(Error::Busy)));
+        assert_eq!(3, count());
+        writeln!(input, "COMMIT;").unwrap();
+        drop(input);
+        assert!(writer.wait().unwrap().success());
+        assert_eq!(4, count());
+
+        run("INSERT INTO t VALUES (5)").unwrap();
+        let check = "PRAGMA integrity_check; SELECT group_concat(x) FROM t;";
+        assert_eq!(Some(String::from("ok\n1,2,3,4,5\n")), sqlite3(&path, check));
+        drop(connection);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_schema_changed_by_sqlite3() {
+        let path = std::env::temp_dir().join(format!("schema-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        if sqlite3(&path, "CREATE TABLE t(x); INSERT INTO t VALUES (1);").is_none() {
+            return;
+        }
+        let connection = Connection::open(&path).unwrap();
+        let run = |sql: &str| {
+            connection
+                .prepare(sql)?
+                .query(&[])?
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let select = connection.prepare("SELECT x FROM t").unwrap();
+        assert_eq!(1, select.query(&[]).unwrap().count());
+
+        // The schema is read again once sqlite changed it, even for statements prepared before.
+        sqlite3(
+            &path,
+            "CREATE TABLE u(y); INSERT INTO u VALUES ('new'); INSERT INTO t VALUES (2);",
+        )
+        .unwrap();
+        let rows = run("SELECT y FROM u").unwrap();
+        assert_eq!("new", rows[0].get::<String>(0).unwrap());
+        assert_eq!(2, select.query(&[]).unwrap().count());
+        assert_eq!(vec!["t", "u"], connection.table_names());
+
+        sqlite3(&path, "DROP TABLE t").unwrap();
+        assert!(matches!(select.query(&[]), Err(Error::NoSuchTable(_))));
+        assert_eq!(1, run("SELECT y FROM u").unwrap().len());
+        drop(connection);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reads_what_sqlite3_commits_to_the_wal() {
+        let path = std::env::temp_dir().join(format!("wal-read-test-{}.db", std::process::id()));
+        let wal_path = path.with_extension("db-wal");
+        let _ = fs::remove_file(&path);
+        if sqlite3(&path, "PRAGMA journal_mode = WAL; CREATE TABLE t(x);").is_none() {
+            return;
+        }
+        // sqlite keeps the log until its last connection to the database closes.
+        let mut shell = Command::new("sqlite3")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut input = shell.stdin.take().unwrap();
+        let mut output = BufReader::new(shell.stdout.take().unwrap());
+        let mut run = |sql: &str| {
+            writeln!(input, "{}", sql).unwrap();
+            let mut line = String::new();
+            output.read_line(&mut line).unwrap();
+            line
+        };
+        let mut commit = |sql: &str| {
+            let sql = format!("{} SELECT 'committed';", sql);
+            assert_eq!("committed\n", run(&sql));
+        };
+        commit("INSERT INTO t VALUES (1);");
+        assert!(wal_path.exists());
+
+        let connection = Connection::open(&path).unwrap();
+        let sum = |sql: &str| {
+            let rows = connection.prepare(sql)?.query(&[])?;
+            rows.map(|row| row?.get::<i64>(0)).sum::<Result<i64, _>>()
+        };
+        assert_eq!(1, sum("SELECT x FROM t").unwrap());
+
+        // Each read sees what was committed to the log since the last one, schema included.
+        commit("INSERT INTO t SELECT value FROM generate_series(2, 1000);");
+        assert_eq!(500500, sum("SELECT x FROM t").unwrap());
+        commit("CREATE TABLE u(y); INSERT INTO u VALUES (7);");
+        assert_eq!(7, sum("SELECT y FROM u").unwrap());
+
+        // A read holds on to its snapshot while sqlite commits more.
+        let select = connection.prepare("SELECT x FROM t").unwrap();
+        let mut rows = select.query(&[]).unwrap();
+        commit("DELETE FROM t WHERE x > 1;");
+        // The read lock keeps sqlite from starting the log over, the checkpoint coming back busy.
+        assert!(run("PRAGMA wal_checkpoint(RESTART);").starts_with("1|"));
+        let mut before = 0;
+        for row in rows.by_ref() {
+            before += row.unwrap().get::<i64>(0).unwrap();
+        }
+        assert_eq!(500500, before);
+        drop(rows);
+        assert_eq!(1, sum("SELECT x FROM t").unwrap());
+
+        // Closing checkpoints the log into the database file and deletes it.
+        drop(input);
+        assert!(shell.wait().unwrap().success());
+        assert!(!wal_path.exists());
+        assert_eq!(1, sum("SELECT x FROM t").unwrap());
+        assert_eq!(7, sum("SELECT y FROM u").unwrap());
+        drop(select);
+        drop(connection);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(path.with_extension("db-shm"));
+    }
+}
@@ -1,54 +1,176 @@
 use crate::{
-    btree_page_header::BTreePageHeader,
-    cell::TableBTreeLeafCell,
-    common::{BTreePageType, Error, Index, Schema, Table},
-    database_header::DatabaseHeader,
-    reader::Reader,
+    btree_cursor::BTreeCursor,
+    cell::CellPayload,
+    common::{Error, Index, Schema, Table, Trigger, View},
+    pager::{Pager, ReadLock},
+    planner::PlannerCache,
+    schema::IndexSchema,
 };
-use std::collections::HashMap;
+use std::{cell::OnceCell, collections::HashMap};
 
-#[derive(Debug)]
+/// An open database and its schema.
 pub(crate) struct Database {
-    pub(crate) header: DatabaseHeader,
+    pub(crate) pager: Pager,
+    /// The schema read when opening the database, followed by those read since.
+    schema: SchemaVersion,
+    /// What the planner read from the file to estimate costs, until the next commit.
+    pub(crate) planner_cache: PlannerCache,
+}
+
+/// The schema objects as of one schema cookie. Names are matched regardless of ASCII case, as
+/// in SQL, so the objects are keyed by their names in lowercase.
+pub(crate) struct SchemaVersion {
     pub(crate) tables: HashMap<String, Table>,
     pub(crate) indices: HashMap<String, Index>,
+    pub(crate) views: HashMap<String, View>,
+    pub(crate) triggers: HashMap<String, Trigger>,
+    cookie: u32,
+    /// The schema read once another connection changed this one. Earlier versions are kept for
+    /// as long as the database, as they are borrowed by whatever was planned with them.
+    next: OnceCell<Box<SchemaVersion>>,
 }
 
 impl Database {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Result<Self, Error> {
-        let file_header = DatabaseHeader::from(&reader);
-        let first_header = BTreePageHeader::from(&reader.at(100));
-        assert_eq!(BTreePageType::LeafTable, first_header.kind);
+    pub(crate) fn from(pager: Pager) -> Result<Self, Error> {
+        let schema = {
+            let _lock = pager.read()?;
+            SchemaVersion::read(&pager)?
+        };
+        Ok(Self {
+            pager,
+            schema,
+            planner_cache: PlannerCache::default(),
+        })
+    }
+
+    /// Takes the shared lock for a statement, reading the schema again when another connection
+    /// changed it since it was last read.
+    pub(crate) fn read(&self) -> Result<ReadLock<'_>, Error> {
+        let lock = self.pager.read()?;
+        let schema = self.schema();
+        if schema.cookie != self.pager.schema_cookie() {
+            // The last version has no next one yet, so this always sets it.
+            let _ = schema.next.set(Box::new(SchemaVersion::read(&self.pager)?));
+        }
+        Ok(lock)
+    }
+
+    /// The schema as last read.
+    pub(crate) fn schema(&self) -> &SchemaVersion {
+        let mut schema = &self.schema;
+        while let Some(next) = schema.next.get() {
+            schema = next;
+        }
+        schema
+    }
+
+    pub(crate) fn table(&self, name: &str) -> Option<&Table> {
+        self.schema().tables.get(&name.to_ascii_lowercase())
+    }
+
+    pub(crate) fn view(&self, name: &str) -> Option<&View> {
+        self.schema().views.get(&name.to_ascii_lowercase())
+    }
+
+    /// Names of the tables and views, as listed by `.tables`.
+    pub(crate) fn table_names_sorted(&self) -> Vec<String> {
+        let schema = self.schema();
+        let mut names = schema
+            .tables
+            .values()
+            .map(|table| table.table_name.clone())
+            .chain(schema.views.values().map(|view| view.view_name.clone()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The indexes on a table, ordered by name.
+    pub(crate) fn table_indices(&self, table_name: &str) -> Vec<&Index> {
+        let mut indices = self
+            .schema()
+            .indices
+            .values()
+            .filter(|index| index.table_name.eq_ignore_ascii_case(table_name))
+            .collect::<Vec<_>>();
+        indices.sort_by(|a, b| a.index_name.cmp(&b.index_name));
+        indices
+    }
+}
 
+impl SchemaVersion {
+    /// Reads the schema objects from the `sqlite_schema` table, under the caller's lock.
+    fn read(pager: &Pager) -> Result<Self, Error> {
         let mut tables = HashMap::new();
         let mut indices = HashMap::new();
+        let mut views = HashMap::new();
+        let mut triggers = HashMap::new();
+        let mut auto_indices = vec![];
 
-        for cell_offset in first_header.cell_offsets {
-            let cell = TableBTreeLeafCell::from(&reader.at(cell_offset));
-            match cell.payload.read_as_schema_definition() {
+        for payload in Self::schema_rows(pager)? {
+            match payload.read_as_schema_definition()? {
                 Schema::Table(table) => {
-                    tables.insert(table.table_name.clone(), table);
+                    tables.insert(table.table_name.to_ascii_lowercase(), table);
                 }
                 Schema::Index(index) => {
-                    indices.insert(index.table_name.clone(), index);
+                    indices.insert(index.index_name.to_ascii_lowercase(), index);
+                }
+                Schema::AutoIndex(index) => auto_indices.push(index),
+                Schema::View(view) => {
+                    views.insert(view.view_name.to_ascii_lowercase(), view);
+                }
+                Schema::Trigger(trigger) => {
+                    triggers.insert(trigger.trigger_name.to_ascii_lowercase(), trigger);
                 }
             }
         }
 
+        // `sqlite_autoindex_<table>_<n>` backs the nth UNIQUE or PRIMARY KEY constraint.
+        for mut index in auto_indices {
+            let columns =
+                tables
+                    .get(&index.table_name.to_ascii_lowercase())
+                    .and_then(|table: &Table| {
+                        let prefix = format!("sqlite_autoindex_{}_", index.table_name);
+                        let n = index
+                            .index_name
+                            .strip_prefix(&prefix)?
+                            .parse::<usize>()
+                            .ok()?;
+                        table.sql_schema.unique_constraints.get(n.checked_sub(1)?)
+                    });
+            let Some(columns) = columns else {
+                return Err(format!("malformed database schema ({})", index.index_name).into());
+            };
+            index.sql_schema = IndexSchema::automatic(index.index_name.clone(), columns);
+            indices.insert(index.index_name.to_ascii_lowercase(), index);
+        }
+        for index in indices.values_mut() {
+            if let Some(table) = tables.get(&index.table_name.to_ascii_lowercase()) {
+                index.sql_schema.inherit_collations(&table.sql_schema);
+            }
+        }
+
         Ok(Self {
-            header: file_header,
             tables,
             indices,
+            views,
+            triggers,
+            cookie: pager.schema_cookie(),
+            next: OnceCell::new(),
         })
     }
 
-    pub(crate) fn table_names_sorted(&self) -> Vec<String> {
-        let mut names = self
-            .tables
-            .keys()
-            .map(|table_name| table_name.clone())
-            .collect::<Vec<_>>();
-        names.sort();
-        names
+    /// Reads the rows of the `sqlite_schema` table, whose b-tree is rooted at page 1 and
+    /// continues on interior and leaf pages elsewhere in the file once it outgrows it.
+    fn schema_rows(pager: &Pager) -> Result<Vec<CellPayload>, Error> {
+        let mut cursor = BTreeCursor::new(pager, 1);
+        let mut rows = vec![];
+        let mut valid = cursor.first()?;
+        while valid {
+            rows.push(cursor.payload()?);
+            valid = cursor.next()?;
+        }
+        Ok(rows)
     }
 }
@@ -1,31 +1,67 @@
-use crate::reader::Reader;
+use crate::{common::Error, reader::Reader};
 
 #[derive(Debug)]
 pub(crate) struct DatabaseHeader {
     pub(crate) page_size: usize,
-    schema_format: i32,
+    pub(crate) reserved_space: usize,
+    /// The file format read version: 1 for rollback journal mode, 2 for WAL mode.
+    pub(crate) read_version: u8,
+    pub(crate) change_counter: u32,
+    /// The size of the database in pages, only valid if `version_valid_for` matches the change
+    /// counter: older versions of sqlite left it alone when writing.
+    pub(crate) database_size: u32,
+    pub(crate) version_valid_for: u32,
+    /// Bumped by every change to the schema, telling whether the one read before still holds.
+    pub(crate) schema_cookie: u32,
 }
 
 impl DatabaseHeader {
-    pub(crate) fn from(reader: &Reader<'_, u8>) -> Self {
+    pub(crate) fn from(reader: &Reader<'_, u8>) -> Result<Self, Error> {
         // The database page size in bytes. Must be a power of two between 512 and 32768 inclusive, or the value 1 representing a page size of 65536.f[..2]).unwrap();
-        let page_size = reader.at(16).peek_u16();
-        let page_size: usize = if page_size == 1 {
-            0x10_000
-        } else {
-            assert!(page_size >= 512);
-            assert!(page_size <= 32768);
-            page_size as usize
+        let page_size = reader.at(16)?.peek_u16()?;
+        let page_size: usize = match page_size {
+            1 => 0x10_000,
+            512..=32768 if page_size.is_power_of_two() => page_size as usize,
+            _ => return Err("file is not a database".into()),
         };
         // debug!("Page size: {}", page_size);
 
-        // The schema format number. Supported schema formats are 1, 2, 3, and 4.
-        let schema_format = reader.at(44).peek_i32();
-        // debug!("Scheme format: {}", schema_format);
+        // File format write and read versions, 2 when the database is in WAL mode.
+        let read_version = reader.at(19)?.peek_u8()?;
 
-        Self {
+        // Bytes of unused "reserved" space at the end of each page. Usually 0.
+        let reserved_space = reader.at(20)?.peek_u8()? as usize;
+
+        // File change counter, bumped by every transaction that modifies the database.
+        let change_counter = reader.at(24)?.peek_i32()? as u32;
+
+        // Size of the database file in pages, the "in-header database size".
+        let database_size = reader.at(28)?.peek_i32()? as u32;
+
+        // The schema cookie, bumped whenever the schema changes.
+        let schema_cookie = reader.at(40)?.peek_i32()? as u32;
+
+        // The value of the change counter when the in-header database size was last written.
+        let version_valid_for = reader.at(92)?.peek_i32()? as u32;
+
+        Ok(Self {
             page_size,
-            schema_format,
-        }
+            reserved_space,
+            read_version,
+            change_counter,
+            database_size,
+            version_valid_for,
+            schema_cookie,
+        })
+    }
+
+    /// Whether committed pages may be in the `-wal` file rather than in the database file.
+    pub(crate) fn is_wal(&self) -> bool {
+        self.read_version == 2
+    }
+
+    /// The usable size of a page: the page size minus the reserved space at the end of each page.
+    pub(crate) fn usable_size(&self) -> usize {
+        self.page_size - self.reserved_space
     }
 }
@@ -0,0 +1,617 @@
+use std::cmp::Ordering;
+
+use crate::{
+    ast::{BinaryOp, Expr, LikeOp, SelectStmt, UnaryOp},
+    common::Error,
+    record::Record,
+    schema::{Affinity, Collation},
+};
+
+/// Source of column values for the row an expression is evaluated against.
+pub(crate) trait RowContext {
+    fn column_value(&self, table: Option<&str>, name: &str) -> Result<Record, Error>;
+
+    fn column_affinity(&self, table: Option<&str>, name: &str) -> Result<Option<Affinity>, Error>;
+
+    /// The collation a column was declared with, which comparisons with it use.
+    fn column_collation(&self, table: Option<&str>, name: &str) -> Result<Collation, Error>;
+
+    /// Precomputed value of a function call, such as an aggregate evaluated over a group.
+    fn function_value(&self, _expr: &Expr) -> Option<Record> {
+        None
+    }
+
+    /// Runs a subquery for this row, which its correlated column references read from. At most
+    /// `limit` rows are needed.
+    fn subquery_rows(
+        &self,
+        _select: &SelectStmt,
+        _limit: Option<usize>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        Err(Error::Unsupported(String::from("a subquery here")))
+    }
+
+    /// Affinity of the first result column of a subquery: that of the column it reads, if any.
+    fn subquery_affinity(&self, _select: &SelectStmt) -> Result<Option<Affinity>, Error> {
+        Ok(None)
+    }
+}
+
+/// Context for expressions that cannot reference columns, such as `LIMIT` and `OFFSET`.
+pub(crate) struct ConstantContext;
+
+impl RowContext for ConstantContext {
+    fn column_value(&self, _table: Option<&str>, name: &str) -> Result<Record, Error> {
+        Err(Error::NoSuchColumn(name.to_string()))
+    }
+
+    fn column_affinity(
+        &self,
+        _table: Option<&str>,
+        _name: &str,
+    ) -> Result<Option<Affinity>, Error> {
+        Ok(None)
+    }
+
+    fn column_collation(&self, _table: Option<&str>, name: &str) -> Result<Collation, Error> {
+        Err(Error::NoSuchColumn(name.to_string()))
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression following SQLite's semantics: NULL propagates through
+    /// operators, and boolean results are integers 1/0 or NULL when unknown.
+    pub(crate) fn eval(&self, row: &dyn RowContext) -> Result<Record, Error> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Parameter { index, name } => {
+                let name = name.clone().unwrap_or_else(|| format!("?{}", index));
+                return Err(format!("no value bound to parameter {}", name).into());
+            }
+            Expr::Column { table, name } => row.column_value(table.as_deref(), name)?,
+            Expr::Function { name, .. } => match row.function_value(self) {
+                Some(value) => value,
+                None => return Err(Error::Unsupported(format!("function {}()", name))),
+            },
+            Expr::Unary { op, expr } => {
+                let value = expr.eval(row)?;
+                match op {
+                    UnaryOp::Not => bool_record(value.truthiness().map(|v| !v)),
+                    UnaryOp::Negate => arithmetic(BinaryOp::Subtract, &Record::I64(0), &value),
+                }
+            }
+            Expr::Binary { op, lhs, rhs } => match op {
+                BinaryOp::And => {
+                    let lhs = lhs.eval(row)?.truthiness();
+                    if lhs == Some(false) {
+                        return Ok(bool_record(Some(false)));
+                    }
+                    match (lhs, rhs.eval(row)?.truthiness()) {
+                        (_, Some(false)) => bool_record(Some(false)),
+                        (Some(true), Some(true)) => bool_record(Some(true)),
+                        _ => Record::Null,
+                    }
+                }
+                BinaryOp::Or => {
+                    let lhs = lhs.eval(row)?.truthiness();
+                    if lhs == Some(true) {
+                        return Ok(bool_record(Some(true)));
+                    }
+                    match (lhs, rhs.eval(row)?.truthiness()) {
+                        (_, Some(true)) => bool_record(Some(true)),
+                        (Some(false), Some(false)) => bool_record(Some(false)),
+                        _ => Record::Null,
+                    }
+                }
+                BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq
+                | BinaryOp::Is
+                | BinaryOp::IsNot => {
+                    let (lhs_value, rhs_value) = comparison_operands(lhs, rhs, row)?;
+                    let collation = comparison_collation(lhs, rhs, row)?;
+                    let ordering = compare(&lhs_value, &rhs_value, &collation);
+                    let same = ordering == Some(Ordering::Equal)
+                        || lhs_value.is_null() && rhs_value.is_null();
+                    match op {
+                        BinaryOp::Is => bool_record(Some(same)),
+                        BinaryOp::IsNot => bool_record(Some(!same)),
+                        BinaryOp::Eq => bool_record(ordering.map(Ordering::is_eq)),
+                        BinaryOp::NotEq => bool_record(ordering.map(Ordering::is_ne)),
+                        BinaryOp::Lt => bool_record(ordering.map(Ordering::is_lt)),
+                        BinaryOp::LtEq => bool_record(ordering.map(Ordering::is_le)),
+                        BinaryOp::Gt => bool_record(ordering.map(Ordering::is_gt)),
+                        BinaryOp::GtEq => bool_record(ordering.map(Ordering::is_ge)),
+                        _ => unreachable!(),
+                    }
+                }
+                BinaryOp::Concat => {
+                    let (lhs, rhs) = (lhs.eval(row)?, rhs.eval(row)?);
+                    if lhs.is_null() || rhs.is_null() {
+                        Record::Null
+                    } else {
+                        Record::String(format!("{}{}", lhs, rhs))
+                    }
+                }
+                BinaryOp::Add
+                | BinaryOp::Subtract
+                | BinaryOp::Multiply
+                | BinaryOp::Divide
+                | BinaryOp::Modulo => arithmetic(*op, &lhs.eval(row)?, &rhs.eval(row)?),
+            },
+            Expr::IsNull { expr, negated } => {
+                bool_record(Some(expr.eval(row)?.is_null() != *negated))
+            }
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let low_collation = comparison_collation(expr, low, row)?;
+                let high_collation = comparison_collation(expr, high, row)?;
+                let (value, low) = comparison_operands(expr, low, row)?;
+                let (value_for_high, high) = comparison_operands(expr, high, row)?;
+                let above_low = compare(&value, &low, &low_collation).map(Ordering::is_ge);
+                let below_high =
+                    compare(&value_for_high, &high, &high_collation).map(Ordering::is_le);
+                let between = match (above_low, below_high) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                };
+                bool_record(between.map(|v| v != *negated))
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                // The values in the list have no affinity, as if written `+x`.
+                let items = list.iter().map(|item| Ok((item.eval(row)?, None)));
+                let value = (expr.eval(row)?, expr.affinity(row)?);
+                in_list(value, &expr.collation(row)?, items, *negated)?
+            }
+            Expr::InSelect {
+                expr,
+                select,
+                negated,
+            } => {
+                let affinity = row.subquery_affinity(select)?;
+                let items = row.subquery_rows(select, None)?.into_iter().map(|values| {
+                    let value = values.into_iter().next().unwrap_or(Record::Null);
+                    Ok((value, affinity))
+                });
+                let value = (expr.eval(row)?, expr.affinity(row)?);
+                in_list(value, &expr.collation(row)?, items, *negated)?
+            }
+            Expr::Subquery(select) => row
+                .subquery_rows(select, Some(1))?
+                .into_iter()
+                .next()
+                .and_then(|values| values.into_iter().next())
+                .unwrap_or(Record::Null),
+            Expr::Exists(select) => {
+                bool_record(Some(!row.subquery_rows(select, Some(1))?.is_empty()))
+            }
+            Expr::Like {
+                op,
+                expr,
+                pattern,
+                escape,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                let pattern = pattern.eval(row)?;
+                let escape = escape.as_ref().map(|escape| escape.eval(row)).transpose()?;
+                if value.is_null()
+                    || pattern.is_null()
+                    || escape.as_ref().is_some_and(Record::is_null)
+                {
+                    return Ok(Record::Null);
+                }
+
+                let value = value.to_string().chars().collect::<Vec<_>>();
+                let pattern = pattern.to_string().chars().collect::<Vec<_>>();
+                let is_match = match op {
+                    LikeOp::Like => {
+                        let escape = escape.and_then(|escape| escape.to_string().chars().next());
+                        like_match(&pattern, &value, escape)
+                    }
+                    LikeOp::Glob => glob_match(&pattern, &value),
+                };
+                bool_record(Some(is_match != *negated))
+            }
+            Expr::Collate { expr, .. } => expr.eval(row)?,
+        })
+    }
+
+    /// Affinity of the expression when used as a comparison operand: only column references
+    /// carry one, every other expression has none.
+    fn affinity(&self, row: &dyn RowContext) -> Result<Option<Affinity>, Error> {
+        match self {
+            Expr::Column { table, name } => row.column_affinity(table.as_deref(), name),
+            Expr::Collate { expr, .. } => expr.affinity(row),
+            _ => Ok(None),
+        }
+    }
+
+    /// The collation text is compared by when the expression is the only operand that has
+    /// one, BINARY when it has none.
+    pub(crate) fn collation(&self, row: &dyn RowContext) -> Result<Collation, Error> {
+        known(
+            self.operand_collation(row)?
+                .map_or(Collation::Binary, |(collation, _)| collation),
+        )
+    }
+
+    /// The collation the expression brings to a comparison: the one named by `COLLATE`, which
+    /// is explicit, or that of the column it reads.
+    fn operand_collation(&self, row: &dyn RowContext) -> Result<Option<(Collation, bool)>, Error> {
+        Ok(match self {
+            Expr::Collate { collation, .. } => Some((collation.clone(), true)),
+            Expr::Column { table, name } => {
+                Some((row.column_collation(table.as_deref(), name)?, false))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// The collation a comparison uses: an explicit one before that of a column, the left
+/// operand's before the right one's. See "Collating Sequences" in
+/// https://www.sqlite.org/datatype3.html.
+pub(crate) fn comparison_collation(
+    lhs: &Expr,
+    rhs: &Expr,
+    row: &dyn RowContext,
+) -> Result<Collation, Error> {
+    let collation = match (lhs.operand_collation(row)?, rhs.operand_collation(row)?) {
+        (Some((collation, true)), _) | (_, Some((collation, true))) => collation,
+        (Some((collation, _)), _) | (_, Some((collation, _))) => collation,
+        (None, None) => Collation::Binary,
+    };
+    known(collation)
+}
+
+/// Fails on a collation that can't be compared by.
+fn known(collation: Collation) -> Result<Collation, Error> {
+    match collation {
+        Collation::Unknown(name) => Err(format!("no such collation sequence: {}", name).into()),
+        collation => Ok(collation),
+    }
+}
+
+/// `value IN (items)`, each item compared with the value as by `=`, given their affinities and
+/// the collation of the value: true when an item equals the value, NULL when none does but
+/// some comparison was unknown.
+fn in_list(
+    (value, affinity): (Record, Option<Affinity>),
+    collation: &Collation,
+    items: impl Iterator<Item = Result<(Record, Option<Affinity>), Error>>,
+    negated: bool,
+) -> Result<Record, Error> {
+    let mut items = items.peekable();
+    if items.peek().is_none() {
+        return Ok(bool_record(Some(negated)));
+    }
+    if value.is_null() {
+        return Ok(Record::Null);
+    }
+
+    let mut found = Some(false);
+    for item in items {
+        let (lhs, rhs) = apply_affinities((value.clone(), affinity), item?);
+        match compare(&lhs, &rhs, collation) {
+            Some(Ordering::Equal) => {
+                found = Some(true);
+                break;
+            }
+            None => found = None,
+            Some(_) => {}
+        }
+    }
+    Ok(bool_record(found.map(|v| v != negated)))
+}
+
+fn bool_record(value: Option<bool>) -> Record {
+    match value {
+        Some(v) => Record::I64(v as i64),
+        None => Record::Null,
+    }
+}
+
+/// NULL-aware comparison: `None` when either side is NULL.
+fn compare(lhs: &Record, rhs: &Record, collation: &Collation) -> Option<Ordering> {
+    if lhs.is_null() || rhs.is_null() {
+        return None;
+    }
+    Some(collation.compare(lhs, rhs))
+}
+
+/// Evaluates both operands of a comparison and applies affinity conversions.
+/// See "Type Conversions Prior To Comparison" in https://www.sqlite.org/datatype3.html.
+fn comparison_operands(
+    lhs: &Expr,
+    rhs: &Expr,
+    row: &dyn RowContext,
+) -> Result<(Record, Record), Error> {
+    let lhs = (lhs.eval(row)?, lhs.affinity(row)?);
+    let rhs = (rhs.eval(row)?, rhs.affinity(row)?);
+    Ok(apply_affinities(lhs, rhs))
+}
+
+/// Converts the operands of a comparison according to their affinities.
+fn apply_affinities(
+    (mut lhs, lhs_affinity): (Record, Option<Affinity>),
+    (mut rhs, rhs_affinity): (Record, Option<Affinity>),
+) -> (Record, Record) {
+    let is_numeric = |affinity: Option<Affinity>| {
+        matches!(
+            affinity,
+            Some(Affinity::Integer | Affinity::Real | Affinity::Numeric)
+        )
+    };
+    let is_textual = |affinity: Option<Affinity>| matches!(affinity, Some(Affinity::Text));
+
+    if is_numeric(lhs_affinity) && !is_numeric(rhs_affinity) {
+        rhs = rhs.with_numeric_affinity();
+    } else if is_numeric(rhs_affinity) && !is_numeric(lhs_affinity) {
+        lhs = lhs.with_numeric_affinity();
+    } else if is_textual(lhs_affinity) && rhs_affinity.is_none() {
+        rhs = rhs.with_text_affinity();
+    } else if is_textual(rhs_affinity) && lhs_affinity.is_none() {
+        lhs = lhs.with_text_affinity();
+    }
+
+    (lhs, rhs)
+}
+
+fn arithmetic(op: BinaryOp, lhs: &Record, rhs: &Record) -> Record {
+    if lhs.is_null() || rhs.is_null() {
+        return Record::Null;
+    }
+
+    let (lhs, rhs) = (lhs.to_numeric(), rhs.to_numeric());
+    if let (Some(a), Some(b)) = (lhs.as_int(), rhs.as_int()) {
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Subtract => a.checked_sub(b),
+            BinaryOp::Multiply => a.checked_mul(b),
+            BinaryOp::Divide if b == 0 => return Record::Null,
+            BinaryOp::Divide => a.checked_div(b),
+            BinaryOp::Modulo if b == 0 => return Record::Null,
+            BinaryOp::Modulo => a.checked_rem(b),
+            other => unreachable!("Not an arithmetic operator: {:?}", other),
+        };
+        // Integer overflow falls back to floating point, as in SQLite.
+        if let Some(result) = result {
+            return Record::I64(result);
+        }
+    }
+
+    let a = lhs.as_real().unwrap_or(0.0);
+    let b = rhs.as_real().unwrap_or(0.0);
+    match op {
+        BinaryOp::Add => Record::Real(a + b),
+        BinaryOp::Subtract => Record::Real(a - b),
+        BinaryOp::Multiply => Record::Real(a * b),
+        BinaryOp::Divide if b == 0.0 => Record::Null,
+        BinaryOp::Divide => Record::Real(a / b),
+        BinaryOp::Modulo => {
+            let (a, b) = (a as i64, b as i64);
+            if b == 0 {
+                Record::Null
+            } else {
+                Record::Real((a % b) as f64)
+            }
+        }
+        other => unreachable!("Not an arithmetic operator: {:?}", other),
+    }
+}
+
+/// Case-insensitive (for ASCII) LIKE matching with `%` and `_` wildcards.
+fn like_match(pattern: &[char], value: &[char], escape: Option<char>) -> bool {
+    match pattern.first() {
+        None => value.is_empty(),
+        Some(&c) if Some(c) == escape => match (pattern.get(1), value.first()) {
+            (Some(p), Some(v)) if p.eq_ignore_ascii_case(v) => {
+                like_match(&pattern[2..], &value[1..], escape)
+            }
+            _ => false,
+        },
+        Some('%') => {
+            (0..=value.len()).any(|skip| like_match(&pattern[1..], &value[skip..], escape))
+        }
+        Some('_') => !value.is_empty() && like_match(&pattern[1..], &value[1..], escape),
+        Some(p) => match value.first() {
+            Some(v) if p.eq_ignore_ascii_case(v) => like_match(&pattern[1..], &value[1..], escape),
+            _ => false,
+        },
+    }
+}
+
+/// Case-sensitive GLOB matching with `*`, `?` and `[...]` character classes.
+fn glob_match(pattern: &[char], value: &[char]) -> bool {
+    match pattern.first() {
+        None => value.is_empty(),
+        Some('*') => (0..=value.len()).any(|skip| glob_match(&pattern[1..], &value[skip..])),
+        Some('?') => !value.is_empty() && glob_match(&pattern[1..], &value[1..]),
+        Some('[') => {
+            let Some(&v) = value.first() else {
+                return false;
+            };
+            let mut i = 1;
+            let negated = pattern.get(i) == Some(&'^');
+            if negated {
+                i += 1;
+            }
+
+            let mut matched = false;
+            let mut first = true;
+            while let Some(&c) = pattern.get(i) {
+                if c == ']' && !first {
+                    break;
+                }
+                first = false;
+                if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&c| c != ']')
+                {
+                    if c <= v && v <= pattern[i + 2] {
+                        matched = true;
+                    }
+                    i += 3;
+                } else {
+                    if c == v {
+                        matched = true;
+                    }
+                    i += 1;
+                }
+            }
+
+            if i >= pattern.len() {
+                // Unterminated character class never matches.
+                return false;
+            }
+            matched != negated && glob_match(&pattern[i + 1..], &value[1..])
+        }
+        Some(&p) => value.first() == Some(&p) && glob_match(&pattern[1..], &value[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ast::SelectStmt,
+        common::Error,
+        evaluator::RowContext,
+        parser::Parser,
+        record::Record,
+        schema::{Affinity, Collation},
+    };
+
+    struct TestRow;
+
+    impl RowContext for TestRow {
+        fn column_value(&self, _table: Option<&str>, name: &str) -> Result<Record, Error> {
+            Ok(match name {
+                "id" => Record::I64(7),
+                "price" => Record::Real(2.5),
+                "name" => Record::String(String::from("Apple Pie")),
+                "missing" => Record::Null,
+                other => return Err(Error::NoSuchColumn(other.to_string())),
+            })
+        }
+
+        fn column_affinity(
+            &self,
+            _table: Option<&str>,
+            name: &str,
+        ) -> Result<Option<Affinity>, Error> {
+            Ok(match name {
+                "id" => Some(Affinity::Integer),
+                "price" => Some(Affinity::Real),
+                _ => Some(Affinity::Text),
+            })
+        }
+
+        fn column_collation(&self, _table: Option<&str>, name: &str) -> Result<Collation, Error> {
+            Ok(match name {
+                "name" => Collation::NoCase,
+                _ => Collation::Binary,
+            })
+        }
+
+        /// Every subquery reads a single TEXT column holding '7'.
+        fn subquery_rows(
+            &self,
+            _select: &SelectStmt,
+            _limit: Option<usize>,
+        ) -> Result<Vec<Vec<Record>>, Error> {
+            Ok(vec![vec![Record::String(String::from("7"))]])
+        }
+
+        fn subquery_affinity(&self, _select: &SelectStmt) -> Result<Option<Affinity>, Error> {
+            Ok(Some(Affinity::Text))
+        }
+    }
+
+    fn eval(sql: &str) -> Record {
+        let stmt = Parser::parse_select(&format!("SELECT x FROM t WHERE {}", sql)).unwrap();
+        stmt.where_clause.unwrap().eval(&TestRow).unwrap()
+    }
+
+    #[test]
+    fn test_eval_comparisons_and_logic() {
+        assert_eq!(Record::I64(1), eval("id > 5 AND price <= 2.5"));
+        assert_eq!(Record::I64(1), eval("id = '7'"));
+        assert_eq!(Record::I64(1), eval("NOT (id <> 7) OR missing = 1"));
+        assert_eq!(
+            Record::I64(1),
+            eval("id BETWEEN 1 AND 10 AND id NOT IN (1, 2, 3)")
+        );
+        assert_eq!(Record::I64(1), eval("missing IS NULL AND name IS NOT NULL"));
+        assert_eq!(
+            Record::I64(1),
+            eval("id + 3 * 2 = 13 AND 7 / 2 = 3 AND 7 % 4 = 3")
+        );
+        assert_eq!(Record::I64(1), eval("name || '!' = 'Apple Pie!'"));
+    }
+
+    #[test]
+    fn test_eval_collations() {
+        assert_eq!(
+            Record::I64(1),
+            eval("name = 'APPLE PIE' AND 'apple pie' = name")
+        );
+        assert_eq!(Record::I64(0), eval("name = 'APPLE PIE' COLLATE BINARY"));
+        assert_eq!(Record::I64(1), eval("name IN ('x', 'apple PIE')"));
+        assert_eq!(Record::I64(1), eval("name BETWEEN 'APPLE' AND 'B'"));
+        assert_eq!(Record::I64(1), eval("'Apple Pie  ' = name COLLATE RTRIM"));
+        assert_eq!(Record::I64(0), eval("'Apple Pie  ' = name"));
+    }
+
+    #[test]
+    fn test_eval_null_logic() {
+        assert!(eval("missing = 1").is_null());
+        assert!(eval("missing = 1 AND id = 7").is_null());
+        assert_eq!(Record::I64(0), eval("missing = 1 AND id = 8"));
+        assert_eq!(Record::I64(1), eval("missing = 1 OR id = 7"));
+        assert!(eval("NOT (missing = 1)").is_null());
+        assert!(eval("id IN (1, NULL)").is_null());
+        assert!(eval("id / 0").is_null());
+    }
+
+    #[test]
+    fn test_eval_like_and_glob() {
+        assert_eq!(Record::I64(1), eval("name LIKE 'apple%'"));
+        assert_eq!(Record::I64(1), eval("name LIKE '_pple P_e'"));
+        assert_eq!(Record::I64(0), eval("name LIKE 'pie%'"));
+        assert_eq!(Record::I64(1), eval("'10%' LIKE '10!%' ESCAPE '!'"));
+        assert_eq!(Record::I64(1), eval("name GLOB 'A*[a-z]'"));
+        assert_eq!(Record::I64(0), eval("name GLOB 'apple*'"));
+        assert_eq!(Record::I64(1), eval("name NOT GLOB '*[0-9]*'"));
+    }
+
+    #[test]
+    fn test_eval_in() {
+        assert_eq!(Record::I64(1), eval("id IN ('7', 8)"));
+        // The values of a list have no affinity, unlike the column on the left.
+        assert_eq!(Record::I64(0), eval("'7' IN (id)"));
+        assert!(eval("id IN (1, missing)").is_null());
+        assert!(eval("missing IN (1, 2)").is_null());
+        // A subquery column compares with its own affinity.
+        assert_eq!(Record::I64(1), eval("id IN (SELECT s FROM u)"));
+        assert_eq!(Record::I64(1), eval("7 IN (SELECT s FROM u)"));
+        assert_eq!(Record::I64(0), eval("id NOT IN (SELECT s FROM u)"));
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let stmt = Parser::parse_select("SELECT x FROM t WHERE id = 7 AND nope > 1").unwrap();
+        let err = stmt.where_clause.unwrap().eval(&TestRow).unwrap_err();
+        assert_eq!("no such column: nope", err.to_string());
+    }
+}
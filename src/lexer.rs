@@ -0,0 +1,411 @@
+use crate::common::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Bare word: either a keyword or an unquoted identifier. Keywords are matched
+    /// case-insensitively by the parser.
+    Word(String),
+    /// Identifier in double quotes, backticks or square brackets.
+    QuotedIdentifier(String),
+    String(String),
+    Integer(i64),
+    /// 9223372036854775808, one past the largest integer: the smallest one once negated, a
+    /// real otherwise.
+    MinIntegerMagnitude,
+    Float(f64),
+    Blob(Vec<u8>),
+    /// Parameter placeholder, as written: `?`, `?NNN`, `:name`, `@name` or `$name`.
+    Variable(String),
+    Comma,
+    Dot,
+    Semicolon,
+    LeftParen,
+    RightParen,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+/// Whether `sql` ends with a complete statement: a `;` outside of any string, quoted identifier
+/// or comment. Input that fails to tokenize otherwise than by ending inside a quote or comment is
+/// complete once it ends with `;`, leaving the parser to report the error.
+pub fn is_complete(sql: &str) -> bool {
+    let mut lexer = Lexer::new(sql);
+    match lexer.tokenize() {
+        Ok(tokens) => tokens
+            .iter()
+            .rev()
+            .nth(1)
+            .is_some_and(|token| token.kind == TokenKind::Semicolon),
+        Err(_) => lexer.pos < lexer.chars.len() && sql.trim_end().ends_with(';'),
+    }
+}
+
+pub(crate) struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer {
+    pub(crate) fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub(crate) fn tokenize(&mut self) -> Result<Vec<Token>, Error> {
+        let mut tokens = vec![];
+
+        loop {
+            self.skip_whitespace_and_comments()?;
+
+            let (line, column) = (self.line, self.column);
+            let Some(c) = self.peek(0) else {
+                tokens.push(Token {
+                    kind: TokenKind::Eof,
+                    line,
+                    column,
+                });
+                return Ok(tokens);
+            };
+
+            let kind = match c {
+                '\'' => TokenKind::String(self.quoted('\'')?),
+                '"' => TokenKind::QuotedIdentifier(self.quoted('"')?),
+                '`' => TokenKind::QuotedIdentifier(self.quoted('`')?),
+                '[' => {
+                    self.advance();
+                    let mut name = String::new();
+                    loop {
+                        match self.advance() {
+                            Some(']') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(self.error_at(line, column, "unterminated [")),
+                        }
+                    }
+                    TokenKind::QuotedIdentifier(name)
+                }
+                'x' | 'X' if self.peek(1) == Some('\'') => {
+                    self.advance();
+                    let hex = self.quoted('\'')?;
+                    TokenKind::Blob(
+                        Self::decode_hex(&hex)
+                            .ok_or_else(|| self.error_at(line, column, "malformed blob literal"))?,
+                    )
+                }
+                c if c.is_ascii_digit()
+                    || (c == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit())) =>
+                {
+                    self.number(line, column)?
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut word = String::new();
+                    while let Some(c) = self.peek(0) {
+                        if c.is_alphanumeric() || c == '_' || c == '$' {
+                            word.push(c);
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                    TokenKind::Word(word)
+                }
+                '?' => {
+                    self.advance();
+                    let mut name = String::from("?");
+                    while let Some(c) = self.peek(0).filter(|c| c.is_ascii_digit()) {
+                        name.push(c);
+                        self.advance();
+                    }
+                    TokenKind::Variable(name)
+                }
+                ':' | '@' | '$'
+                    if self
+                        .peek(1)
+                        .is_some_and(|c| c.is_alphanumeric() || c == '_') =>
+                {
+                    let mut name = String::from(c);
+                    self.advance();
+                    while let Some(c) = self.peek(0) {
+                        if c.is_alphanumeric() || c == '_' || c == '$' {
+                            name.push(c);
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                    TokenKind::Variable(name)
+                }
+                _ => self.symbol(line, column)?,
+            };
+
+            tokens.push(Token { kind, line, column });
+        }
+    }
+
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error_at(&self, line: usize, column: usize, msg: &str) -> Error {
+        Error::Parse {
+            line,
+            column,
+            msg: msg.to_string(),
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Error> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.advance();
+                }
+                (Some('-'), Some('-')) => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.advance();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let (line, column) = (self.line, self.column);
+                    self.advance();
+                    self.advance();
+                    loop {
+                        match (self.peek(0), self.peek(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.advance();
+                                self.advance();
+                                break;
+                            }
+                            (Some(_), _) => {
+                                self.advance();
+                            }
+                            (None, _) => {
+                                return Err(self.error_at(line, column, "unterminated comment"));
+                            }
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Reads a quoted section where the quote character is escaped by doubling it.
+    fn quoted(&mut self, quote: char) -> Result<String, Error> {
+        let (line, column) = (self.line, self.column);
+        self.advance();
+
+        let mut out = String::new();
+        loop {
+            match self.advance() {
+                Some(c) if c == quote => {
+                    if self.peek(0) == Some(quote) {
+                        self.advance();
+                        out.push(quote);
+                    } else {
+                        return Ok(out);
+                    }
+                }
+                Some(c) => out.push(c),
+                None => {
+                    return Err(self.error_at(
+                        line,
+                        column,
+                        &format!("unterminated quoted section starting with {}", quote),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, line: usize, column: usize) -> Result<TokenKind, Error> {
+        if self.peek(0) == Some('0') && matches!(self.peek(1), Some('x' | 'X')) {
+            self.advance();
+            self.advance();
+            let mut digits = String::new();
+            while let Some(c) = self.peek(0).filter(|c| c.is_ascii_hexdigit()) {
+                digits.push(c);
+                self.advance();
+            }
+            return u64::from_str_radix(&digits, 16)
+                .map(|v| TokenKind::Integer(v as i64))
+                .map_err(|_| self.error_at(line, column, "malformed hex literal"));
+        }
+
+        let mut raw = String::new();
+        let mut is_float = false;
+        while let Some(c) = self.peek(0) {
+            if c.is_ascii_digit() {
+                raw.push(c);
+            } else if c == '.' && !is_float {
+                is_float = true;
+                raw.push(c);
+            } else if matches!(c, 'e' | 'E') {
+                is_float = true;
+                raw.push(c);
+                self.advance();
+                if let Some(sign) = self.peek(0).filter(|c| matches!(c, '+' | '-')) {
+                    raw.push(sign);
+                    self.advance();
+                }
+                continue;
+            } else {
+                break;
+            }
+            self.advance();
+        }
+
+        if !is_float && let Ok(v) = raw.parse::<i64>() {
+            return Ok(TokenKind::Integer(v));
+        }
+        if !is_float && raw.trim_start_matches('0') == "9223372036854775808" {
+            return Ok(TokenKind::MinIntegerMagnitude);
+        }
+
+        raw.parse::<f64>()
+            .map(TokenKind::Float)
+            .map_err(|_| self.error_at(line, column, &format!("malformed number: {}", raw)))
+    }
+
+    fn symbol(&mut self, line: usize, column: usize) -> Result<TokenKind, Error> {
+        let c = self.advance().expect("Symbol character");
+        let next = self.peek(0);
+
+        let (kind, consume_next) = match (c, next) {
+            (',', _) => (TokenKind::Comma, false),
+            ('.', _) => (TokenKind::Dot, false),
+            (';', _) => (TokenKind::Semicolon, false),
+            ('(', _) => (TokenKind::LeftParen, false),
+            (')', _) => (TokenKind::RightParen, false),
+            ('*', _) => (TokenKind::Star, false),
+            ('+', _) => (TokenKind::Plus, false),
+            ('-', _) => (TokenKind::Minus, false),
+            ('/', _) => (TokenKind::Slash, false),
+            ('%', _) => (TokenKind::Percent, false),
+            ('|', Some('|')) => (TokenKind::Concat, true),
+            ('=', Some('=')) => (TokenKind::Eq, true),
+            ('=', _) => (TokenKind::Eq, false),
+            ('!', Some('=')) => (TokenKind::NotEq, true),
+            ('<', Some('>')) => (TokenKind::NotEq, true),
+            ('<', Some('=')) => (TokenKind::LtEq, true),
+            ('<', _) => (TokenKind::Lt, false),
+            ('>', Some('=')) => (TokenKind::GtEq, true),
+            ('>', _) => (TokenKind::Gt, false),
+            (other, _) => {
+                return Err(self.error_at(line, column, &format!("unrecognized token: {}", other)));
+            }
+        };
+
+        if consume_next {
+            self.advance();
+        }
+
+        Ok(kind)
+    }
+
+    fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::Error,
+        lexer::{Lexer, TokenKind, is_complete},
+    };
+
+    fn kinds(sql: &str) -> Vec<TokenKind> {
+        Lexer::new(sql)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec![
+                TokenKind::Word(String::from("select")),
+                TokenKind::QuotedIdentifier(String::from("a b")),
+                TokenKind::Comma,
+                TokenKind::Integer(12),
+                TokenKind::Comma,
+                TokenKind::Float(1.5),
+                TokenKind::Word(String::from("FROM")),
+                TokenKind::Word(String::from("t")),
+                TokenKind::Word(String::from("where")),
+                TokenKind::Word(String::from("x")),
+                TokenKind::NotEq,
+                TokenKind::String(String::from("it's = 1")),
+                TokenKind::Eof,
+            ],
+            kinds("select \"a b\", 12, 1.5 FROM t -- comment\nwhere x <> 'it''s = 1'")
+        );
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("SELECT 1 FROM t;"));
+        assert!(is_complete("SELECT 1\nFROM t; -- done\n"));
+        assert!(!is_complete("SELECT 1 FROM t"));
+        assert!(!is_complete("SELECT ';"));
+        assert!(!is_complete("SELECT 1 /* ; */"));
+        assert!(is_complete("SELECT # FROM t;"));
+    }
+
+    #[test]
+    fn test_tokenize_error_position() {
+        let err = Lexer::new("SELECT a\nFROM t WHERE a = 'open")
+            .tokenize()
+            .unwrap_err();
+        let Error::Parse { line, column, .. } = err else {
+            panic!("unexpected error {:?}", err)
+        };
+        assert_eq!((2, 18), (line, column));
+    }
+}
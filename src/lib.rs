@@ -0,0 +1,43 @@
+//! Reads and writes SQLite database files.
+//!
+//! ```no_run
+//! use codecrafters_sqlite::Connection;
+//!
+//! let connection = Connection::open("sample.db")?;
+//! for row in connection.prepare("SELECT name FROM apples")?.query(&[])? {
+//!     println!("{}", row?.get::<String>("name")?);
+//! }
+//! # Ok::<(), codecrafters_sqlite::Error>(())
+//! ```
+
+mod aggregate;
+mod ast;
+mod btree_cursor;
+mod btree_page_header;
+mod btree_writer;
+mod cell;
+mod common;
+mod connection;
+mod database;
+mod database_header;
+mod evaluator;
+mod lexer;
+mod output;
+mod pager;
+mod parser;
+mod planner;
+mod query;
+mod query_executor;
+mod reader;
+mod record;
+mod schema;
+mod wal;
+mod write_executor;
+
+pub use crate::{
+    common::Error,
+    connection::{Connection, FromRecord, Row, RowIndex, Rows, Statement},
+    lexer::is_complete,
+    output::ListWriter,
+    record::Record,
+};
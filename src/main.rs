@@ -1,54 +1,96 @@
-use anyhow::Result;
-use clap::Parser;
+use std::{io, process::ExitCode};
+
+use clap::Parser as _;
+use codecrafters_sqlite::{Connection, Error, ListWriter, Record, Rows, Statement};
 use log::info;
-use std::{fs::File, io::Read};
 
-use crate::{database::Database, query::Query, query_executor::QueryExecutor, reader::Reader};
+use crate::shell::Shell;
 
-mod btree_page_header;
-mod cell;
-mod common;
-mod database;
-mod database_header;
-mod query;
-mod query_executor;
-mod reader;
-mod record;
-mod schema;
+mod shell;
 
 #[derive(clap::Parser)]
 #[command(version, about, long_about = None)]
 struct ProgramArgs {
     db_file_name: String,
-    command: String,
+    /// SQL statements, separated by `;`, or a dot-command to run. Without one, an interactive
+    /// shell is opened instead.
+    command: Option<String>,
+    /// A value for a parameter of the statements: bound by name when given as `:name=VALUE`
+    /// (or `@name=`, `$name=`), otherwise to the next `?` in order. VALUE is read as an SQL
+    /// literal such as `42` or `'text'`, or taken as text.
+    #[arg(long = "param", value_name = "[NAME=]VALUE", requires = "command")]
+    params: Vec<String>,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     // unsafe { std::env::set_var("RUST_LOG", "debug") };
     pretty_env_logger::init();
 
     info!("Peter SQLite Start");
 
     let args = ProgramArgs::parse();
-    let mut file = File::open(&args.db_file_name)?;
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer).unwrap();
-    let reader = Reader::new(&buffer[..]);
-    let db = Database::from(&reader).unwrap();
-
-    match args.command.as_str() {
-        ".dbinfo" => {
-            println!("database page size: {}", db.header.page_size);
-            println!("number of tables: {}", db.tables.len());
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
         }
-        ".tables" => {
-            println!("{}", db.table_names_sorted().join(" "));
+    }
+}
+
+fn run(args: &ProgramArgs) -> Result<(), Error> {
+    let connection = Connection::open(&args.db_file_name)?;
+    let mut shell = Shell::new(&connection, io::stdout());
+
+    match args.command.as_deref() {
+        None => shell.run_interactive()?,
+        Some(command) if command.starts_with('.') => {
+            shell.dot_command(command)?;
         }
-        other => {
-            let query = Query::parse(other);
-            QueryExecutor::execute_query(&query, &db, &reader);
+        Some(other) => {
+            let mut positional = vec![];
+            let mut named = vec![];
+            for param in &args.params {
+                match param.split_once('=') {
+                    Some((name, value)) if name.starts_with([':', '@', '$']) => {
+                        named.push((name, Record::parse(value)))
+                    }
+                    _ => positional.push(Record::parse(param)),
+                }
+            }
+
+            let mut positional = positional.into_iter();
+            let mut writer = ListWriter::new(io::stdout().lock());
+            for stmt in connection.prepare_all(other)? {
+                writer.write_rows(bind(&stmt, &mut positional, &named)?)?;
+            }
+            let unused = positional.as_slice();
+            if !unused.is_empty() {
+                let msg = format!("{} values left over for no parameters", unused.len());
+                return Err(Error::from(msg));
+            }
         }
     }
 
     Ok(())
 }
+
+/// Runs a statement of the command with its parameters bound by name when any `--param` was
+/// named, otherwise to the next positional values.
+fn bind<'conn>(
+    stmt: &Statement<'conn>,
+    positional: &mut impl Iterator<Item = Record>,
+    named: &[(&str, Record)],
+) -> Result<Rows<'conn>, Error> {
+    if named.is_empty() {
+        let values = positional.take(stmt.parameter_count()).collect::<Vec<_>>();
+        return stmt.query(&values);
+    }
+
+    let named = named
+        .iter()
+        .filter(|(name, _)| stmt.parameter_index(name).is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    stmt.query_named(&named)
+}
@@ -0,0 +1,58 @@
+use std::io::Write;
+
+use crate::{
+    common::Error,
+    connection::{Row, Rows},
+};
+
+/// Writes rows as the sqlite3 shell does in its default list mode: a line per row, its values
+/// separated by `|`, with NULL written `NULL`.
+pub struct ListWriter<W> {
+    out: W,
+}
+
+impl<W: Write> ListWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn write_row(&mut self, row: &Row) -> Result<(), Error> {
+        let values = row
+            .values()
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>();
+        writeln!(self.out, "{}", values.join("|"))?;
+        Ok(())
+    }
+
+    /// Writes each row as soon as it is read, stopping at the first error.
+    pub fn write_rows(&mut self, rows: Rows) -> Result<(), Error> {
+        for row in rows {
+            self.write_row(&row?)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{connection::Connection, output::ListWriter};
+
+    #[test]
+    fn test_list_writer() {
+        let connection = Connection::open("sample.db").unwrap();
+        let stmt = connection
+            .prepare("SELECT id, name, NULL FROM apples WHERE id <= 2 ORDER BY id")
+            .unwrap();
+        let mut writer = ListWriter::new(vec![]);
+        writer.write_rows(stmt.query(&[]).unwrap()).unwrap();
+
+        let text = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!("1|Granny Smith|NULL\n2|Fuji|NULL\n", text);
+    }
+}
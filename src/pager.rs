@@ -0,0 +1,890 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, hash_map::RandomState},
+    fs::{self, File, OpenOptions},
+    hash::BuildHasher,
+    io::{self, ErrorKind},
+    os::{fd::AsRawFd, unix::fs::FileExt},
+    path::{Path, PathBuf},
+    rc::Rc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{common::Error, database_header::DatabaseHeader, reader::Reader, wal::Wal};
+
+/// Pages kept in memory by default, about as many as sqlite's default cache of 2000 KiB holds
+/// at the default page size.
+const DEFAULT_CACHE_PAGES: usize = 2000;
+
+/// Size of the database header at the start of page 1.
+pub(crate) const DATABASE_HEADER_SIZE: usize = 100;
+
+/// Offsets in the database header of the first freelist trunk page and of the number of pages
+/// on the freelist.
+const FIRST_TRUNK_OFFSET: usize = 32;
+const FREE_PAGES_OFFSET: usize = 36;
+
+/// The bytes starting every header of a rollback journal.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+
+/// Size our journal headers are padded to. sqlite reads the size from each journal, as the
+/// sector size of the disk it was written to.
+const JOURNAL_SECTOR_SIZE: usize = 512;
+
+/// The page count of a journal header of sqlite's no-sync mode, where the records run to the
+/// end of the journal.
+const JOURNAL_RECORDS_TO_END: u32 = 0xffff_ffff;
+
+/// Offsets in the database file of the bytes sqlite locks, on the page at 1 GiB it never stores
+/// anything on: the pending byte, the reserved byte, then the range shared locks are taken on.
+const PENDING_BYTE: u64 = 0x4000_0000;
+const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+const SHARED_SIZE: u64 = 510;
+
+/// How long to keep trying to take a lock another connection holds, before giving up with
+/// `database is locked`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The bytes of a page, shared with the page cache.
+pub(crate) type Page = Rc<[u8]>;
+
+/// Reads the pages of a database file on demand, keeping the most recently used ones in a
+/// bounded cache instead of holding the whole file in memory.
+///
+/// Pages written by a transaction are kept aside until it commits, and read back from there in
+/// the meantime. A commit goes through sqlite's rollback journal, the `-journal` file next to
+/// the database holding the original content of the pages it overwrites, so that a commit cut
+/// short can be undone by the next one opening the database, whether sqlite or us.
+///
+/// Connections in other processes, sqlite's included, are kept in step through the locks sqlite
+/// takes on the database file: a shared lock while reading, the reserved lock while a
+/// transaction writes, and the exclusive lock while a commit writes to the file. The locks
+/// belong to the process, so two connections to the same file within one don't see each other.
+///
+/// A database in WAL mode is read through its write-ahead log, which holds the latest version
+/// of the pages committed since the last checkpoint, as of when the shared lock was taken.
+/// Writing to it isn't supported.
+pub(crate) struct Pager {
+    file: File,
+    journal_path: PathBuf,
+    wal: Option<RefCell<Wal>>,
+    pub(crate) header: DatabaseHeader,
+    /// Set when the file was opened without write access, to refuse to commit.
+    read_only: bool,
+    cache: RefCell<PageCache>,
+    dirty: RefCell<HashMap<usize, Page>>,
+    /// Number of pages in the database, including the ones the transaction added.
+    page_count: Cell<usize>,
+    /// Number of pages in the database as of the last commit.
+    committed_page_count: Cell<usize>,
+    /// Set between `BEGIN` and `COMMIT` or `ROLLBACK`, when statements don't commit on their own.
+    in_transaction: Cell<bool>,
+    /// Bumped by every commit and rollback, and by finding another connection committed,
+    /// telling whether what was worked out from the pages before is still up to date.
+    generation: Cell<u64>,
+    lock: Cell<Lock>,
+    /// Number of statements holding a `ReadLock`, which keep the shared lock until the last ends.
+    readers: Cell<usize>,
+    /// The change counter of the database header as of the last time it was locked, telling
+    /// whether another connection committed since.
+    change_counter: Cell<u32>,
+    /// The schema cookie of the database header as of the last time it was locked, bumped by
+    /// every change to the schema.
+    schema_cookie: Cell<u32>,
+}
+
+/// The locks held on the database file, each allowing what the one before does and more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Lock {
+    None,
+    /// Held while reading, keeping other connections from committing.
+    Shared,
+    /// Held by the one transaction writing to the database, while others keep reading.
+    Reserved,
+    /// Held while a commit writes to the file, once every other connection is done reading.
+    Exclusive,
+}
+
+/// The shared lock held for a statement, until it is dropped.
+pub(crate) struct ReadLock<'a> {
+    pager: &'a Pager,
+}
+
+impl Drop for ReadLock<'_> {
+    fn drop(&mut self) {
+        self.pager.readers.set(self.pager.readers.get() - 1);
+        self.pager.release();
+    }
+}
+
+/// The state of a transaction at some point, to undo the statement that followed it.
+pub(crate) struct Savepoint {
+    dirty: HashMap<usize, Page>,
+    page_count: usize,
+}
+
+impl Pager {
+    /// Opens a database file, for reading only when it can't be written to. A journal left by
+    /// a commit that didn't complete is played back first.
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::with_capacity(path, DEFAULT_CACHE_PAGES)
+    }
+
+    pub(crate) fn with_capacity(path: impl AsRef<Path>, capacity: usize) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let open_error = |err| format!("unable to open database file {}: {}", path.display(), err);
+        let (file, read_only) = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => (file, false),
+            Err(_) => (File::open(path).map_err(open_error)?, true),
+        };
+        let mut journal_path = path.as_os_str().to_owned();
+        journal_path.push("-journal");
+        let journal_path = PathBuf::from(journal_path);
+        Self::lock_file(&file, &journal_path, read_only)?;
+
+        let mut header_bytes = [0; DATABASE_HEADER_SIZE];
+        file.read_exact_at(&mut header_bytes, 0)?;
+        let header = DatabaseHeader::from(&Reader::new(&header_bytes[..]))?;
+        let wal = header
+            .is_wal()
+            .then(|| RefCell::new(Wal::new(path, header.page_size)));
+
+        let page_count = Self::database_size(&file, &header)?;
+        let change_counter = header.change_counter;
+        let schema_cookie = header.schema_cookie;
+        let pager = Self {
+            file,
+            journal_path,
+            wal,
+            header,
+            read_only,
+            cache: RefCell::new(PageCache::new(capacity.max(1))),
+            dirty: RefCell::new(HashMap::new()),
+            page_count: Cell::new(page_count),
+            committed_page_count: Cell::new(page_count),
+            in_transaction: Cell::new(false),
+            generation: Cell::new(0),
+            lock: Cell::new(Lock::Shared),
+            readers: Cell::new(0),
+            change_counter: Cell::new(change_counter),
+            schema_cookie: Cell::new(schema_cookie),
+        };
+        // The pages committed to the log are read as the first read begins.
+        let caught_up = pager.catch_up();
+        pager.release();
+        caught_up.map(|()| pager)
+    }
+
+    /// The size of the database in pages, as the header records it when written by a recent
+    /// enough sqlite, or from the size of the file.
+    fn database_size(file: &File, header: &DatabaseHeader) -> Result<usize, Error> {
+        if header.database_size != 0 && header.change_counter == header.version_valid_for {
+            return Ok(header.database_size as usize);
+        }
+        Ok(file.metadata()?.len() as usize / header.page_size)
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    pub(crate) fn schema_cookie(&self) -> u32 {
+        self.schema_cookie.get()
+    }
+
+    pub(crate) fn page_count(&self) -> usize {
+        self.page_count.get()
+    }
+
+    /// Reads a page by its number, counting from 1. A page past the end of the database is
+    /// only referenced by a corrupt one.
+    pub(crate) fn page(&self, number: usize) -> Result<Page, Error> {
+        if let Some(page) = self.dirty.borrow().get(&number) {
+            return Ok(page.clone());
+        }
+        if number == 0 || number > self.page_count() {
+            return Err(Error::Corrupt {
+                page: Some(number),
+                reason: format!("not in the {} pages of the database", self.page_count()),
+            });
+        }
+        self.committed_page(number)
+    }
+
+    /// Reads a page as of the last commit.
+    fn committed_page(&self, number: usize) -> Result<Page, Error> {
+        if let Some(page) = self.cache.borrow_mut().get(number) {
+            return Ok(page);
+        }
+
+        let wal_page = match &self.wal {
+            Some(wal) => wal.borrow().read_page(number)?,
+            None => None,
+        };
+        let bytes = match wal_page {
+            Some(bytes) => bytes,
+            None => {
+                let page_size = self.header.page_size;
+                let mut bytes = vec![0; page_size];
+                self.file
+                    .read_exact_at(&mut bytes, ((number - 1) * page_size) as u64)?;
+                bytes
+            }
+        };
+
+        let page: Page = bytes.into();
+        self.cache.borrow_mut().insert(number, page.clone());
+        Ok(page)
+    }
+
+    /// Numbers of the pages read from the file since the last call, emptying the cache so
+    /// that the next reads are seen too.
+    #[cfg(test)]
+    pub(crate) fn take_read_pages(&self) -> std::collections::HashSet<usize> {
+        let mut cache = self.cache.borrow_mut();
+        let pages = cache.pages.keys().copied().collect();
+        cache.clear();
+        pages
+    }
+
+    /// Replaces the content of a page, as of the next commit.
+    pub(crate) fn write(&self, number: usize, bytes: Vec<u8>) {
+        assert_eq!(
+            self.header.page_size,
+            bytes.len(),
+            "Page size of page {}",
+            number
+        );
+        assert!(
+            number <= self.page_count(),
+            "Page {} was never allocated",
+            number
+        );
+        self.dirty.borrow_mut().insert(number, bytes.into());
+    }
+
+    /// Takes a page off the freelist, or adds one at the end of the database, returning its
+    /// number. Its content is up to the caller to write.
+    pub(crate) fn allocate_page(&self) -> Result<usize, Error> {
+        let number = self.take_free_page()?.unwrap_or_else(|| {
+            let number = self.page_count() + 1;
+            self.page_count.set(number);
+            number
+        });
+        self.write(number, vec![0; self.header.page_size]);
+        Ok(number)
+    }
+
+    /// Puts a page no longer used on the freelist, for a later allocation to reuse.
+    ///
+    /// The freelist is a chain of trunk pages, each listing the numbers of free leaf pages
+    /// after the number of the next trunk and the count of leaves. A freed page is listed on
+    /// the first trunk if it has room, and otherwise becomes the first trunk itself.
+    pub(crate) fn free_page(&self, number: usize) -> Result<(), Error> {
+        let first_trunk = self.header_field(FIRST_TRUNK_OFFSET)?;
+        // Older versions of sqlite misread trunks filled to the last few entries.
+        let max_leaves = self.header.usable_size() / 4 - 8;
+
+        if first_trunk != 0 {
+            let mut trunk = self.page(first_trunk)?.to_vec();
+            let leaves = Self::trunk_field(&trunk, 4, first_trunk)?;
+            if leaves < max_leaves {
+                let entry = 8 + leaves * 4;
+                trunk[entry..entry + 4].copy_from_slice(&(number as u32).to_be_bytes());
+                trunk[4..8].copy_from_slice(&(leaves as u32 + 1).to_be_bytes());
+                self.write(first_trunk, trunk);
+                let free_pages = self.header_field(FREE_PAGES_OFFSET)?;
+                return self.set_header_field(FREE_PAGES_OFFSET, free_pages + 1);
+            }
+        }
+
+        let mut trunk = vec![0; self.header.page_size];
+        trunk[..4].copy_from_slice(&(first_trunk as u32).to_be_bytes());
+        self.write(number, trunk);
+        self.set_header_field(FIRST_TRUNK_OFFSET, number)?;
+        let free_pages = self.header_field(FREE_PAGES_OFFSET)?;
+        self.set_header_field(FREE_PAGES_OFFSET, free_pages + 1)
+    }
+
+    /// Takes the last leaf of the first freelist trunk, or the trunk itself once it lists none.
+    fn take_free_page(&self) -> Result<Option<usize>, Error> {
+        let first_trunk = self.header_field(FIRST_TRUNK_OFFSET)?;
+        if first_trunk == 0 {
+            return Ok(None);
+        }
+
+        let mut trunk = self.page(first_trunk)?.to_vec();
+        let leaves = Self::trunk_field(&trunk, 4, first_trunk)?;
+        let number = if leaves > 0 {
+            let leaf = Self::trunk_field(&trunk, 8 + (leaves - 1) * 4, first_trunk)?;
+            trunk[4..8].copy_from_slice(&(leaves as u32 - 1).to_be_bytes());
+            self.write(first_trunk, trunk);
+            leaf
+        } else {
+            let next_trunk = Self::trunk_field(&trunk, 0, first_trunk)?;
+            self.set_header_field(FIRST_TRUNK_OFFSET, next_trunk)?;
+            first_trunk
+        };
+        let free_pages = self.header_field(FREE_PAGES_OFFSET)?;
+        self.set_header_field(FREE_PAGES_OFFSET, free_pages.saturating_sub(1))?;
+        Ok(Some(number))
+    }
+
+    /// A page number or count listed on a freelist trunk.
+    fn trunk_field(trunk: &[u8], offset: usize, number: usize) -> Result<usize, Error> {
+        let field = Reader::new(trunk)
+            .at(offset)
+            .and_then(|field| field.peek_i32());
+        Ok(field.map_err(|err| err.on_page(number))? as u32 as usize)
+    }
+
+    /// Number of pages on the freelist.
+    #[cfg(test)]
+    pub(crate) fn free_page_count(&self) -> Result<usize, Error> {
+        self.header_field(FREE_PAGES_OFFSET)
+    }
+
+    fn header_field(&self, offset: usize) -> Result<usize, Error> {
+        Ok(Reader::new(&self.page(1)?[offset..]).peek_i32()? as u32 as usize)
+    }
+
+    fn set_header_field(&self, offset: usize, value: usize) -> Result<(), Error> {
+        let mut first_page = self.page(1)?.to_vec();
+        first_page[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes());
+        self.write(1, first_page);
+        Ok(())
+    }
+
+    /// Takes the shared lock for a statement to read the database under, until the returned
+    /// lock is dropped. Taking it after another connection committed drops the cached pages.
+    pub(crate) fn read(&self) -> Result<ReadLock<'_>, Error> {
+        if self.lock.get() == Lock::None {
+            self.lock_shared()?;
+        }
+        self.readers.set(self.readers.get() + 1);
+        Ok(ReadLock { pager: self })
+    }
+
+    /// Takes the reserved lock a statement needs to write, held until the transaction ends. Only
+    /// one connection holds it at a time, failing the others right away, while readers carry on.
+    pub(crate) fn reserve(&self) -> Result<(), Error> {
+        if self.lock.get() >= Lock::Reserved || self.read_only || self.header.is_wal() {
+            return Ok(());
+        }
+        if self.lock.get() == Lock::None {
+            self.lock_shared()?;
+        }
+        if !set_lock(&self.file, libc::F_WRLCK, RESERVED_BYTE, 1)? {
+            return Err(Error::Busy);
+        }
+        self.lock.set(Lock::Reserved);
+        Ok(())
+    }
+
+    fn lock_shared(&self) -> Result<(), Error> {
+        self.lock.set(Lock::Shared);
+        let locked = Self::lock_file(&self.file, &self.journal_path, self.read_only)
+            .and_then(|()| self.catch_up());
+        if locked.is_err() {
+            self.unlock(Lock::None);
+        }
+        locked
+    }
+
+    /// Drops the pages cached before another connection committed, once the shared lock is
+    /// taken. A change to the schema shows in `schema_cookie`, for the schema to be read again.
+    ///
+    /// In WAL mode, the log is read up to the last commit under a read lock of its own, held
+    /// along with the shared lock. The header is then on page 1, which a commit since the last
+    /// checkpoint left in the log, and sqlite doesn't bump the change counter in this mode.
+    fn catch_up(&self) -> Result<(), Error> {
+        let mut header_bytes = [0; DATABASE_HEADER_SIZE];
+        let page_count = if let Some(wal) = &self.wal {
+            let mut wal = wal.borrow_mut();
+            if !wal.begin_read()? {
+                return Ok(());
+            }
+            match wal.read_page(1)? {
+                Some(first_page) => {
+                    header_bytes.copy_from_slice(&first_page[..DATABASE_HEADER_SIZE])
+                }
+                None => self.file.read_exact_at(&mut header_bytes, 0)?,
+            }
+            wal.database_size()
+        } else {
+            self.file.read_exact_at(&mut header_bytes, 0)?;
+            None
+        };
+        let header = DatabaseHeader::from(&Reader::new(&header_bytes[..]))?;
+        if self.wal.is_none() && header.change_counter == self.change_counter.get() {
+            return Ok(());
+        }
+
+        let page_count = match page_count {
+            Some(page_count) => page_count,
+            None => Self::database_size(&self.file, &header)?,
+        };
+        self.cache.borrow_mut().clear();
+        self.page_count.set(page_count);
+        self.committed_page_count.set(page_count);
+        self.change_counter.set(header.change_counter);
+        self.schema_cookie.set(header.schema_cookie);
+        self.generation.set(self.generation.get() + 1);
+        Ok(())
+    }
+
+    /// Lowers the lock to the one still needed: whatever a transaction took until it ends, the
+    /// shared lock while a statement reads, or none.
+    fn release(&self) {
+        if self.in_transaction() {
+            return;
+        }
+        let needed = if self.readers.get() > 0 {
+            Lock::Shared
+        } else {
+            Lock::None
+        };
+        if self.lock.get() > needed {
+            self.unlock(needed);
+        }
+    }
+
+    /// Lowers the lock to the shared one or none. Unlocking bytes that aren't locked does
+    /// nothing, and unlocking fails only on a file that isn't open, so errors are ignored.
+    fn unlock(&self, to: Lock) {
+        if to == Lock::None
+            && let Some(wal) = &self.wal
+        {
+            wal.borrow_mut().end_read();
+        }
+        let _ = if to == Lock::None {
+            set_lock(&self.file, libc::F_UNLCK, PENDING_BYTE, 2 + SHARED_SIZE)
+        } else {
+            set_lock(&self.file, libc::F_RDLCK, SHARED_FIRST, SHARED_SIZE)
+                .and_then(|_| set_lock(&self.file, libc::F_UNLCK, PENDING_BYTE, 2))
+        };
+        self.lock.set(to);
+    }
+
+    /// Takes a shared lock on the database file. A journal left without a writer holding the
+    /// reserved lock is hot, from a commit cut short, and is played back before reading, with
+    /// the file to ourselves.
+    fn lock_file(file: &File, journal_path: &Path, read_only: bool) -> Result<(), Error> {
+        // A commit waiting for the readers to finish holds the pending byte, keeping new ones out.
+        wait_for_lock(|| {
+            if !set_lock(file, libc::F_RDLCK, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            let locked = set_lock(file, libc::F_RDLCK, SHARED_FIRST, SHARED_SIZE)?;
+            set_lock(file, libc::F_UNLCK, PENDING_BYTE, 1)?;
+            Ok(locked)
+        })?;
+        if !journal_path.exists() || lock_held(file, RESERVED_BYTE)? {
+            return Ok(());
+        }
+        if read_only {
+            return Self::play_back_journal(file, journal_path, read_only);
+        }
+
+        Self::lock_exclusive(file)?;
+        let played_back = Self::play_back_journal(file, journal_path, read_only);
+        set_lock(file, libc::F_RDLCK, SHARED_FIRST, SHARED_SIZE)?;
+        set_lock(file, libc::F_UNLCK, PENDING_BYTE, 1)?;
+        played_back
+    }
+
+    /// Takes the pending lock, keeping new readers out, then the exclusive lock once the other
+    /// connections are done reading.
+    fn lock_exclusive(file: &File) -> Result<(), Error> {
+        wait_for_lock(|| set_lock(file, libc::F_WRLCK, PENDING_BYTE, 1))?;
+        wait_for_lock(|| set_lock(file, libc::F_WRLCK, SHARED_FIRST, SHARED_SIZE)).inspect_err(
+            |_| {
+                let _ = set_lock(file, libc::F_UNLCK, PENDING_BYTE, 1);
+            },
+        )
+    }
+
+    /// Starts a transaction that statements add to until `commit` or `rollback`.
+    pub(crate) fn begin(&self) -> Result<(), Error> {
+        if self.in_transaction.replace(true) {
+            return Err("cannot start a transaction within a transaction".into());
+        }
+        Ok(())
+    }
+
+    /// Whether a transaction started by `begin` is open.
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.in_transaction.get()
+    }
+
+    pub(crate) fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            dirty: self.dirty.borrow().clone(),
+            page_count: self.page_count(),
+        }
+    }
+
+    /// Undoes the writes made since the savepoint, leaving the transaction open.
+    pub(crate) fn restore(&self, savepoint: Savepoint) {
+        *self.dirty.borrow_mut() = savepoint.dirty;
+        self.page_count.set(savepoint.page_count);
+    }
+
+    /// Writes the pages of the transaction to the file, along with the database header
+    /// recording the new size and a bumped change counter, and ends the transaction.
+    ///
+    /// The original content of the pages is saved to the journal and synced before the
+    /// database is written to, and the journal deleted once the database is synced: a commit
+    /// cut short before that leaves a hot journal, undoing it when played back.
+    ///
+    /// The file is only written with the exclusive lock, waiting for the other connections to
+    /// finish reading. A commit that can't get it leaves the transaction open, to commit later.
+    pub(crate) fn commit(&self) -> Result<(), Error> {
+        if !self.dirty.borrow().is_empty() && !self.read_only && !self.header.is_wal() {
+            self.reserve()?;
+            Self::lock_exclusive(&self.file)?;
+            self.lock.set(Lock::Exclusive);
+        }
+        let committed = self.write_transaction();
+        self.release();
+        committed
+    }
+
+    fn write_transaction(&self) -> Result<(), Error> {
+        self.in_transaction.set(false);
+        self.generation.set(self.generation.get() + 1);
+        if self.dirty.borrow().is_empty() {
+            return Ok(());
+        }
+        if self.read_only {
+            self.rollback();
+            return Err(Error::ReadOnly);
+        }
+        if self.header.is_wal() {
+            self.rollback();
+            return Err("cannot write to a database in WAL mode".into());
+        }
+
+        let mut first_page = self.page(1)?.to_vec();
+        let change_counter = Reader::new(&first_page[24..]).peek_i32()? as u32;
+        let change_counter = change_counter.wrapping_add(1);
+        self.change_counter.set(change_counter);
+        first_page[24..28].copy_from_slice(&change_counter.to_be_bytes());
+        first_page[28..32].copy_from_slice(&(self.page_count() as u32).to_be_bytes());
+        first_page[92..96].copy_from_slice(&change_counter.to_be_bytes());
+        self.write(1, first_page);
+
+        let dirty = std::mem::take(&mut *self.dirty.borrow_mut());
+        let mut numbers = dirty.keys().copied().collect::<Vec<_>>();
+        numbers.sort_unstable();
+        self.write_journal(&numbers)?;
+        self.write_pages(&dirty, &numbers)?;
+        fs::remove_file(&self.journal_path)?;
+
+        let mut cache = self.cache.borrow_mut();
+        for (number, page) in dirty {
+            cache.insert(number, page);
+        }
+        self.committed_page_count.set(self.page_count());
+        Ok(())
+    }
+
+    /// Drops the pages written since the last commit, and ends the transaction.
+    pub(crate) fn rollback(&self) {
+        self.in_transaction.set(false);
+        self.generation.set(self.generation.get() + 1);
+        self.dirty.borrow_mut().clear();
+        self.page_count.set(self.committed_page_count.get());
+        self.release();
+    }
+
+    /// Writes the journal of a commit: a header, then for each page the commit overwrites, its
+    /// number, original content and a checksum, the way sqlite writes it.
+    ///
+    /// The header holds the number of page records, a nonce the checksums start from, the
+    /// original database size in pages, and the sector and page sizes. The nonce keeps the
+    /// records of an older journal left in the file from checking out as records of this one.
+    fn write_journal(&self, numbers: &[usize]) -> Result<(), Error> {
+        let page_size = self.header.page_size;
+        let original = numbers
+            .iter()
+            .copied()
+            .filter(|&number| number <= self.committed_page_count.get())
+            .collect::<Vec<_>>();
+        let nonce = RandomState::new().hash_one(SystemTime::now()) as u32;
+
+        let mut journal =
+            Vec::with_capacity(JOURNAL_SECTOR_SIZE + original.len() * (page_size + 8));
+        journal.extend_from_slice(&JOURNAL_MAGIC);
+        for field in [
+            original.len(),
+            nonce as usize,
+            self.committed_page_count.get(),
+            JOURNAL_SECTOR_SIZE,
+            page_size,
+        ] {
+            journal.extend_from_slice(&(field as u32).to_be_bytes());
+        }
+        journal.resize(JOURNAL_SECTOR_SIZE, 0);
+        for number in original {
+            let page = self.committed_page(number)?;
+            journal.extend_from_slice(&(number as u32).to_be_bytes());
+            journal.extend_from_slice(&page);
+            journal.extend_from_slice(&journal_checksum(nonce, &page).to_be_bytes());
+        }
+
+        let file = File::create(&self.journal_path)?;
+        file.write_all_at(&journal, 0)?;
+        Ok(file.sync_all()?)
+    }
+
+    fn write_pages(&self, pages: &HashMap<usize, Page>, numbers: &[usize]) -> Result<(), Error> {
+        for number in numbers {
+            let offset = ((number - 1) * self.header.page_size) as u64;
+            self.file.write_all_at(&pages[number], offset)?;
+        }
+        Ok(self.file.sync_data()?)
+    }
+
+    /// Restores the pages saved to a hot journal, one left by a commit that didn't complete,
+    /// and truncates the database to its original size, before deleting the journal.
+    ///
+    /// A journal can hold several segments, each starting with a header on a sector boundary.
+    /// Playback stops at the first record failing its checksum, written after the last sync.
+    fn play_back_journal(file: &File, journal_path: &Path, read_only: bool) -> Result<(), Error> {
+        let journal = match fs::read(journal_path) {
+            Ok(journal) => journal,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        // A journal starting with anything else was never synced, and the database not
+        // written to.
+        if !journal.starts_with(&JOURNAL_MAGIC) {
+            if !read_only {
+                fs::remove_file(journal_path)?;
+            }
+            return Ok(());
+        }
+        if read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let field =
+            |offset: usize| Ok::<_, Error>(Reader::new(&journal[offset..]).peek_i32()? as u32);
+        let mut original_size = None;
+        let mut offset = 0;
+        while offset + 28 <= journal.len() && journal[offset..].starts_with(&JOURNAL_MAGIC) {
+            let (records, nonce, size, sector_size, page_size) = (
+                field(offset + 8)?,
+                field(offset + 12)?,
+                field(offset + 16)? as usize,
+                field(offset + 20)? as usize,
+                field(offset + 24)? as usize,
+            );
+            if !(512..=65536).contains(&page_size)
+                || !page_size.is_power_of_two()
+                || !(32..=65536).contains(&sector_size)
+                || !sector_size.is_power_of_two()
+            {
+                break;
+            }
+            original_size.get_or_insert((size, page_size));
+
+            offset += sector_size;
+            let record_size = page_size + 8;
+            let records = if records == JOURNAL_RECORDS_TO_END {
+                journal.len().saturating_sub(offset) / record_size
+            } else {
+                records as usize
+            };
+            for _ in 0..records {
+                if offset + record_size > journal.len() {
+                    break;
+                }
+                let number = field(offset)? as usize;
+                let page = &journal[offset + 4..offset + 4 + page_size];
+                if field(offset + 4 + page_size)? != journal_checksum(nonce, page) {
+                    break;
+                }
+                if number > 0 {
+                    file.write_all_at(page, ((number - 1) * page_size) as u64)?;
+                }
+                offset += record_size;
+            }
+            offset = offset.next_multiple_of(sector_size);
+        }
+
+        if let Some((size, page_size)) = original_size {
+            file.set_len((size * page_size) as u64)?;
+            file.sync_all()?;
+        }
+        fs::remove_file(journal_path)?;
+        Ok(())
+    }
+}
+
+/// Sets a POSIX advisory lock on bytes of the file, the kind sqlite takes, or removes it with
+/// `F_UNLCK`. Tells whether it was set, rather than conflicting with another process' lock.
+pub(crate) fn set_lock(file: &File, kind: libc::c_int, start: u64, len: u64) -> io::Result<bool> {
+    let lock = file_lock(kind, start, len);
+    // SAFETY: the descriptor is open as long as `file` is, and `lock` outlives the call.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN | libc::EACCES) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Whether another process holds a lock on a byte of the file.
+fn lock_held(file: &File, offset: u64) -> io::Result<bool> {
+    let mut lock = file_lock(libc::F_WRLCK, offset, 1);
+    // SAFETY: as for `set_lock`, the call only writing to `lock`.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+fn file_lock(kind: libc::c_int, start: u64, len: u64) -> libc::flock {
+    // SAFETY: `flock` is plain integers, for which zero is valid, in whichever fields the
+    // platform adds to the ones set here.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = kind as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = start as libc::off_t;
+    lock.l_len = len as libc::off_t;
+    lock
+}
+
+/// Retries taking a lock another connection holds, until `BUSY_TIMEOUT` runs out.
+pub(crate) fn wait_for_lock(mut try_lock: impl FnMut() -> io::Result<bool>) -> Result<(), Error> {
+    let start = Instant::now();
+    while !try_lock()? {
+        if start.elapsed() >= BUSY_TIMEOUT {
+            return Err(Error::Busy);
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+/// The checksum of a journal record, the nonce plus every 200th byte of the page, counting down
+/// from 200 bytes before its end.
+fn journal_checksum(nonce: u32, page: &[u8]) -> u32 {
+    (1..page.len().div_ceil(200))
+        .map(|i| page[page.len() - i * 200])
+        .fold(nonce, |sum, byte| sum.wrapping_add(byte as u32))
+}
+
+/// Least recently used pages are evicted first once the cache is full.
+struct PageCache {
+    capacity: usize,
+    /// Pages by number, with the tick they were last used at.
+    pages: HashMap<usize, (Page, u64)>,
+    /// Page numbers by the tick they were last used at, oldest first.
+    recency: BTreeMap<u64, usize>,
+    tick: u64,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, number: usize) -> Option<Page> {
+        let (page, used) = self.pages.get_mut(&number)?;
+        self.recency.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, number);
+        Some(page.clone())
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+    }
+
+    fn insert(&mut self, number: usize, page: Page) {
+        if let Some((_, used)) = self.pages.remove(&number) {
+            self.recency.remove(&used);
+        }
+        if self.pages.len() >= self.capacity
+            && let Some((_, oldest)) = self.recency.pop_first()
+        {
+            self.pages.remove(&oldest);
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, number);
+        self.pages.insert(number, (page, self.tick));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::pager::Pager;
+
+    #[test]
+    fn test_page_cache_evicts_least_recently_used() {
+        let page_size = 512;
+        let mut file = vec![0u8; page_size * 4];
+        file[16..18].copy_from_slice(&(page_size as u16).to_be_bytes());
+        for page in 1..4 {
+            file[page * page_size] = page as u8 + 1;
+        }
+        let path = std::env::temp_dir().join(format!("pager-test-{}.db", std::process::id()));
+        fs::write(&path, &file).unwrap();
+
+        let pager = Pager::with_capacity(&path, 2).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(512, pager.header.page_size);
+        assert_eq!(2, pager.page(2).unwrap()[0]);
+        assert_eq!(3, pager.page(3).unwrap()[0]);
+        pager.page(2).unwrap();
+        assert_eq!(4, pager.page(4).unwrap()[0]);
+        assert!(pager.page(5).is_err());
+
+        let cache = pager.cache.borrow();
+        assert!(cache.pages.contains_key(&2));
+        assert!(!cache.pages.contains_key(&3));
+        assert!(cache.pages.contains_key(&4));
+    }
+
+    #[test]
+    fn test_hot_journal_is_played_back() {
+        let path = std::env::temp_dir().join(format!("journal-test-{}.db", std::process::id()));
+        fs::copy("sample.db", &path).unwrap();
+        let original = fs::read(&path).unwrap();
+
+        // A commit cut short after writing the database, before deleting the journal.
+        let pager = Pager::open(&path).unwrap();
+        let page_size = pager.header.page_size;
+        pager.write(2, vec![0xff; page_size]);
+        let added = pager.allocate_page().unwrap();
+        pager.write(added, vec![0xee; page_size]);
+        let dirty = pager.dirty.borrow().clone();
+        let numbers = [2, added];
+        pager.write_journal(&numbers).unwrap();
+        pager.write_pages(&dirty, &numbers).unwrap();
+        drop(pager);
+        assert_ne!(original, fs::read(&path).unwrap());
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(original, fs::read(&path).unwrap());
+        assert!(!pager.journal_path.exists());
+        assert_eq!(original.len() / page_size, pager.page_count());
+        fs::remove_file(&path).unwrap();
+    }
+}
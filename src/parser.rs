@@ -0,0 +1,1163 @@
+use crate::{
+    ast::{
+        BinaryOp, DeleteStmt, Expr, FunctionArgs, InsertSource, InsertStmt, Join, JoinKind, LikeOp,
+        OrderingTerm, ResultColumn, SelectStmt, Statement, TableRef, UnaryOp, UpdateStmt,
+    },
+    common::Error,
+    lexer::{Lexer, Token, TokenKind},
+    record::Record,
+    schema::Collation,
+};
+
+/// Keywords that can never be used as an implicit (`AS`-less) alias.
+const RESERVED_KEYWORDS: &[&str] = &[
+    "ALL", "AND", "AS", "ASC", "BETWEEN", "BY", "COLLATE", "CROSS", "DELETE", "DESC", "DISTINCT",
+    "ESCAPE", "EXISTS", "EXPLAIN", "FROM", "GLOB", "GROUP", "HAVING", "IN", "INNER", "INSERT",
+    "INTO", "IS", "ISNULL", "JOIN", "LEFT", "LIKE", "LIMIT", "NATURAL", "NOT", "NOTNULL", "NULL",
+    "OFFSET", "ON", "OR", "ORDER", "OUTER", "SELECT", "SET", "UPDATE", "USING", "VALUES", "WHERE",
+];
+
+/// Largest number a `?NNN` parameter may have.
+const MAX_PARAMETER_NUMBER: usize = 32766;
+
+/// Recursive-descent parser over the token stream of the `Lexer`.
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Derived tables parsed so far, numbering the unnamed ones.
+    derived_tables: usize,
+    /// Names of the parameters of the current statement by index, `None` for those only
+    /// written `?`.
+    parameters: Vec<Option<String>>,
+}
+
+impl Parser {
+    pub(crate) fn new(sql: &str) -> Result<Self, Error> {
+        Ok(Self {
+            tokens: Lexer::new(sql).tokenize()?,
+            pos: 0,
+            derived_tables: 0,
+            parameters: vec![],
+        })
+    }
+
+    /// Parses a complete statement, optionally terminated by a `;`.
+    pub(crate) fn parse_statement(sql: &str) -> Result<Statement, Error> {
+        let mut parser = Self::new(sql)?;
+        let stmt = parser.statement()?;
+        parser.consume(&TokenKind::Semicolon);
+        parser.expect_eof()?;
+        Ok(stmt)
+    }
+
+    /// Parses a list of statements separated by `;`, as in `BEGIN; DELETE ...; COMMIT`.
+    pub(crate) fn parse_statements(sql: &str) -> Result<Vec<Statement>, Error> {
+        let mut parser = Self::new(sql)?;
+        let mut stmts = vec![];
+        loop {
+            while parser.consume(&TokenKind::Semicolon) {}
+            if parser.peek().kind == TokenKind::Eof {
+                return Ok(stmts);
+            }
+            stmts.push(parser.statement()?);
+            if !parser.consume(&TokenKind::Semicolon) {
+                parser.expect_eof()?;
+                return Ok(stmts);
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        self.parameters.clear();
+        if self.consume_keyword("EXPLAIN") {
+            self.expect_keyword("QUERY")?;
+            self.expect_keyword("PLAN")?;
+            Ok(Statement::ExplainQueryPlan(self.select_stmt()?))
+        } else if self.is_keyword("INSERT") {
+            Ok(Statement::Insert(self.insert_stmt()?))
+        } else if self.is_keyword("UPDATE") {
+            Ok(Statement::Update(self.update_stmt()?))
+        } else if self.is_keyword("DELETE") {
+            Ok(Statement::Delete(self.delete_stmt()?))
+        } else if self.consume_keyword("BEGIN") {
+            let _ = self.consume_keyword("DEFERRED")
+                || self.consume_keyword("IMMEDIATE")
+                || self.consume_keyword("EXCLUSIVE");
+            self.consume_keyword("TRANSACTION");
+            Ok(Statement::Begin)
+        } else if self.consume_keyword("COMMIT") || self.consume_keyword("END") {
+            self.consume_keyword("TRANSACTION");
+            Ok(Statement::Commit)
+        } else if self.consume_keyword("ROLLBACK") {
+            self.consume_keyword("TRANSACTION");
+            Ok(Statement::Rollback)
+        } else {
+            Ok(Statement::Select(self.select_stmt()?))
+        }
+    }
+
+    /// Parses a complete `SELECT` statement, optionally terminated by a `;`.
+    pub(crate) fn parse_select(sql: &str) -> Result<SelectStmt, Error> {
+        let mut parser = Self::new(sql)?;
+        let stmt = parser.select_stmt()?;
+        parser.consume(&TokenKind::Semicolon);
+        parser.expect_eof()?;
+        Ok(stmt)
+    }
+
+    /// Parses a lone expression, such as the `DEFAULT` of a column.
+    pub(crate) fn parse_expr(sql: &str) -> Result<Expr, Error> {
+        let mut parser = Self::new(sql)?;
+        let expr = parser.expr()?;
+        parser.expect_eof()?;
+        Ok(expr)
+    }
+
+    fn select_stmt(&mut self) -> Result<SelectStmt, Error> {
+        self.expect_keyword("SELECT")?;
+
+        let mut columns = vec![self.result_column()?];
+        while self.consume(&TokenKind::Comma) {
+            columns.push(self.result_column()?);
+        }
+
+        self.expect_keyword("FROM")?;
+        let from = self.table_ref()?;
+        let mut joins = vec![];
+        while let Some(kind) = self.join_operator()? {
+            let table = self.table_ref()?;
+            let on = if self.consume_keyword("ON") {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            joins.push(Join { kind, table, on });
+        }
+
+        let where_clause = self.where_clause()?;
+
+        let mut group_by = vec![];
+        if self.consume_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by.push(self.expr()?);
+            while self.consume(&TokenKind::Comma) {
+                group_by.push(self.expr()?);
+            }
+        }
+
+        let having = if self.consume_keyword("HAVING") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        let mut order_by = vec![];
+        if self.consume_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            order_by.push(self.ordering_term()?);
+            while self.consume(&TokenKind::Comma) {
+                order_by.push(self.ordering_term()?);
+            }
+        }
+
+        let (mut limit, mut offset) = (None, None);
+        if self.consume_keyword("LIMIT") {
+            let first = self.expr()?;
+            if self.consume_keyword("OFFSET") {
+                limit = Some(first);
+                offset = Some(self.expr()?);
+            } else if self.consume(&TokenKind::Comma) {
+                // `LIMIT offset, count`
+                offset = Some(first);
+                limit = Some(self.expr()?);
+            } else {
+                limit = Some(first);
+            }
+        }
+
+        Ok(SelectStmt {
+            columns,
+            from,
+            joins,
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn insert_stmt(&mut self) -> Result<InsertStmt, Error> {
+        self.expect_keyword("INSERT")?;
+        self.expect_keyword("INTO")?;
+        let table = self.identifier()?;
+
+        let mut columns = vec![];
+        if self.consume(&TokenKind::LeftParen) {
+            columns.push(self.identifier()?);
+            while self.consume(&TokenKind::Comma) {
+                columns.push(self.identifier()?);
+            }
+            self.expect(&TokenKind::RightParen)?;
+        }
+
+        let source = if self.consume_keyword("VALUES") {
+            let mut rows = vec![self.values_row()?];
+            while self.consume(&TokenKind::Comma) {
+                rows.push(self.values_row()?);
+            }
+            InsertSource::Values(rows)
+        } else {
+            InsertSource::Select(Box::new(self.select_stmt()?))
+        };
+
+        Ok(InsertStmt {
+            table,
+            columns,
+            source,
+        })
+    }
+
+    fn update_stmt(&mut self) -> Result<UpdateStmt, Error> {
+        self.expect_keyword("UPDATE")?;
+        let table = self.identifier()?;
+        self.expect_keyword("SET")?;
+
+        let mut assignments = vec![];
+        loop {
+            let column = self.identifier()?;
+            self.expect(&TokenKind::Eq)?;
+            assignments.push((column, self.expr()?));
+            if !self.consume(&TokenKind::Comma) {
+                break;
+            }
+        }
+
+        Ok(UpdateStmt {
+            table,
+            assignments,
+            where_clause: self.where_clause()?,
+        })
+    }
+
+    fn delete_stmt(&mut self) -> Result<DeleteStmt, Error> {
+        self.expect_keyword("DELETE")?;
+        self.expect_keyword("FROM")?;
+        let table = self.identifier()?;
+
+        Ok(DeleteStmt {
+            table,
+            where_clause: self.where_clause()?,
+        })
+    }
+
+    /// `[WHERE expr]`
+    fn where_clause(&mut self) -> Result<Option<Expr>, Error> {
+        if self.consume_keyword("WHERE") {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `(expr, ...)`
+    fn values_row(&mut self) -> Result<Vec<Expr>, Error> {
+        self.expect(&TokenKind::LeftParen)?;
+        let mut values = vec![self.expr()?];
+        while self.consume(&TokenKind::Comma) {
+            values.push(self.expr()?);
+        }
+        self.expect(&TokenKind::RightParen)?;
+        Ok(values)
+    }
+
+    /// `expr [ASC | DESC] [NULLS FIRST | NULLS LAST]`
+    fn ordering_term(&mut self) -> Result<OrderingTerm, Error> {
+        let expr = self.expr()?;
+        let descending = if self.consume_keyword("DESC") {
+            true
+        } else {
+            self.consume_keyword("ASC");
+            false
+        };
+
+        let nulls_first = if self.consume_keyword("NULLS") {
+            if self.consume_keyword("FIRST") {
+                Some(true)
+            } else {
+                self.expect_keyword("LAST")?;
+                Some(false)
+            }
+        } else {
+            None
+        };
+
+        Ok(OrderingTerm {
+            expr,
+            descending,
+            nulls_first,
+        })
+    }
+
+    fn result_column(&mut self) -> Result<ResultColumn, Error> {
+        if self.consume(&TokenKind::Star) {
+            return Ok(ResultColumn::Star);
+        }
+        if self.peek_kind(1) == &TokenKind::Dot && self.peek_kind(2) == &TokenKind::Star {
+            let table = self.identifier()?;
+            self.advance();
+            self.advance();
+            return Ok(ResultColumn::TableStar(table));
+        }
+
+        let expr = self.expr()?;
+        let alias = self.alias()?;
+        Ok(ResultColumn::Expr { expr, alias })
+    }
+
+    /// `,`, `[INNER | CROSS] JOIN` or `LEFT [OUTER] JOIN`, if one follows.
+    fn join_operator(&mut self) -> Result<Option<JoinKind>, Error> {
+        if self.consume(&TokenKind::Comma) {
+            return Ok(Some(JoinKind::Inner));
+        }
+
+        let kind = if self.consume_keyword("LEFT") {
+            self.consume_keyword("OUTER");
+            JoinKind::Left
+        } else if self.consume_keyword("INNER")
+            || self.consume_keyword("CROSS")
+            || self.is_keyword("JOIN")
+        {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_keyword("JOIN")?;
+        Ok(Some(kind))
+    }
+
+    /// `table [[AS] alias]` or `(SELECT ...) [[AS] alias]`
+    fn table_ref(&mut self) -> Result<TableRef, Error> {
+        if self.consume(&TokenKind::LeftParen) {
+            let select = self.select_stmt()?;
+            self.expect(&TokenKind::RightParen)?;
+            self.derived_tables += 1;
+            return Ok(TableRef {
+                name: format!("(subquery-{})", self.derived_tables),
+                alias: self.alias()?,
+                select: Some(Box::new(select)),
+            });
+        }
+
+        let name = self.identifier()?;
+        let alias = self.alias()?;
+        Ok(TableRef {
+            name,
+            alias,
+            select: None,
+        })
+    }
+
+    /// `[AS] alias`
+    fn alias(&mut self) -> Result<Option<String>, Error> {
+        if self.consume_keyword("AS") {
+            return self.identifier().map(Some);
+        }
+
+        match &self.peek().kind {
+            TokenKind::QuotedIdentifier(_) => self.identifier().map(Some),
+            TokenKind::Word(word) if !Self::is_reserved(word) => self.identifier().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn expr(&mut self) -> Result<Expr, Error> {
+        self.or_expr()
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.and_expr()?;
+        while self.consume_keyword("OR") {
+            let rhs = self.and_expr()?;
+            lhs = Self::binary(BinaryOp::Or, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.not_expr()?;
+        while self.consume_keyword("AND") {
+            let rhs = self.not_expr()?;
+            lhs = Self::binary(BinaryOp::And, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, Error> {
+        if self.consume_keyword("NOT") {
+            let expr = self.not_expr()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.equality_expr()
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.comparison_expr()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Eq => Some(BinaryOp::Eq),
+                TokenKind::NotEq => Some(BinaryOp::NotEq),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.advance();
+                let rhs = self.comparison_expr()?;
+                lhs = Self::binary(op, lhs, rhs);
+                continue;
+            }
+
+            if self.consume_keyword("IS") {
+                let op = if self.consume_keyword("NOT") {
+                    BinaryOp::IsNot
+                } else {
+                    BinaryOp::Is
+                };
+                let rhs = self.comparison_expr()?;
+                lhs = Self::binary(op, lhs, rhs);
+                continue;
+            }
+
+            if self.consume_keyword("ISNULL") {
+                lhs = Self::is_null(lhs, false);
+                continue;
+            }
+            if self.consume_keyword("NOTNULL") {
+                lhs = Self::is_null(lhs, true);
+                continue;
+            }
+            if self.is_keyword("NOT") && self.is_keyword_at(1, "NULL") {
+                self.advance();
+                self.advance();
+                lhs = Self::is_null(lhs, true);
+                continue;
+            }
+
+            let negated = self.is_keyword("NOT")
+                && ["IN", "LIKE", "GLOB", "BETWEEN"]
+                    .iter()
+                    .any(|keyword| self.is_keyword_at(1, keyword));
+            if negated {
+                self.advance();
+            }
+
+            if self.consume_keyword("IN") {
+                self.expect(&TokenKind::LeftParen)?;
+                if self.is_keyword("SELECT") {
+                    let select = self.select_stmt()?;
+                    self.expect(&TokenKind::RightParen)?;
+                    lhs = Expr::InSelect {
+                        expr: Box::new(lhs),
+                        select: Box::new(select),
+                        negated,
+                    };
+                    continue;
+                }
+
+                let mut list = vec![];
+                if !self.consume(&TokenKind::RightParen) {
+                    list.push(self.expr()?);
+                    while self.consume(&TokenKind::Comma) {
+                        list.push(self.expr()?);
+                    }
+                    self.expect(&TokenKind::RightParen)?;
+                }
+                lhs = Expr::InList {
+                    expr: Box::new(lhs),
+                    list,
+                    negated,
+                };
+            } else if self.consume_keyword("BETWEEN") {
+                let low = self.comparison_expr()?;
+                self.expect_keyword("AND")?;
+                let high = self.comparison_expr()?;
+                lhs = Expr::Between {
+                    expr: Box::new(lhs),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                };
+            } else if self.is_keyword("LIKE") || self.is_keyword("GLOB") {
+                let op = if self.consume_keyword("LIKE") {
+                    LikeOp::Like
+                } else {
+                    self.advance();
+                    LikeOp::Glob
+                };
+                let pattern = self.comparison_expr()?;
+                let escape = if self.consume_keyword("ESCAPE") {
+                    Some(Box::new(self.comparison_expr()?))
+                } else {
+                    None
+                };
+                lhs = Expr::Like {
+                    op,
+                    expr: Box::new(lhs),
+                    pattern: Box::new(pattern),
+                    escape,
+                    negated,
+                };
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn comparison_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.additive_expr()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Lt => BinaryOp::Lt,
+                TokenKind::LtEq => BinaryOp::LtEq,
+                TokenKind::Gt => BinaryOp::Gt,
+                TokenKind::GtEq => BinaryOp::GtEq,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.additive_expr()?;
+            lhs = Self::binary(op, lhs, rhs);
+        }
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.multiplicative_expr()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Plus => BinaryOp::Add,
+                TokenKind::Minus => BinaryOp::Subtract,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.multiplicative_expr()?;
+            lhs = Self::binary(op, lhs, rhs);
+        }
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.concat_expr()?;
+        loop {
+            let op = match self.peek().kind {
+                TokenKind::Star => BinaryOp::Multiply,
+                TokenKind::Slash => BinaryOp::Divide,
+                TokenKind::Percent => BinaryOp::Modulo,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            let rhs = self.concat_expr()?;
+            lhs = Self::binary(op, lhs, rhs);
+        }
+    }
+
+    fn concat_expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.collate_expr()?;
+        while self.consume(&TokenKind::Concat) {
+            let rhs = self.collate_expr()?;
+            lhs = Self::binary(BinaryOp::Concat, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    /// `expr COLLATE name`, binding tighter than any binary operator.
+    fn collate_expr(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.unary_expr()?;
+        while self.consume_keyword("COLLATE") {
+            let name = self.identifier()?;
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation: Collation::from(&name),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, Error> {
+        if self.consume(&TokenKind::Minus) {
+            if self.consume(&TokenKind::MinIntegerMagnitude) {
+                return Ok(Expr::Literal(Record::I64(i64::MIN)));
+            }
+            let expr = self.unary_expr()?;
+            let negated = match &expr {
+                // A hex literal for the smallest integer has no negation, left to the
+                // evaluator to turn into a real.
+                Expr::Literal(Record::I64(v)) => v.checked_neg().map(Record::I64),
+                Expr::Literal(Record::Real(v)) => Some(Record::Real(-v)),
+                _ => None,
+            };
+            return Ok(match negated {
+                Some(value) => Expr::Literal(value),
+                None => Expr::Unary {
+                    op: UnaryOp::Negate,
+                    expr: Box::new(expr),
+                },
+            });
+        }
+        if self.consume(&TokenKind::Plus) {
+            return self.unary_expr();
+        }
+        self.primary_expr()
+    }
+
+    /// Numbers a parameter as SQLite does: `?NNN` takes number NNN, a name reused takes the
+    /// number it was first given, and anything else the next number after the largest so far.
+    fn parameter(&mut self, name: String, token: &Token) -> Result<Expr, Error> {
+        let index = match name.strip_prefix('?') {
+            Some("") => self.parameters.len() + 1,
+            Some(number) => number
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=MAX_PARAMETER_NUMBER).contains(number))
+                .ok_or_else(|| Error::Parse {
+                    line: token.line,
+                    column: token.column,
+                    msg: format!(
+                        "variable number must be between ?1 and ?{}",
+                        MAX_PARAMETER_NUMBER
+                    ),
+                })?,
+            None => match self
+                .parameters
+                .iter()
+                .position(|other| other.as_ref() == Some(&name))
+            {
+                Some(i) => i + 1,
+                None => self.parameters.len() + 1,
+            },
+        };
+
+        if self.parameters.len() < index {
+            self.parameters.resize(index, None);
+        }
+        let name = (name != "?").then_some(name);
+        if name.is_some() {
+            self.parameters[index - 1] = name.clone();
+        }
+        Ok(Expr::Parameter { index, name })
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, Error> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Integer(v) => {
+                self.advance();
+                Ok(Expr::Literal(Record::I64(v)))
+            }
+            TokenKind::Float(v) => {
+                self.advance();
+                Ok(Expr::Literal(Record::Real(v)))
+            }
+            TokenKind::MinIntegerMagnitude => {
+                self.advance();
+                Ok(Expr::Literal(Record::Real(-(i64::MIN as f64))))
+            }
+            TokenKind::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(Record::String(s)))
+            }
+            TokenKind::Blob(bytes) => {
+                self.advance();
+                Ok(Expr::Literal(Record::Blob(bytes)))
+            }
+            TokenKind::Variable(ref name) => {
+                self.advance();
+                self.parameter(name.clone(), &token)
+            }
+            TokenKind::LeftParen => {
+                self.advance();
+                let expr = if self.is_keyword("SELECT") {
+                    Expr::Subquery(Box::new(self.select_stmt()?))
+                } else {
+                    self.expr()?
+                };
+                self.expect(&TokenKind::RightParen)?;
+                Ok(expr)
+            }
+            TokenKind::Word(ref word) if word.eq_ignore_ascii_case("EXISTS") => {
+                self.advance();
+                self.expect(&TokenKind::LeftParen)?;
+                let select = self.select_stmt()?;
+                self.expect(&TokenKind::RightParen)?;
+                Ok(Expr::Exists(Box::new(select)))
+            }
+            TokenKind::Word(ref word) if word.eq_ignore_ascii_case("NULL") => {
+                self.advance();
+                Ok(Expr::Literal(Record::Null))
+            }
+            TokenKind::Word(ref word) if Self::is_reserved(word) => Err(self.syntax_error()),
+            TokenKind::Word(_) | TokenKind::QuotedIdentifier(_) => {
+                let name = self.identifier()?;
+
+                if self.consume(&TokenKind::LeftParen) {
+                    let args = self.function_args()?;
+                    return Ok(Expr::Function { name, args });
+                }
+
+                if self.consume(&TokenKind::Dot) {
+                    let column = self.identifier()?;
+                    return Ok(Expr::Column {
+                        table: Some(name),
+                        name: column,
+                    });
+                }
+
+                Ok(Expr::Column { table: None, name })
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// Arguments of a function call, after the opening parenthesis.
+    fn function_args(&mut self) -> Result<FunctionArgs, Error> {
+        if self.consume(&TokenKind::Star) {
+            self.expect(&TokenKind::RightParen)?;
+            return Ok(FunctionArgs::Star);
+        }
+
+        let distinct = self.consume_keyword("DISTINCT");
+        let mut args = vec![];
+        if !self.consume(&TokenKind::RightParen) {
+            args.push(self.expr()?);
+            while self.consume(&TokenKind::Comma) {
+                args.push(self.expr()?);
+            }
+            self.expect(&TokenKind::RightParen)?;
+        }
+
+        Ok(FunctionArgs::List { distinct, args })
+    }
+
+    fn identifier(&mut self) -> Result<String, Error> {
+        match &self.peek().kind {
+            TokenKind::Word(word) if !Self::is_reserved(word) => {
+                let word = word.clone();
+                self.advance();
+                Ok(word)
+            }
+            TokenKind::QuotedIdentifier(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    fn is_null(expr: Expr, negated: bool) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(expr),
+            negated,
+        }
+    }
+
+    fn is_reserved(word: &str) -> bool {
+        RESERVED_KEYWORDS
+            .iter()
+            .any(|keyword| keyword.eq_ignore_ascii_case(word))
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_kind(&self, ahead: usize) -> &TokenKind {
+        &self.tokens[(self.pos + ahead).min(self.tokens.len() - 1)].kind
+    }
+
+    fn advance(&mut self) {
+        if self.peek().kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.is_keyword_at(0, keyword)
+    }
+
+    fn is_keyword_at(&self, ahead: usize, keyword: &str) -> bool {
+        let token = &self.tokens[(self.pos + ahead).min(self.tokens.len() - 1)];
+        matches!(&token.kind, TokenKind::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.consume_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    fn consume(&mut self, kind: &TokenKind) -> bool {
+        if &self.peek().kind == kind {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<(), Error> {
+        if self.consume(kind) {
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    fn expect_eof(&self) -> Result<(), Error> {
+        if self.peek().kind == TokenKind::Eof {
+            Ok(())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    /// sqlite3-style `near "X": syntax error` at the current token.
+    fn syntax_error(&self) -> Error {
+        let token = self.peek();
+        let msg = match &token.kind {
+            TokenKind::Eof => String::from("incomplete input"),
+            kind => format!("near \"{}\": syntax error", Self::token_text(kind)),
+        };
+        Error::Parse {
+            line: token.line,
+            column: token.column,
+            msg,
+        }
+    }
+
+    fn token_text(kind: &TokenKind) -> String {
+        match kind {
+            TokenKind::Word(word) => word.clone(),
+            TokenKind::QuotedIdentifier(name) => format!("\"{}\"", name),
+            TokenKind::String(s) => format!("'{}'", s),
+            TokenKind::Integer(v) => v.to_string(),
+            TokenKind::MinIntegerMagnitude => String::from("9223372036854775808"),
+            TokenKind::Float(v) => v.to_string(),
+            TokenKind::Blob(_) => String::from("x'...'"),
+            TokenKind::Variable(name) => name.clone(),
+            TokenKind::Comma => String::from(","),
+            TokenKind::Dot => String::from("."),
+            TokenKind::Semicolon => String::from(";"),
+            TokenKind::LeftParen => String::from("("),
+            TokenKind::RightParen => String::from(")"),
+            TokenKind::Star => String::from("*"),
+            TokenKind::Plus => String::from("+"),
+            TokenKind::Minus => String::from("-"),
+            TokenKind::Slash => String::from("/"),
+            TokenKind::Percent => String::from("%"),
+            TokenKind::Concat => String::from("||"),
+            TokenKind::Eq => String::from("="),
+            TokenKind::NotEq => String::from("!="),
+            TokenKind::Lt => String::from("<"),
+            TokenKind::LtEq => String::from("<="),
+            TokenKind::Gt => String::from(">"),
+            TokenKind::GtEq => String::from(">="),
+            TokenKind::Eof => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ast::{BinaryOp, Expr, FunctionArgs, InsertSource, JoinKind, ResultColumn, Statement},
+        common::Error,
+        parser::Parser,
+        record::Record,
+    };
+
+    #[test]
+    fn test_parse_select() {
+        let stmt = Parser::parse_select(
+            "select brand, \"model name\" AS m from cars where brand = 'AND = OR' and year = 2020;",
+        )
+        .unwrap();
+
+        assert_eq!(2, stmt.columns.len());
+        assert!(matches!(
+            &stmt.columns[1],
+            ResultColumn::Expr { expr: Expr::Column { name, .. }, alias: Some(alias) }
+                if name == "model name" && alias == "m"
+        ));
+        assert_eq!("cars", stmt.from.name);
+        assert!(matches!(
+            stmt.where_clause,
+            Some(Expr::Binary {
+                op: BinaryOp::And,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_negative_literals() {
+        let stmt = Parser::parse_select(
+            "SELECT -5, -0x8000000000000000, -9223372036854775808, 9223372036854775808 FROM t",
+        )
+        .unwrap();
+        assert!(matches!(
+            &stmt.columns[0],
+            ResultColumn::Expr {
+                expr: Expr::Literal(Record::I64(-5)),
+                ..
+            }
+        ));
+        // Negating the smallest integer overflows, so it is left to the evaluator.
+        assert!(matches!(
+            &stmt.columns[1],
+            ResultColumn::Expr {
+                expr: Expr::Unary { .. },
+                ..
+            }
+        ));
+        // The smallest integer written in decimal is an integer, its magnitude alone a real.
+        assert!(matches!(
+            &stmt.columns[2],
+            ResultColumn::Expr {
+                expr: Expr::Literal(Record::I64(i64::MIN)),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &stmt.columns[3],
+            ResultColumn::Expr {
+                expr: Expr::Literal(Record::Real(9.223372036854775808e18)),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_explain_query_plan() {
+        let stmt =
+            Parser::parse_statement("EXPLAIN QUERY PLAN SELECT a FROM t WHERE a > 1").unwrap();
+        assert!(matches!(stmt, Statement::ExplainQueryPlan(select) if select.from.name == "t"));
+        assert!(Parser::parse_statement("EXPLAIN SELECT a FROM t").is_err());
+    }
+
+    #[test]
+    fn test_parse_insert() {
+        let stmt =
+            Parser::parse_statement("INSERT INTO t (a, \"b c\") VALUES (1, 'x'), (-2, NULL);")
+                .unwrap();
+        let Statement::Insert(insert) = stmt else {
+            panic!("Expected an INSERT");
+        };
+        assert_eq!("t", insert.table);
+        assert_eq!(["a", "b c"], insert.columns.as_slice());
+        assert!(matches!(&insert.source, InsertSource::Values(rows)
+            if rows.len() == 2 && rows.iter().all(|row| row.len() == 2)));
+
+        let stmt = Parser::parse_statement("insert into t select * from u where x > 1").unwrap();
+        assert!(matches!(stmt, Statement::Insert(insert)
+            if insert.columns.is_empty() && matches!(insert.source, InsertSource::Select(_))));
+
+        assert!(Parser::parse_statement("INSERT INTO t VALUES ()").is_err());
+        assert!(Parser::parse_statement("INSERT t VALUES (1)").is_err());
+    }
+
+    #[test]
+    fn test_parse_update_delete() {
+        let stmt =
+            Parser::parse_statement("UPDATE t SET a = a + 1, \"b c\" = 'x' WHERE id = 3").unwrap();
+        let Statement::Update(update) = stmt else {
+            panic!("Expected an UPDATE");
+        };
+        assert_eq!("t", update.table);
+        let columns = update
+            .assignments
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b c"], columns);
+        assert!(update.where_clause.is_some());
+
+        let stmt = Parser::parse_statement("delete from t").unwrap();
+        assert!(matches!(stmt, Statement::Delete(delete)
+            if delete.table == "t" && delete.where_clause.is_none()));
+
+        assert!(Parser::parse_statement("UPDATE t WHERE a = 1").is_err());
+        assert!(Parser::parse_statement("DELETE t").is_err());
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let stmts =
+            Parser::parse_statements("BEGIN TRANSACTION; DELETE FROM t;; UPDATE t SET a = 1; END")
+                .unwrap();
+        assert!(matches!(
+            stmts.as_slice(),
+            [
+                Statement::Begin,
+                Statement::Delete(_),
+                Statement::Update(_),
+                Statement::Commit
+            ]
+        ));
+        assert!(matches!(
+            Parser::parse_statements("begin immediate; rollback;")
+                .unwrap()
+                .as_slice(),
+            [Statement::Begin, Statement::Rollback]
+        ));
+        assert!(Parser::parse_statements("BEGIN COMMIT").is_err());
+    }
+
+    #[test]
+    fn test_parse_joins() {
+        let stmt = Parser::parse_select(
+            "SELECT p.*, c.name FROM people AS p JOIN countries c ON c.code = p.country \
+             LEFT OUTER JOIN orders o ON o.person_id = p.id, tags WHERE tags.person = p.id",
+        )
+        .unwrap();
+
+        assert!(matches!(&stmt.columns[0], ResultColumn::TableStar(table) if table == "p"));
+        assert_eq!(Some("p"), stmt.from.alias.as_deref());
+        let joins = stmt
+            .joins
+            .iter()
+            .map(|join| (join.kind, join.table.name.as_str(), join.on.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (JoinKind::Inner, "countries", true),
+                (JoinKind::Left, "orders", true),
+                (JoinKind::Inner, "tags", false),
+            ],
+            joins
+        );
+
+        assert!(Parser::parse_select("SELECT * FROM a LEFT b").is_err());
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let stmt = Parser::parse_select(
+            "SELECT name, (SELECT max(amount) FROM orders) FROM (SELECT * FROM people) p \
+             WHERE id NOT IN (SELECT person_id FROM orders) AND EXISTS (SELECT 1 FROM tags)",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            &stmt.columns[1],
+            ResultColumn::Expr {
+                expr: Expr::Subquery(_),
+                ..
+            }
+        ));
+        assert!(stmt.from.select.is_some());
+        assert_eq!(Some("p"), stmt.from.alias.as_deref());
+        let conjuncts = stmt.where_clause.as_ref().unwrap().conjuncts();
+        assert!(matches!(conjuncts[0], Expr::InSelect { negated: true, .. }));
+        assert!(matches!(conjuncts[1], Expr::Exists(_)));
+
+        let stmt = Parser::parse_select("SELECT * FROM (SELECT 1 FROM a)").unwrap();
+        assert_eq!("(subquery-1)", stmt.from.name);
+    }
+
+    #[test]
+    fn test_parse_count_star() {
+        let stmt = Parser::parse_select("SELECT COUNT(*) FROM apples").unwrap();
+        assert!(matches!(
+            &stmt.columns[0],
+            ResultColumn::Expr { expr: Expr::Function { name, args: FunctionArgs::Star }, .. }
+                if name == "COUNT"
+        ));
+    }
+
+    #[test]
+    fn test_parse_order_by_limit() {
+        let stmt = Parser::parse_select(
+            "SELECT name FROM apples ORDER BY color DESC NULLS FIRST, name LIMIT 5 OFFSET 10",
+        )
+        .unwrap();
+        assert_eq!(2, stmt.order_by.len());
+        assert!(stmt.order_by[0].descending && stmt.order_by[0].nulls_first());
+        assert!(!stmt.order_by[1].descending && stmt.order_by[1].nulls_first());
+        assert!(matches!(stmt.limit, Some(Expr::Literal(Record::I64(5)))));
+        assert!(matches!(stmt.offset, Some(Expr::Literal(Record::I64(10)))));
+
+        let stmt = Parser::parse_select("SELECT name FROM apples LIMIT 10, 5").unwrap();
+        assert!(matches!(stmt.limit, Some(Expr::Literal(Record::I64(5)))));
+        assert!(matches!(stmt.offset, Some(Expr::Literal(Record::I64(10)))));
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let Statement::Update(update) = Parser::parse_statement(
+            "UPDATE t SET a = ?, b = :b WHERE c = ?5 AND d = ? AND e = :b AND f = @f",
+        )
+        .unwrap() else {
+            unreachable!()
+        };
+        let mut parameters = vec![];
+        for expr in update
+            .assignments
+            .iter()
+            .map(|(_, expr)| expr)
+            .chain(update.where_clause.as_ref().unwrap().conjuncts())
+        {
+            expr.walk(&mut |expr| {
+                if let Expr::Parameter { index, name } = expr {
+                    parameters.push((*index, name.clone()));
+                }
+            });
+        }
+        assert_eq!(
+            vec![
+                (1, None),
+                (2, Some(String::from(":b"))),
+                (5, Some(String::from("?5"))),
+                (6, None),
+                (2, Some(String::from(":b"))),
+                (7, Some(String::from("@f"))),
+            ],
+            parameters
+        );
+
+        let err = Parser::parse_statement("SELECT ?0").unwrap_err();
+        assert_eq!(
+            "variable number must be between ?1 and ?32766 at line 1, column 8",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err = Parser::parse_select("SELECT name\nFROM apples WHERE = 1").unwrap_err();
+        let Error::Parse { line, column, msg } = err else {
+            panic!("Expected a parse error, got {:?}", err);
+        };
+        assert_eq!((2, 19), (line, column));
+        assert_eq!("near \"=\": syntax error", msg);
+    }
+}
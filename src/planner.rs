@@ -0,0 +1,1410 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+};
+
+use log::debug;
+
+use crate::{
+    ast::{BinaryOp, Expr, JoinKind, OrderingTerm, ResultColumn, SelectStmt},
+    btree_page_header::BTreePageHeader,
+    common::{Error, Index, Table},
+    database::Database,
+    evaluator::{RowContext, comparison_collation},
+    parser::Parser,
+    query::{Bound, Query},
+    query_executor::TableScan,
+    reader::Reader,
+    record::Record,
+    schema::{Affinity, Collation, IndexSchema, ROWID_NAMES, TableField, TableSchema},
+};
+
+/// Rows a table is assumed to hold per value of an indexed column when `sqlite_stat1` has no
+/// statistics for the index.
+const DEFAULT_ROWS_PER_KEY: f64 = 10.0;
+/// Fraction of the rows assumed to satisfy a one-sided range (`x > ?`), and a two-sided range.
+const ONE_SIDED_RANGE_FACTOR: f64 = 0.25;
+const TWO_SIDED_RANGE_FACTOR: f64 = 1.0 / 64.0;
+/// Cost of one comparison while sorting, relative to reading a page.
+const SORT_COMPARISON_COST: f64 = 0.01;
+
+/// How the rows of the table are read.
+pub(crate) enum AccessPath<'a> {
+    FullScan,
+    RowidRange(RowidRange),
+    IndexRange {
+        index: &'a Index,
+        range: IndexRange,
+        covering: bool,
+    },
+    /// Every entry of an index, forward or backward, either to read rows in ORDER BY order or
+    /// because the index holds every column the query needs.
+    IndexScan {
+        index: &'a Index,
+        reverse: bool,
+        covering: bool,
+    },
+}
+
+impl AccessPath<'_> {
+    /// One line summary of the access path, as sqlite3 words it.
+    fn describe(&self, table: &str) -> String {
+        let index_kind = |covering: bool| if covering { "COVERING INDEX" } else { "INDEX" };
+
+        match self {
+            AccessPath::FullScan => format!("SCAN {}", table),
+            AccessPath::RowidRange(range) => {
+                let constraints = if range.start == range.end {
+                    vec!["rowid=?"]
+                } else {
+                    [
+                        (range.start != i64::MIN).then_some("rowid>?"),
+                        (range.end != i64::MAX).then_some("rowid<?"),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect()
+                };
+                format!(
+                    "SEARCH {} USING INTEGER PRIMARY KEY ({})",
+                    table,
+                    constraints.join(" AND ")
+                )
+            }
+            AccessPath::IndexRange {
+                index,
+                range,
+                covering,
+            } => {
+                let fields = &index.sql_schema.fields;
+                let mut constraints = fields[..range.prefix.len()]
+                    .iter()
+                    .map(|field| format!("{}=?", field.field))
+                    .collect::<Vec<_>>();
+                if let Some(field) = fields.get(range.prefix.len()) {
+                    if range.lower.is_some() {
+                        constraints.push(format!("{}>?", field.field));
+                    }
+                    if range.upper.is_some() {
+                        constraints.push(format!("{}<?", field.field));
+                    }
+                }
+                format!(
+                    "SEARCH {} USING {} {} ({})",
+                    table,
+                    index_kind(*covering),
+                    index.index_name,
+                    constraints.join(" AND ")
+                )
+            }
+            AccessPath::IndexScan {
+                index, covering, ..
+            } => format!(
+                "SCAN {} USING {} {}",
+                table,
+                index_kind(*covering),
+                index.index_name
+            ),
+        }
+    }
+}
+
+/// Where the rows of a FROM clause table come from.
+pub(crate) enum Source<'a> {
+    Table(&'a Table),
+    /// A `(SELECT ...)` in the FROM clause, run to completion before the outer query reads it.
+    Derived {
+        query: Box<Query>,
+        plan: Box<QueryPlan<'a>>,
+        schema: TableSchema,
+    },
+}
+
+/// A table of the FROM clause, under the name its columns are qualified with.
+pub(crate) struct Binding<'a> {
+    pub(crate) name: String,
+    pub(crate) source: Source<'a>,
+    /// Position of the table's first column in a joined row.
+    pub(crate) offset: usize,
+}
+
+impl<'a> Binding<'a> {
+    fn of(query: &Query, db: &'a Database) -> Result<Vec<Self>, Error> {
+        let tables = [&query.from]
+            .into_iter()
+            .chain(query.joins.iter().map(|join| &join.table));
+
+        let mut offset = 0;
+        let mut bindings = vec![];
+        for table_ref in tables {
+            let source = match &table_ref.select {
+                Some(select) => Self::derived(select, &[], db)?,
+                None => match db.table(&table_ref.name) {
+                    Some(table) => Source::Table(table),
+                    None => Self::view(&table_ref.name, db)?,
+                },
+            };
+            let binding = Self {
+                name: table_ref.alias.as_ref().unwrap_or(&table_ref.name).clone(),
+                source,
+                offset,
+            };
+            offset += binding.width();
+            bindings.push(binding);
+        }
+        Ok(bindings)
+    }
+
+    /// Plans a view, read as the derived table of its select.
+    fn view(name: &str, db: &'a Database) -> Result<Source<'a>, Error> {
+        let view = db
+            .view(name)
+            .ok_or_else(|| Error::NoSuchTable(name.to_string()))?;
+        let select = Parser::parse_select(&view.sql_schema.select)
+            .map_err(|err| format!("malformed database schema ({}) - {}", name, err))?;
+        Self::derived(&select, &view.sql_schema.columns, db)
+    }
+
+    /// Plans a derived table. Its columns are named by `names`, else after their alias or the
+    /// column they read, and keep the affinity of that column.
+    fn derived(
+        select: &SelectStmt,
+        names: &[String],
+        db: &'a Database,
+    ) -> Result<Source<'a>, Error> {
+        let query = Query::from_select(select.clone())?;
+        let plan = QueryPlanner::plan(&query, db)?;
+
+        let bindings = &plan.bindings;
+        let table_fields = |binding: &Binding| {
+            binding
+                .schema()
+                .fields
+                .iter()
+                .map(|field| TableField::with_affinity(field.name.clone(), field.affinity()))
+                .collect::<Vec<_>>()
+        };
+        let mut fields = vec![];
+        for column in &query.columns {
+            match column {
+                ResultColumn::Star => fields.extend(bindings.iter().flat_map(table_fields)),
+                ResultColumn::TableStar(table) => fields.extend(
+                    bindings
+                        .iter()
+                        .filter(|binding| binding.name.eq_ignore_ascii_case(table))
+                        .flat_map(table_fields),
+                ),
+                ResultColumn::Expr { expr, alias } => {
+                    let (name, affinity) = match expr {
+                        Expr::Column { table, name } => {
+                            let affinity = Self::resolve(bindings, table.as_deref(), name)?
+                                .map_or(Affinity::Blob, |position| {
+                                    Self::affinity_at(bindings, position)
+                                });
+                            (name.clone(), affinity)
+                        }
+                        _ => (format!("column{}", fields.len() + 1), Affinity::Blob),
+                    };
+                    let name = alias.clone().unwrap_or(name);
+                    fields.push(TableField::with_affinity(name, affinity));
+                }
+            }
+        }
+
+        for (field, name) in fields.iter_mut().zip(names) {
+            field.name = name.clone();
+        }
+
+        Ok(Source::Derived {
+            schema: TableSchema::new(fields),
+            query: Box::new(query),
+            plan: Box::new(plan),
+        })
+    }
+
+    /// The stored table the binding reads, unless it is derived.
+    pub(crate) fn table(&self) -> Option<&'a Table> {
+        match self.source {
+            Source::Table(table) => Some(table),
+            Source::Derived { .. } => None,
+        }
+    }
+
+    pub(crate) fn schema(&self) -> &TableSchema {
+        match &self.source {
+            Source::Table(table) => &table.sql_schema,
+            Source::Derived { schema, .. } => schema,
+        }
+    }
+
+    /// Number of values the binding takes in a joined row. Rows of stored tables end with the
+    /// rowid, after the columns.
+    pub(crate) fn width(&self) -> usize {
+        match self.source {
+            Source::Table(table) => table.sql_schema.fields.len() + 1,
+            Source::Derived { ref schema, .. } => schema.fields.len(),
+        }
+    }
+
+    /// Position of a column in the binding's rows, including the rowid under its own names.
+    fn find_column(&self, name: &str) -> Option<usize> {
+        let schema = self.schema();
+        schema.find_field(name).or_else(|| {
+            (self.table().is_some() && schema.is_rowid_name(name)).then_some(schema.fields.len())
+        })
+    }
+
+    /// The affinity of the value at a position of a joined row.
+    pub(crate) fn affinity_at(bindings: &[Self], position: usize) -> Affinity {
+        let binding = bindings
+            .iter()
+            .rfind(|binding| binding.offset <= position)
+            .expect("Positions start at the first binding");
+        binding
+            .schema()
+            .fields
+            .get(position - binding.offset)
+            .map_or(Affinity::Integer, |field| field.affinity())
+    }
+
+    /// The collation of the column at a position of a joined row, binary for rowids.
+    pub(crate) fn collation_at(bindings: &[Self], position: usize) -> Collation {
+        let binding = bindings
+            .iter()
+            .rfind(|binding| binding.offset <= position)
+            .expect("Positions start at the first binding");
+        binding
+            .schema()
+            .fields
+            .get(position - binding.offset)
+            .map_or(Collation::Binary, |field| field.collation.clone())
+    }
+
+    /// Position in a joined row of the column a reference resolves to.
+    pub(crate) fn resolve(
+        bindings: &[Self],
+        table: Option<&str>,
+        name: &str,
+    ) -> Result<Option<usize>, Error> {
+        let mut found = bindings
+            .iter()
+            .filter(|binding| table.is_none_or(|table| binding.name.eq_ignore_ascii_case(table)))
+            .filter_map(|binding| Some(binding.offset + binding.find_column(name)?));
+
+        let position = found.next();
+        if found.next().is_some() {
+            return Err(format!("ambiguous column name: {}", name).into());
+        }
+        Ok(position)
+    }
+
+    /// Whether every column the expression references resolves to one of the bindings. Columns
+    /// read by subqueries aren't known, so expressions with subqueries never do.
+    fn resolves_all(bindings: &[Self], expr: &Expr) -> bool {
+        let mut resolved = !expr.has_subquery();
+        expr.walk(&mut |expr| {
+            if let Expr::Column { table, name } = expr {
+                resolved &= matches!(Self::resolve(bindings, table.as_deref(), name), Ok(Some(_)));
+            }
+        });
+        resolved
+    }
+}
+
+/// The columns of the bound tables as known before any row is read, telling how a term
+/// compares them.
+struct BoundColumns<'a, 'b>(&'a [Binding<'b>]);
+
+impl RowContext for BoundColumns<'_, '_> {
+    fn column_value(&self, _table: Option<&str>, name: &str) -> Result<Record, Error> {
+        Err(Error::NoSuchColumn(name.to_string()))
+    }
+
+    fn column_affinity(&self, table: Option<&str>, name: &str) -> Result<Option<Affinity>, Error> {
+        Ok(Binding::resolve(self.0, table, name)?
+            .map(|position| Binding::affinity_at(self.0, position)))
+    }
+
+    fn column_collation(&self, table: Option<&str>, name: &str) -> Result<Collation, Error> {
+        match Binding::resolve(self.0, table, name)? {
+            Some(position) => Ok(Binding::collation_at(self.0, position)),
+            None => Err(Error::NoSuchColumn(name.to_string())),
+        }
+    }
+}
+
+/// How the rows of a joined table matching the current rows of the tables before it are found.
+pub(crate) enum JoinLookup<'a> {
+    /// The rowid equals the key, evaluated against the tables before.
+    Rowid(Expr),
+    /// The first column of the index equals the key.
+    Index {
+        index: &'a Index,
+        key: Expr,
+    },
+    Scan,
+}
+
+pub(crate) struct JoinPlan<'a> {
+    pub(crate) kind: JoinKind,
+    pub(crate) on: Option<Expr>,
+    pub(crate) lookup: JoinLookup<'a>,
+}
+
+impl JoinPlan<'_> {
+    fn describe(&self, table: &str) -> String {
+        let step = match &self.lookup {
+            JoinLookup::Rowid(_) => {
+                format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", table)
+            }
+            JoinLookup::Index { index, .. } => format!(
+                "SEARCH {} USING INDEX {} ({}=?)",
+                table, index.index_name, index.sql_schema.fields[0].field
+            ),
+            JoinLookup::Scan => format!("SCAN {}", table),
+        };
+
+        match self.kind {
+            JoinKind::Inner => step,
+            JoinKind::Left => format!("{} LEFT-JOIN", step),
+        }
+    }
+}
+
+pub(crate) struct QueryPlan<'a> {
+    pub(crate) bindings: Vec<Binding<'a>>,
+    /// How the rows of the first table are read.
+    pub(crate) path: AccessPath<'a>,
+    /// The WHERE terms involving only the first table, checked before joining the others.
+    pub(crate) base_filter: Option<Expr>,
+    /// Nested loops over the joined tables, in FROM clause order.
+    pub(crate) joins: Vec<JoinPlan<'a>>,
+    /// Whether the access path yields the rows in ORDER BY order.
+    pub(crate) presorted: bool,
+    pub(crate) group_by: bool,
+    pub(crate) sorts: bool,
+    pub(crate) cost: f64,
+}
+
+/// A line of the `EXPLAIN QUERY PLAN` tree, with the lines nested under it.
+struct PlanNode {
+    label: String,
+    children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn leaf(label: String) -> Self {
+        Self {
+            label,
+            children: vec![],
+        }
+    }
+
+    fn render(
+        nodes: &[PlanNode],
+        indent: &str,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        for (i, node) in nodes.iter().enumerate() {
+            let last = i + 1 == nodes.len();
+            let (branch, nested) = if last { ("`--", "   ") } else { ("|--", "|  ") };
+            writeln!(f, "{}{}{}", indent, branch, node.label)?;
+            Self::render(&node.children, &format!("{}{}", indent, nested), f)?;
+        }
+        Ok(())
+    }
+}
+
+impl QueryPlan<'_> {
+    fn nodes(&self) -> Vec<PlanNode> {
+        // Derived tables are materialized before the query reads any table.
+        let mut nodes = self
+            .bindings
+            .iter()
+            .filter_map(|binding| match &binding.source {
+                Source::Derived { plan, .. } => Some(PlanNode {
+                    label: format!("MATERIALIZE {}", binding.name),
+                    children: plan.nodes(),
+                }),
+                Source::Table(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        nodes.push(PlanNode::leaf(self.path.describe(&self.bindings[0].name)));
+        for (join, binding) in self.joins.iter().zip(&self.bindings[1..]) {
+            nodes.push(PlanNode::leaf(join.describe(&binding.name)));
+        }
+        if self.group_by {
+            nodes.push(PlanNode::leaf("USE TEMP B-TREE FOR GROUP BY".to_string()));
+        }
+        if self.sorts {
+            nodes.push(PlanNode::leaf("USE TEMP B-TREE FOR ORDER BY".to_string()));
+        }
+        nodes
+    }
+}
+
+impl Display for QueryPlan<'_> {
+    /// Renders the plan the way sqlite3 prints `EXPLAIN QUERY PLAN`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "QUERY PLAN")?;
+        PlanNode::render(&self.nodes(), "", f)
+    }
+}
+
+/// Size of a b-tree, estimated from its interior pages and its first leaf.
+#[derive(Debug, Clone, Copy)]
+struct TreeShape {
+    depth: f64,
+    pages: f64,
+    entries: f64,
+}
+
+impl TreeShape {
+    fn of(db: &Database, root_page: usize) -> Result<Self, Error> {
+        // The header of a page and the page numbers of its children.
+        let read = |page: usize| -> Result<_, Error> {
+            let bytes = db.pager.page(page)?;
+            let reader = Reader::new(&bytes[..]);
+            let header = BTreePageHeader::from(&reader).map_err(|err| err.on_page(page))?;
+            let children = if header.kind.is_interior() {
+                header
+                    .child_pages(&reader)
+                    .map_err(|err| err.on_page(page))?
+            } else {
+                vec![]
+            };
+            Ok((header, children))
+        };
+
+        // Every leaf is at the same depth, so the leftmost path gives the depth and a sample leaf.
+        let mut depth = 1;
+        let (mut header, mut children) = read(root_page)?;
+        while header.kind.is_interior() {
+            if depth > db.pager.page_count() {
+                return Err(Error::corrupt("b-tree page is its own descendant").on_page(root_page));
+            }
+            (header, children) = read(children[0])?;
+            depth += 1;
+        }
+        let entries_per_leaf = header.cell_count as f64;
+
+        // Count the leaves through the interior pages above them, without reading the leaves.
+        let mut level = vec![root_page];
+        let mut pages = 0.0;
+        for _ in 1..depth {
+            pages += level.len() as f64;
+            let mut next = vec![];
+            for page in level {
+                next.extend(read(page)?.1);
+            }
+            level = next;
+        }
+        let leaves = level.len() as f64;
+
+        Ok(Self {
+            depth: depth as f64,
+            pages: pages + leaves,
+            entries: (leaves * entries_per_leaf).max(1.0),
+        })
+    }
+}
+
+/// Row counts written to `sqlite_stat1` by `ANALYZE`, by table and index name in lowercase.
+#[derive(Debug, Default)]
+struct Statistics {
+    table_rows: HashMap<String, f64>,
+    /// Per index: its number of entries, then the average number of entries sharing a value for
+    /// each prefix of its columns.
+    index_rows: HashMap<String, Vec<f64>>,
+}
+
+impl Statistics {
+    fn load(db: &Database) -> Result<Self, Error> {
+        let mut stats = Self::default();
+        let Some(table) = db.schema().tables.get("sqlite_stat1") else {
+            return Ok(stats);
+        };
+
+        let schema = &table.sql_schema;
+        let (tbl, idx, stat) = (
+            schema.field_index("tbl")?,
+            schema.field_index("idx")?,
+            schema.field_index("stat")?,
+        );
+        let mut scan = TableScan::new(db, table, RowidRange::ALL);
+        while let Some((_, row)) = scan.next_row()? {
+            // The stat column may end with flags such as `unordered`, which are skipped.
+            let counts = row[stat]
+                .to_string()
+                .split_whitespace()
+                .map_while(|count| count.parse::<f64>().ok())
+                .collect::<Vec<_>>();
+            if let Some(rows) = counts.first() {
+                stats
+                    .table_rows
+                    .insert(row[tbl].to_string().to_ascii_lowercase(), *rows);
+            }
+            if let Some(index) = row[idx].as_str() {
+                stats.index_rows.insert(index.to_ascii_lowercase(), counts);
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+/// The tree shapes and statistics read while planning, kept for the next queries as long as
+/// the pager generation they were read in lasts.
+#[derive(Default)]
+pub(crate) struct PlannerCache(RefCell<CachedEstimates>);
+
+#[derive(Default)]
+struct CachedEstimates {
+    generation: u64,
+    statistics: Option<Rc<Statistics>>,
+    /// Tree shapes by root page.
+    shapes: HashMap<usize, TreeShape>,
+}
+
+impl PlannerCache {
+    /// The estimates, dropped first if a commit or rollback may have changed the pages since.
+    fn current(db: &Database) -> std::cell::RefMut<'_, CachedEstimates> {
+        let mut cached = db.planner_cache.0.borrow_mut();
+        if cached.generation != db.pager.generation() {
+            *cached = CachedEstimates {
+                generation: db.pager.generation(),
+                ..CachedEstimates::default()
+            };
+        }
+        cached
+    }
+
+    fn statistics(db: &Database) -> Result<Rc<Statistics>, Error> {
+        if let Some(stats) = &Self::current(db).statistics {
+            return Ok(stats.clone());
+        }
+        let stats = Rc::new(Statistics::load(db)?);
+        Self::current(db).statistics = Some(stats.clone());
+        Ok(stats)
+    }
+
+    fn tree_shape(db: &Database, root_page: usize) -> Result<TreeShape, Error> {
+        if let Some(shape) = Self::current(db).shapes.get(&root_page) {
+            return Ok(*shape);
+        }
+        let shape = TreeShape::of(db, root_page)?;
+        Self::current(db).shapes.insert(root_page, shape);
+        Ok(shape)
+    }
+}
+
+/// An access path with its estimated cost in page reads and the number of rows it reads.
+struct Candidate<'a> {
+    path: AccessPath<'a>,
+    cost: f64,
+    rows: f64,
+    presorted: bool,
+}
+
+pub(crate) struct QueryPlanner;
+
+impl QueryPlanner {
+    /// Picks how the first table of the query is read, then how each joined table finds the
+    /// rows matching the tables before it, in nested loops.
+    pub(crate) fn plan<'a>(query: &Query, db: &'a Database) -> Result<QueryPlan<'a>, Error> {
+        let bindings = Binding::of(query, db)?;
+        if query.joins.is_empty() {
+            return Self::plan_table(query, bindings, db);
+        }
+
+        let base_query = Self::base_query(query, &bindings);
+        let joins = Self::plan_joins(query, &bindings, db);
+        let plan = Self::plan_table(&base_query, bindings, db)?;
+        Ok(QueryPlan {
+            base_filter: base_query.filter,
+            joins,
+            group_by: !query.group_by.is_empty(),
+            sorts: !query.order_by.is_empty(),
+            ..plan
+        })
+    }
+
+    /// Lists the access paths usable for a single-table query and picks the cheapest one.
+    fn plan_table<'a>(
+        query: &Query,
+        bindings: Vec<Binding<'a>>,
+        db: &'a Database,
+    ) -> Result<QueryPlan<'a>, Error> {
+        let table = match &bindings[0].source {
+            Source::Table(table) => *table,
+            // The rows of a derived table are only read in full.
+            Source::Derived { plan, .. } => {
+                let cost = plan.cost;
+                return Ok(QueryPlan {
+                    bindings,
+                    path: AccessPath::FullScan,
+                    base_filter: query.filter.clone(),
+                    joins: vec![],
+                    presorted: false,
+                    group_by: !query.group_by.is_empty(),
+                    sorts: !query.order_by.is_empty(),
+                    cost,
+                });
+            }
+        };
+        let schema = &table.sql_schema;
+        let stats = PlannerCache::statistics(db)?;
+
+        let table_shape = PlannerCache::tree_shape(db, table.root_page)?;
+        let rows = stats
+            .table_rows
+            .get(&table.table_name.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(table_shape.entries);
+        let rows_per_page = (rows / table_shape.pages).max(1.0);
+
+        let mut candidates = vec![Candidate {
+            path: AccessPath::FullScan,
+            cost: table_shape.pages,
+            rows,
+            presorted: false,
+        }];
+
+        if let Some(range) = RowidRange::from(query, schema) {
+            let matched = if range.start == range.end {
+                1.0
+            } else {
+                rows * Self::range_factor(range.start != i64::MIN, range.end != i64::MAX)
+            };
+            candidates.push(Candidate {
+                path: AccessPath::RowidRange(range),
+                cost: table_shape.depth + matched / rows_per_page,
+                rows: matched,
+                presorted: false,
+            });
+        }
+
+        let columns = Self::referenced_columns(query, schema);
+        for index in db.table_indices(&table.table_name) {
+            if !Self::indexes_rows(query, index) {
+                continue;
+            }
+            let index_shape = PlannerCache::tree_shape(db, index.root_page)?;
+            let entries_per_page = (rows / index_shape.pages).max(1.0);
+            let covering = columns.iter().all(|column| {
+                schema
+                    .find_field(column)
+                    .is_some_and(|i| schema.rowid_alias() == Some(i))
+                    || index
+                        .sql_schema
+                        .fields
+                        .iter()
+                        .any(|field| field.field.eq_ignore_ascii_case(column))
+            });
+            // Unless the index covers the query, every entry costs a descent of the table.
+            let lookup_cost = if covering { 0.0 } else { table_shape.depth };
+
+            if let Some(range) = IndexRange::from(query, index, schema) {
+                let matched = Self::index_range_rows(
+                    &range,
+                    rows,
+                    stats.index_rows.get(&index.index_name.to_ascii_lowercase()),
+                );
+                candidates.push(Candidate {
+                    path: AccessPath::IndexRange {
+                        index,
+                        range,
+                        covering,
+                    },
+                    cost: index_shape.depth + matched / entries_per_page + matched * lookup_cost,
+                    rows: matched,
+                    presorted: false,
+                });
+            }
+
+            let reverse = index_order_direction(index, schema, &query.order_by);
+            if reverse.is_some() || covering {
+                candidates.push(Candidate {
+                    path: AccessPath::IndexScan {
+                        index,
+                        reverse: reverse.unwrap_or(false),
+                        covering,
+                    },
+                    cost: index_shape.pages + rows * lookup_cost,
+                    rows,
+                    presorted: reverse.is_some(),
+                });
+            }
+        }
+
+        let aggregate = query.is_aggregate();
+        let plans = candidates.into_iter().map(|candidate| {
+            let sorts = !query.order_by.is_empty() && (aggregate || !candidate.presorted);
+            let mut cost = candidate.cost;
+            // Rows arriving in their final order stop the scan as soon as LIMIT is reached.
+            if !sorts
+                && !aggregate
+                && query.filter.is_none()
+                && let Some(limit) = query.limit
+            {
+                cost *= ((limit + query.offset) as f64 / candidate.rows).min(1.0);
+            }
+            if sorts {
+                cost += candidate.rows * candidate.rows.max(2.0).log2() * SORT_COMPARISON_COST;
+            }
+            debug!(
+                "{} costs {:.1}",
+                candidate.path.describe(&table.table_name),
+                cost
+            );
+
+            (candidate, sorts, cost)
+        });
+
+        // The first of equally cheap plans wins, so simpler paths are preferred.
+        let (candidate, sorts, cost) = plans
+            .reduce(|best, plan| if plan.2 < best.2 { plan } else { best })
+            .expect("Full scan is always a candidate");
+        Ok(QueryPlan {
+            bindings,
+            path: candidate.path,
+            base_filter: query.filter.clone(),
+            joins: vec![],
+            presorted: candidate.presorted,
+            group_by: !query.group_by.is_empty(),
+            sorts,
+            cost,
+        })
+    }
+
+    /// The single-table query reading the first table of a join: the WHERE terms involving only
+    /// that table, and the columns of it used anywhere, to tell whether an index covers them.
+    fn base_query(query: &Query, bindings: &[Binding]) -> Query {
+        let base = &bindings[0];
+        let filter = query
+            .conjuncts()
+            .into_iter()
+            .filter(|term| Binding::resolves_all(&bindings[..1], term))
+            .cloned()
+            .reduce(|lhs, rhs| Expr::Binary {
+                op: BinaryOp::And,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            });
+
+        let base_fields = &base.schema().fields;
+        let mut names = vec![];
+        let mut collect = |expr: &Expr| {
+            // Columns read by subqueries aren't known, so they may read any column.
+            let subquery = expr.has_subquery();
+            expr.walk(&mut |expr| {
+                if let Expr::Column { table, name } = expr
+                    && let Ok(Some(position)) = Binding::resolve(bindings, table.as_deref(), name)
+                    && let Some(field) = base_fields.get(position)
+                    && !names.contains(&field.name)
+                {
+                    names.push(field.name.clone());
+                }
+            });
+            for field in base_fields.iter().filter(|_| subquery) {
+                if !names.contains(&field.name) {
+                    names.push(field.name.clone());
+                }
+            }
+        };
+        for column in &query.columns {
+            match column {
+                ResultColumn::Star => base_fields.iter().for_each(|field| {
+                    collect(&Expr::Column {
+                        table: None,
+                        name: field.name.clone(),
+                    })
+                }),
+                ResultColumn::TableStar(table) if base.name.eq_ignore_ascii_case(table) => {
+                    base_fields.iter().for_each(|field| {
+                        collect(&Expr::Column {
+                            table: None,
+                            name: field.name.clone(),
+                        })
+                    })
+                }
+                ResultColumn::TableStar(_) => {}
+                ResultColumn::Expr { expr, .. } => collect(expr),
+            }
+        }
+        query
+            .filter
+            .iter()
+            .chain(query.joins.iter().filter_map(|join| join.on.as_ref()))
+            .chain(&query.group_by)
+            .chain(&query.having)
+            .chain(query.order_by.iter().map(|term| &term.expr))
+            .for_each(collect);
+
+        Query {
+            explain: false,
+            columns: names
+                .into_iter()
+                .map(|name| ResultColumn::Expr {
+                    expr: Expr::Column { table: None, name },
+                    alias: None,
+                })
+                .collect(),
+            from: query.from.clone(),
+            joins: vec![],
+            filter,
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: 0,
+        }
+    }
+
+    /// Picks how each joined table finds the rows matching the tables before it: through its
+    /// rowid or an index when a `column = key` term relates them, otherwise by a scan.
+    fn plan_joins<'a>(query: &Query, bindings: &[Binding], db: &'a Database) -> Vec<JoinPlan<'a>> {
+        let mut plans = vec![];
+        for (i, join) in query.joins.iter().enumerate() {
+            let binding = &bindings[i + 1];
+            let schema = binding.schema();
+
+            // ON terms restrict the joined table, and so do WHERE terms for inner joins. A LEFT
+            // JOIN keeps the rows without a match, which the WHERE clause may not.
+            let mut terms = join.on.iter().flat_map(Expr::conjuncts).collect::<Vec<_>>();
+            if join.kind == JoinKind::Inner {
+                terms.extend(query.conjuncts());
+            }
+
+            let mut keys = vec![];
+            for term in terms {
+                let Expr::Binary {
+                    op: BinaryOp::Eq,
+                    lhs,
+                    rhs,
+                } = term
+                else {
+                    continue;
+                };
+                // An index only finds every match when it orders text the way the term compares.
+                let Ok(collation) =
+                    comparison_collation(lhs, rhs, &BoundColumns(&bindings[..=i + 1]))
+                else {
+                    continue;
+                };
+                for (column, key) in [(lhs, rhs), (rhs, lhs)] {
+                    if let Expr::Column { table, name } = column.as_ref()
+                        && let Ok(Some(position)) =
+                            Binding::resolve(&bindings[..=i + 1], table.as_deref(), name)
+                        && position >= binding.offset
+                        && Binding::resolves_all(&bindings[..=i], key)
+                    {
+                        keys.push((position - binding.offset, key.as_ref(), collation.clone()));
+                    }
+                }
+            }
+
+            let rowid_key = keys.iter().find(|(column, _, _)| {
+                binding.table().is_some()
+                    && (Some(*column) == schema.rowid_alias() || *column == schema.fields.len())
+            });
+            // A partial index lacks the rows its WHERE clause leaves out, so joins never look one up.
+            let mut indices = match &binding.source {
+                Source::Table(table) => db.table_indices(&table.table_name),
+                Source::Derived { .. } => vec![],
+            };
+            indices.retain(|index| index.sql_schema.filter.is_none());
+            let index_key = indices.into_iter().find_map(|index| {
+                let first = &index.sql_schema.fields[0];
+                keys.iter()
+                    .find(|(column, _, collation)| {
+                        collation == first.collation()
+                            && schema
+                                .fields
+                                .get(*column)
+                                .is_some_and(|field| field.name.eq_ignore_ascii_case(&first.field))
+                    })
+                    .map(|(_, key, _)| (index, key))
+            });
+            let lookup = match (rowid_key, index_key) {
+                (Some((_, key, _)), _) => JoinLookup::Rowid((*key).clone()),
+                (None, Some((index, key))) => JoinLookup::Index {
+                    index,
+                    key: (*key).clone(),
+                },
+                (None, None) => JoinLookup::Scan,
+            };
+
+            plans.push(JoinPlan {
+                kind: join.kind,
+                on: join.on.clone(),
+                lookup,
+            });
+        }
+        plans
+    }
+
+    /// Whether an index has an entry for every row the query reads. A partial index only has
+    /// them for the rows its WHERE clause picks, so each of its terms must be one of the query.
+    fn indexes_rows(query: &Query, index: &Index) -> bool {
+        let Some(filter) = &index.sql_schema.filter else {
+            return true;
+        };
+        let terms = query.conjuncts();
+        filter.conjuncts().iter().all(|term| terms.contains(term))
+    }
+
+    /// Rows of the table an index range is expected to match.
+    fn index_range_rows(range: &IndexRange, rows: f64, stats: Option<&Vec<f64>>) -> f64 {
+        let prefix_rows = match range.prefix.len() {
+            0 => rows,
+            // Without statistics each further column is assumed to halve the matching rows.
+            len => stats
+                .and_then(|stats| stats.get(len).copied())
+                .unwrap_or(DEFAULT_ROWS_PER_KEY / 2f64.powi(len as i32 - 1))
+                .min(rows),
+        };
+
+        (prefix_rows * Self::range_factor(range.lower.is_some(), range.upper.is_some())).max(1.0)
+    }
+
+    fn range_factor(lower: bool, upper: bool) -> f64 {
+        match (lower, upper) {
+            (true, true) => TWO_SIDED_RANGE_FACTOR,
+            (true, false) | (false, true) => ONE_SIDED_RANGE_FACTOR,
+            (false, false) => 1.0,
+        }
+    }
+
+    /// The table columns the query reads, to tell whether an index covers it.
+    fn referenced_columns(query: &Query, schema: &TableSchema) -> HashSet<String> {
+        let mut columns = HashSet::new();
+        let mut collect = |expr: &Expr| {
+            // Columns read by subqueries aren't known, so they may read any column.
+            if expr.has_subquery() {
+                columns.extend(schema.fields.iter().map(|field| field.name.clone()));
+            }
+            expr.walk(&mut |expr| {
+                if let Expr::Column { name, .. } = expr
+                    && let Some(i) = schema.find_field(name)
+                {
+                    columns.insert(schema.fields[i].name.clone());
+                }
+            })
+        };
+
+        for column in &query.columns {
+            match column {
+                ResultColumn::Star | ResultColumn::TableStar(_) => {
+                    schema.fields.iter().for_each(|field| {
+                        collect(&Expr::Column {
+                            table: None,
+                            name: field.name.clone(),
+                        })
+                    })
+                }
+                ResultColumn::Expr { expr, .. } => collect(expr),
+            }
+        }
+        query
+            .filter
+            .iter()
+            .chain(&query.group_by)
+            .chain(&query.having)
+            .chain(query.order_by.iter().map(|term| &term.expr))
+            .for_each(collect);
+
+        columns
+    }
+}
+
+/// The part of an index the WHERE clause restricts a query to: equal values for a prefix of
+/// the index columns, optionally followed by a range on the next column.
+#[derive(Clone)]
+pub(crate) struct IndexRange {
+    pub(crate) prefix: Vec<Record>,
+    pub(crate) lower: Option<Bound>,
+    pub(crate) upper: Option<Bound>,
+}
+
+impl IndexRange {
+    /// The range of `index` usable for `query`, if the query restricts at least the first index
+    /// column. Values are converted to the affinity of their column, as the comparisons in the
+    /// WHERE clause would.
+    pub(crate) fn from(query: &Query, index: &Index, schema: &TableSchema) -> Option<Self> {
+        let affinity = |name: &str| {
+            schema
+                .find_field(name)
+                .map_or(Affinity::Blob, |i| schema.fields[i].affinity())
+        };
+        let with_affinity = |bound: Option<Bound>, name: &str| {
+            bound.map(|bound| Bound {
+                value: affinity(name).apply(bound.value),
+                inclusive: bound.inclusive,
+            })
+        };
+
+        // Terms on a column compare text by the collation of the column, so only the index
+        // columns ordered the same way are sought.
+        let fields = index
+            .sql_schema
+            .fields
+            .iter()
+            .take_while(|field| {
+                schema
+                    .find_field(&field.field)
+                    .is_some_and(|i| schema.fields[i].collation == *field.collation())
+            })
+            .collect::<Vec<_>>();
+        let mut prefix = vec![];
+        for field in &fields {
+            match query.equality_on(&field.field) {
+                Some(value) => prefix.push(affinity(&field.field).apply(value.clone())),
+                None => break,
+            }
+        }
+
+        let (lower, upper) = match fields.get(prefix.len()) {
+            Some(field) => {
+                let (mut lower, mut upper) = query.bounds_on(&field.field);
+                if lower.is_none() && upper.is_none() && affinity(&field.field) == Affinity::Text {
+                    (lower, upper) = query.pattern_bounds_on(&field.field);
+                }
+                (
+                    with_affinity(lower, &field.field),
+                    with_affinity(upper, &field.field),
+                )
+            }
+            None => (None, None),
+        };
+        if prefix.is_empty() && lower.is_none() && upper.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            prefix,
+            lower,
+            upper,
+        })
+    }
+
+    /// Where an index entry lies relative to the range, in index order: `Less` before it,
+    /// `Equal` inside it and `Greater` after it.
+    pub(crate) fn position(&self, index_schema: &IndexSchema, values: &[Record]) -> Ordering {
+        let in_index_order = |ordering: Ordering, ascending: bool| {
+            if ascending {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        };
+
+        for ((field, expected), value) in index_schema.fields.iter().zip(&self.prefix).zip(values) {
+            let ordering = field.collation().compare(value, expected);
+            if ordering.is_ne() {
+                return in_index_order(ordering, field.ascending);
+            }
+        }
+
+        let Some(field) = index_schema.fields.get(self.prefix.len()) else {
+            return Ordering::Equal;
+        };
+        if self.lower.is_none() && self.upper.is_none() {
+            return Ordering::Equal;
+        }
+
+        // NULLs sort before every value and never fall inside a range.
+        let value = &values[self.prefix.len()];
+        let collation = field.collation();
+        let below = value.is_null()
+            || self.lower.as_ref().is_some_and(|lower| {
+                match collation.compare(value, &lower.value) {
+                    Ordering::Less => true,
+                    Ordering::Equal => !lower.inclusive,
+                    Ordering::Greater => false,
+                }
+            });
+        let above =
+            self.upper
+                .as_ref()
+                .is_some_and(|upper| match collation.compare(value, &upper.value) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => !upper.inclusive,
+                    Ordering::Less => false,
+                });
+
+        match (below, above) {
+            (true, _) => in_index_order(Ordering::Less, field.ascending),
+            (false, true) => in_index_order(Ordering::Greater, field.ascending),
+            (false, false) => Ordering::Equal,
+        }
+    }
+}
+
+/// Inclusive range of rowids the WHERE clause restricts a query to through the rowid alias.
+#[derive(Clone, Copy)]
+pub(crate) struct RowidRange {
+    pub(crate) start: i64,
+    pub(crate) end: i64,
+}
+
+impl RowidRange {
+    pub(crate) const ALL: Self = Self {
+        start: i64::MIN,
+        end: i64::MAX,
+    };
+
+    pub(crate) fn from(query: &Query, schema: &TableSchema) -> Option<Self> {
+        let names = schema
+            .rowid_alias()
+            .map(|i| schema.fields[i].name.as_str())
+            .into_iter()
+            .chain(ROWID_NAMES)
+            .filter(|name| schema.is_rowid_name(name));
+        let (lower, upper) = names
+            .map(|name| match query.equality_on(name) {
+                Some(value) => {
+                    let bound = Bound {
+                        value: value.clone(),
+                        inclusive: true,
+                    };
+                    (Some(bound.clone()), Some(bound))
+                }
+                None => query.bounds_on(name),
+            })
+            .find(|(lower, upper)| lower.is_some() || upper.is_some())?;
+
+        // Bounds that aren't numbers can't be turned into rowids, the filter still checks them.
+        let start = lower.and_then(|bound| Self::rowid_bound(bound, true));
+        let end = upper.and_then(|bound| Self::rowid_bound(bound, false));
+        if start.is_none() && end.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            start: start.unwrap_or(i64::MIN),
+            end: end.unwrap_or(i64::MAX),
+        })
+    }
+
+    /// The first (for a lower bound) or last rowid inside a bound.
+    fn rowid_bound(bound: Bound, lower: bool) -> Option<i64> {
+        let rowid = match Affinity::Integer.apply(bound.value) {
+            Record::Real(value) => match (lower, bound.inclusive) {
+                (true, true) => value.ceil() as i128,
+                (true, false) => value.floor() as i128 + 1,
+                (false, true) => value.floor() as i128,
+                (false, false) => value.ceil() as i128 - 1,
+            },
+            value => {
+                let value = value.as_int()? as i128;
+                match (lower, bound.inclusive) {
+                    (_, true) => value,
+                    (true, false) => value + 1,
+                    (false, false) => value - 1,
+                }
+            }
+        };
+
+        Some(rowid.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+}
+
+/// Whether walking the index yields rows in the ORDER BY order: `Some(false)` for a forward
+/// walk, `Some(true)` for a backward walk, `None` if the index does not match.
+fn index_order_direction(
+    index: &Index,
+    schema: &TableSchema,
+    order_by: &[OrderingTerm],
+) -> Option<bool> {
+    if order_by.is_empty() || order_by.len() > index.sql_schema.fields.len() {
+        return None;
+    }
+
+    let mut reverse = None;
+    for (term, field) in order_by.iter().zip(&index.sql_schema.fields) {
+        // Terms sort text by their explicit collation, else by that of their column.
+        let (expr, collation) = match &term.expr {
+            Expr::Collate { expr, collation } => (expr.as_ref(), Some(collation.clone())),
+            expr => (expr, None),
+        };
+        let Expr::Column { name, .. } = expr else {
+            return None;
+        };
+        let collation = collation.or_else(|| {
+            schema
+                .find_field(name)
+                .map(|i| schema.fields[i].collation.clone())
+        });
+        if !name.eq_ignore_ascii_case(&field.field) || collation.as_ref() != Some(field.collation())
+        {
+            return None;
+        }
+
+        // NULLs sort lowest, so a walk yielding ascending values yields them first and one
+        // yielding descending values last, whichever way the index stores them.
+        if term.nulls_first() == term.descending {
+            return None;
+        }
+        let term_reverse = term.descending == field.ascending;
+        if *reverse.get_or_insert(term_reverse) != term_reverse {
+            return None;
+        }
+    }
+
+    reverse
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        ast::Statement,
+        common::{Index, sqlite3},
+        database::Database,
+        pager::Pager,
+        parser::Parser,
+        planner::{IndexRange, PlannerCache, QueryPlanner, index_order_direction},
+        query::{Bound, Query},
+        query_executor::QueryExecutor,
+        record::Record,
+        schema::{IndexSchema, TableSchema},
+        write_executor::WriteExecutor,
+    };
+
+    #[test]
+    fn test_index_order_direction() {
+        let schema = TableSchema::from("CREATE TABLE t(x, y COLLATE NOCASE)").unwrap();
+        let direction = |index_sql: &str, order_by: &str| {
+            let mut sql_schema = IndexSchema::from(index_sql).unwrap();
+            sql_schema.inherit_collations(&schema);
+            let index = Index::new(String::from("t"), String::from("t_x"), 2, sql_schema);
+            let select = Parser::parse_select(&format!("SELECT x FROM t ORDER BY {}", order_by));
+            index_order_direction(&index, &schema, &select.unwrap().order_by)
+        };
+
+        let ascending = "CREATE INDEX t_x ON t(x)";
+        assert_eq!(Some(false), direction(ascending, "x"));
+        assert_eq!(Some(false), direction(ascending, "x ASC NULLS FIRST"));
+        assert_eq!(None, direction(ascending, "x ASC NULLS LAST"));
+        assert_eq!(Some(true), direction(ascending, "x DESC"));
+        assert_eq!(Some(true), direction(ascending, "x DESC NULLS LAST"));
+        assert_eq!(None, direction(ascending, "x DESC NULLS FIRST"));
+
+        let descending = "CREATE INDEX t_x ON t(x DESC)";
+        assert_eq!(Some(true), direction(descending, "x"));
+        assert_eq!(Some(true), direction(descending, "x ASC NULLS FIRST"));
+        assert_eq!(None, direction(descending, "x ASC NULLS LAST"));
+        assert_eq!(Some(false), direction(descending, "x DESC"));
+        assert_eq!(Some(false), direction(descending, "x DESC NULLS LAST"));
+        assert_eq!(None, direction(descending, "x DESC NULLS FIRST"));
+
+        // Text is only ordered as the term sorts it when the collations agree.
+        assert_eq!(Some(false), direction("CREATE INDEX t_y ON t(y)", "y"));
+        assert_eq!(
+            None,
+            direction("CREATE INDEX t_y ON t(y)", "y COLLATE BINARY")
+        );
+        let nocase = "CREATE INDEX t_x ON t(x COLLATE NOCASE)";
+        assert_eq!(None, direction(nocase, "x"));
+        assert_eq!(Some(false), direction(nocase, "x COLLATE nocase"));
+    }
+
+    #[test]
+    fn test_index_range_rows() {
+        let bound = |value| {
+            Some(Bound {
+                value: Record::I64(value),
+                inclusive: true,
+            })
+        };
+        let equality = IndexRange {
+            prefix: vec![Record::String("fr".to_string())],
+            lower: None,
+            upper: None,
+        };
+        let range = IndexRange {
+            prefix: vec![],
+            lower: bound(10),
+            upper: bound(20),
+        };
+
+        let stats = vec![5000.0, 834.0];
+        assert_eq!(
+            834.0,
+            QueryPlanner::index_range_rows(&equality, 5000.0, Some(&stats))
+        );
+        assert_eq!(
+            10.0,
+            QueryPlanner::index_range_rows(&equality, 5000.0, None)
+        );
+        assert_eq!(5.0, QueryPlanner::index_range_rows(&equality, 5.0, None));
+        assert_eq!(64.0, QueryPlanner::index_range_rows(&range, 4096.0, None));
+    }
+
+    #[test]
+    fn test_planner_cache_lasts_until_commit() {
+        let path = std::env::temp_dir().join(format!("planner-test-{}.db", std::process::id()));
+        fs::copy("sample.db", &path).unwrap();
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let root_page = db.schema().tables["apples"].root_page;
+        let entries = |db: &Database| PlannerCache::tree_shape(db, root_page).unwrap().entries;
+
+        assert_eq!(4.0, entries(&db));
+        assert!(db.planner_cache.0.borrow().shapes.contains_key(&root_page));
+
+        let Statement::Insert(insert) =
+            Parser::parse_statement("INSERT INTO apples (name, color) VALUES ('Gala', 'Red')")
+                .unwrap()
+        else {
+            unreachable!()
+        };
+        WriteExecutor::execute_insert(&insert, &db).unwrap();
+        assert_eq!(5.0, entries(&db));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_plans_with_best_of_several_indexes() {
+        let path = std::env::temp_dir().join(format!("indexes-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, a, b, pad); \
+                      CREATE INDEX t_a ON t(a); CREATE INDEX t_b ON t(b); \
+                      WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000) \
+                      INSERT INTO t SELECT i, i % 2, i, printf('%.*c', 100, 'p') FROM n; ANALYZE;";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let names = db
+            .table_indices("t")
+            .iter()
+            .map(|index| index.index_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["t_a", "t_b"], names);
+
+        for sql in [
+            "SELECT pad FROM t WHERE a = 1 AND b = 500",
+            "SELECT pad FROM t WHERE b = 500 AND a = 1",
+        ] {
+            let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+            let plan = QueryExecutor::explain_query(&query, &db).unwrap();
+            assert_eq!(
+                Record::String(String::from("`--SEARCH t USING INDEX t_b (b=?)")),
+                plan[1][0]
+            );
+            let sqlite_plan = sqlite3(&path, &format!("EXPLAIN QUERY PLAN {}", sql)).unwrap();
+            assert_eq!(
+                Some(plan[1][0].to_string().as_str()),
+                sqlite_plan.lines().nth(1)
+            );
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,95 +1,298 @@
-use log::debug;
-use regex::Regex;
+use crate::{
+    aggregate::AggregateFunction,
+    ast::{
+        BinaryOp, Expr, Join, LikeOp, OrderingTerm, ResultColumn, SelectStmt, Statement, TableRef,
+    },
+    common::Error,
+    evaluator::ConstantContext,
+    record::Record,
+};
 
-use crate::record::Record;
+/// One end of the range a column is restricted to by the WHERE clause.
+#[derive(Debug, Clone)]
+pub(crate) struct Bound {
+    pub(crate) value: Record,
+    pub(crate) inclusive: bool,
+}
 
 #[derive(Debug)]
-pub(crate) enum QueryField {
-    Count,
-    List(Vec<String>),
+pub(crate) struct Query {
+    /// Describe the query plan instead of running the query.
+    pub(crate) explain: bool,
+    pub(crate) columns: Vec<ResultColumn>,
+    pub(crate) from: TableRef,
+    pub(crate) joins: Vec<Join>,
+    pub(crate) filter: Option<Expr>,
+    pub(crate) group_by: Vec<Expr>,
+    pub(crate) having: Option<Expr>,
+    pub(crate) order_by: Vec<OrderingTerm>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) offset: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum QueryConditionOp {
-    Eq,
-}
+impl Query {
+    #[cfg(test)]
+    pub(crate) fn parse(raw: &str) -> Result<Self, Error> {
+        Self::from_statement(crate::parser::Parser::parse_statement(raw)?)
+    }
 
-impl QueryConditionOp {
-    pub(crate) fn eval(&self, lhs: &Record, rhs: &Record) -> bool {
-        // debug!("LHS={:?} RHS={:?}", &lhs, &rhs);
-        match self {
-            Self::Eq => {
-                match (lhs.as_int(), rhs.as_int()) {
-                    (Some(a), Some(b)) => return a == b,
-                    _ => {}
-                }
-                match (lhs.as_str(), rhs.as_str()) {
-                    (Some(a), Some(b)) => return a == b,
-                    _ => {}
-                }
-                false
-            }
+    pub(crate) fn from_statement(stmt: Statement) -> Result<Self, Error> {
+        match stmt {
+            Statement::Select(stmt) => Self::from_select(stmt),
+            Statement::ExplainQueryPlan(stmt) => Ok(Self {
+                explain: true,
+                ..Self::from_select(stmt)?
+            }),
+            Statement::Insert(_) => Err("not a query: INSERT".into()),
+            Statement::Update(_) => Err("not a query: UPDATE".into()),
+            Statement::Delete(_) => Err("not a query: DELETE".into()),
+            Statement::Begin => Err("not a query: BEGIN".into()),
+            Statement::Commit => Err("not a query: COMMIT".into()),
+            Statement::Rollback => Err("not a query: ROLLBACK".into()),
         }
     }
-}
 
-#[derive(Debug)]
-pub(crate) struct QueryCondition {
-    pub(crate) lhs: String,
-    pub(crate) op: QueryConditionOp,
-    pub(crate) rhs: Record,
-}
+    pub(crate) fn from_select(stmt: SelectStmt) -> Result<Self, Error> {
+        if let Some(filter) = &stmt.where_clause
+            && let Some(call) = AggregateFunction::collect_calls([filter]).first()
+        {
+            let Expr::Function { name, .. } = call else {
+                unreachable!()
+            };
+            return Err(format!("misuse of aggregate: {}()", name).into());
+        }
+        Self::check_subqueries(&stmt)?;
 
-#[derive(Debug)]
-pub(crate) struct Query {
-    pub(crate) fields: QueryField,
-    pub(crate) source: String,
-    pub(crate) conditions: Vec<QueryCondition>,
-}
+        // A negative LIMIT means no limit, a negative OFFSET is ignored.
+        let limit = stmt
+            .limit
+            .map(|limit| Self::eval_integer(&limit, "LIMIT"))
+            .transpose()?
+            .and_then(|limit| usize::try_from(limit).ok());
+        let offset = stmt
+            .offset
+            .map(|offset| Self::eval_integer(&offset, "OFFSET"))
+            .transpose()?
+            .map_or(0, |offset| offset.max(0) as usize);
 
-impl Query {
-    pub(crate) fn parse(raw: &str) -> Self {
-        let query_re =
-            Regex::new(r#"(?i)SELECT\s+(.*)\s+FROM\s+(\w+)\s*(\s+WHERE\s+(.*))?$"#).unwrap();
-        let caps = query_re.captures(raw).unwrap().iter().collect::<Vec<_>>();
-
-        let fields_raw = caps[1].unwrap().as_str();
-        let fields = if fields_raw.to_lowercase().starts_with("count(") {
-            QueryField::Count
-        } else {
-            let field_parts = fields_raw
-                .split(',')
-                .map(|elem| elem.trim().to_string())
-                .collect::<Vec<_>>();
-            QueryField::List(field_parts)
+        let query = Self {
+            explain: false,
+            columns: stmt.columns,
+            from: stmt.from,
+            joins: stmt.joins,
+            filter: stmt.where_clause,
+            group_by: stmt.group_by,
+            having: stmt.having,
+            order_by: stmt.order_by,
+            limit,
+            offset,
         };
 
-        let source = caps[2].unwrap().as_str().trim().to_string();
+        if query.having.is_some() && !query.is_aggregate() {
+            return Err("a GROUP BY clause is required before HAVING".into());
+        }
+
+        Ok(query)
+    }
+
+    /// Whether the query folds its rows into groups, either through GROUP BY or by calling an
+    /// aggregate function in the result columns, HAVING or ORDER BY.
+    pub(crate) fn is_aggregate(&self) -> bool {
+        let column_exprs = self.columns.iter().filter_map(|column| match column {
+            ResultColumn::Expr { expr, .. } => Some(expr),
+            ResultColumn::Star | ResultColumn::TableStar(_) => None,
+        });
+
+        !self.group_by.is_empty()
+            || !AggregateFunction::collect_calls(
+                column_exprs
+                    .chain(self.having.as_ref())
+                    .chain(self.order_by.iter().map(|term| &term.expr)),
+            )
+            .is_empty()
+    }
+
+    /// Scalar and `IN` subqueries must return a single column. Subqueries selecting `*` are only
+    /// checked once they run.
+    fn check_subqueries(stmt: &SelectStmt) -> Result<(), Error> {
+        let column_exprs = stmt.columns.iter().filter_map(|column| match column {
+            ResultColumn::Expr { expr, .. } => Some(expr),
+            ResultColumn::Star | ResultColumn::TableStar(_) => None,
+        });
+        let exprs = column_exprs
+            .chain(stmt.joins.iter().filter_map(|join| join.on.as_ref()))
+            .chain(&stmt.where_clause)
+            .chain(&stmt.group_by)
+            .chain(&stmt.having)
+            .chain(stmt.order_by.iter().map(|term| &term.expr));
 
-        let conditions = match caps[4] {
-            Some(m) => m
-                .as_str()
-                .split("AND")
-                .map(|elem| Self::parse_condition(elem))
-                .collect::<Vec<_>>(),
-            None => vec![],
+        let mut result = Ok(());
+        for expr in exprs {
+            expr.walk(&mut |expr| {
+                let (Expr::Subquery(select) | Expr::InSelect { select, .. }) = expr else {
+                    return;
+                };
+                let expr_columns = select
+                    .columns
+                    .iter()
+                    .all(|column| matches!(column, ResultColumn::Expr { .. }));
+                if expr_columns && select.columns.len() != 1 && result.is_ok() {
+                    result = Err(format!(
+                        "sub-select returns {} columns - expected 1",
+                        select.columns.len()
+                    )
+                    .into());
+                }
+            });
+        }
+        result
+    }
+
+    fn eval_integer(expr: &Expr, clause: &str) -> Result<i64, Error> {
+        expr.eval(&ConstantContext)?
+            .to_numeric()
+            .as_int()
+            .ok_or_else(|| format!("datatype mismatch in {}", clause).into())
+    }
+
+    /// The top-level `AND`-ed terms of the WHERE clause.
+    pub(crate) fn conjuncts(&self) -> Vec<&Expr> {
+        self.filter.iter().flat_map(Expr::conjuncts).collect()
+    }
+
+    /// The literal a column is required to be equal to by one of the WHERE clause terms.
+    pub(crate) fn equality_on(&self, column: &str) -> Option<&Record> {
+        self.conjuncts().into_iter().find_map(|expr| match expr {
+            Expr::Binary {
+                op: BinaryOp::Eq,
+                lhs,
+                rhs,
+            } => match (lhs.as_ref(), rhs.as_ref()) {
+                (Expr::Column { name, .. }, Expr::Literal(value))
+                | (Expr::Literal(value), Expr::Column { name, .. })
+                    if name.eq_ignore_ascii_case(column) && !value.is_null() =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    /// The lower and upper bounds a column is restricted to by the `<`, `<=`, `>`, `>=` and
+    /// `BETWEEN` terms of the WHERE clause. The first bound found on each side is used, the
+    /// remaining terms are still checked against every row.
+    pub(crate) fn bounds_on(&self, column: &str) -> (Option<Bound>, Option<Bound>) {
+        let (mut lower, mut upper) = (None, None);
+        let is_column = |expr: &Expr| matches!(expr, Expr::Column { name, .. } if name.eq_ignore_ascii_case(column));
+        let bound = |value: &Record, inclusive| {
+            (!value.is_null()).then(|| Bound {
+                value: value.clone(),
+                inclusive,
+            })
         };
 
-        Self {
-            fields,
-            source,
-            conditions,
+        for expr in self.conjuncts() {
+            match expr {
+                Expr::Binary { op, lhs, rhs } => {
+                    // Normalize to `column op value`.
+                    let (op, value) = match (lhs.as_ref(), rhs.as_ref()) {
+                        (lhs, Expr::Literal(value)) if is_column(lhs) => (*op, value),
+                        (Expr::Literal(value), rhs) if is_column(rhs) => match op {
+                            BinaryOp::Lt => (BinaryOp::Gt, value),
+                            BinaryOp::LtEq => (BinaryOp::GtEq, value),
+                            BinaryOp::Gt => (BinaryOp::Lt, value),
+                            BinaryOp::GtEq => (BinaryOp::LtEq, value),
+                            _ => continue,
+                        },
+                        _ => continue,
+                    };
+                    match op {
+                        BinaryOp::Gt if lower.is_none() => lower = bound(value, false),
+                        BinaryOp::GtEq if lower.is_none() => lower = bound(value, true),
+                        BinaryOp::Lt if upper.is_none() => upper = bound(value, false),
+                        BinaryOp::LtEq if upper.is_none() => upper = bound(value, true),
+                        _ => {}
+                    }
+                }
+                Expr::Between {
+                    expr,
+                    low,
+                    high,
+                    negated: false,
+                } if is_column(expr) => {
+                    if let Expr::Literal(value) = low.as_ref()
+                        && lower.is_none()
+                    {
+                        lower = bound(value, true);
+                    }
+                    if let Expr::Literal(value) = high.as_ref()
+                        && upper.is_none()
+                    {
+                        upper = bound(value, true);
+                    }
+                }
+                _ => {}
+            }
         }
+
+        (lower, upper)
     }
 
-    fn parse_condition(raw: &str) -> QueryCondition {
-        let parts = raw.split('=').collect::<Vec<_>>();
-        assert_eq!(2, parts.len());
-        let lhs = parts[0].trim().to_string();
-        let op = QueryConditionOp::Eq;
-        let rhs = Record::parse(parts[1].trim());
+    /// The bounds implied by a `LIKE 'prefix%'` or `GLOB 'prefix*'` term on a column. LIKE
+    /// ignores ASCII case, so its bounds span every case variant of the prefix. The bounds only
+    /// hold for columns storing text, as numbers sort before any text.
+    pub(crate) fn pattern_bounds_on(&self, column: &str) -> (Option<Bound>, Option<Bound>) {
+        let pattern = self.conjuncts().into_iter().find_map(|expr| match expr {
+            Expr::Like {
+                op,
+                expr,
+                pattern,
+                escape: None,
+                negated: false,
+            } => match (expr.as_ref(), pattern.as_ref()) {
+                (Expr::Column { name, .. }, Expr::Literal(Record::String(pattern)))
+                    if name.eq_ignore_ascii_case(column) =>
+                {
+                    Some((*op, pattern))
+                }
+                _ => None,
+            },
+            _ => None,
+        });
+        let Some((op, pattern)) = pattern else {
+            return (None, None);
+        };
+
+        let wildcards: &[char] = match op {
+            LikeOp::Like => &['%', '_'],
+            LikeOp::Glob => &['*', '?', '['],
+        };
+        let prefix = &pattern[..pattern.find(wildcards).unwrap_or(pattern.len())];
+        if prefix.is_empty() {
+            return (None, None);
+        }
+
+        let (low, high) = match op {
+            LikeOp::Like => (prefix.to_ascii_uppercase(), prefix.to_ascii_lowercase()),
+            LikeOp::Glob => (prefix.to_string(), prefix.to_string()),
+        };
+        // Every string starting with `high` sorts before `high` with its last character bumped.
+        let mut chars = high.chars();
+        let upper = chars
+            .next_back()
+            .and_then(|last| char::from_u32(last as u32 + 1))
+            .map(|last| Bound {
+                value: Record::String(format!("{}{}", chars.as_str(), last)),
+                inclusive: false,
+            });
 
-        QueryCondition { lhs, op, rhs }
+        let lower = Bound {
+            value: Record::String(low),
+            inclusive: true,
+        };
+        (Some(lower), upper)
     }
 }
 
@@ -99,12 +302,76 @@ mod test {
 
     #[test]
     fn test_query_parse() {
-        dbg!(Query::parse("SELECT COUNT(*) FROM apples"));
-        dbg!(Query::parse(
-            "SELECT COUNT(*) FROM apples WHERE name = 'mariogold'"
-        ));
-        dbg!(Query::parse(
-            "SELECT name, date FROM apples WHERE name = 'mariogold' AND age = 123"
-        ));
+        dbg!(Query::parse("SELECT COUNT(*) FROM apples").unwrap());
+        dbg!(Query::parse("SELECT COUNT(*) FROM apples WHERE name = 'mariogold'").unwrap());
+        dbg!(
+            Query::parse("SELECT name, date FROM apples WHERE name = 'mariogold' AND age = 123")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_query_parse_conditions() {
+        let query = Query::parse(
+            "select brand from cars where brand = 'a=b' and \"band AND\" = 1 AND 'x' = model",
+        )
+        .unwrap();
+        assert_eq!("cars", query.from.name);
+        assert_eq!(3, query.conjuncts().len());
+        assert_eq!(
+            Some("x"),
+            query.equality_on("model").and_then(|value| value.as_str())
+        );
+        assert!(query.equality_on("year").is_none());
+    }
+
+    #[test]
+    fn test_query_bounds() {
+        let query = Query::parse(
+            "select * from cars where 10 < year and year <= 20 and price between 1 and 5",
+        )
+        .unwrap();
+
+        let (lower, upper) = query.bounds_on("year");
+        let (lower, upper) = (lower.unwrap(), upper.unwrap());
+        assert_eq!(Some(10), lower.value.as_int());
+        assert!(!lower.inclusive);
+        assert_eq!(Some(20), upper.value.as_int());
+        assert!(upper.inclusive);
+
+        let (lower, upper) = query.bounds_on("price");
+        assert!(lower.is_some_and(|bound| bound.inclusive) && upper.is_some());
+        assert!(matches!(query.bounds_on("brand"), (None, None)));
+
+        let query = Query::parse("select * from cars where brand like 'Fo_d%'").unwrap();
+        let (lower, upper) = query.pattern_bounds_on("brand");
+        assert_eq!(Some("FO"), lower.unwrap().value.as_str());
+        assert_eq!(Some("fp"), upper.unwrap().value.as_str());
+
+        let query = Query::parse("select * from cars where brand glob 'Ford*'").unwrap();
+        let (lower, upper) = query.pattern_bounds_on("brand");
+        assert_eq!(Some("Ford"), lower.unwrap().value.as_str());
+        assert_eq!(Some("Fore"), upper.unwrap().value.as_str());
+    }
+
+    #[test]
+    fn test_query_parse_aggregates() {
+        assert!(
+            Query::parse("SELECT COUNT(*) FROM apples")
+                .unwrap()
+                .is_aggregate()
+        );
+        assert!(
+            Query::parse("SELECT color FROM apples GROUP BY color")
+                .unwrap()
+                .is_aggregate()
+        );
+        assert!(
+            !Query::parse("SELECT max(a, b) FROM apples")
+                .unwrap()
+                .is_aggregate()
+        );
+        assert!(Query::parse("SELECT color FROM apples HAVING color = 'red'").is_err());
+        assert!(Query::parse("SELECT color FROM apples WHERE count(*) > 1").is_err());
     }
 }
@@ -1,286 +1,1403 @@
-use std::collections::{HashMap, VecDeque};
-
-use log::debug;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    rc::Rc,
+    vec,
+};
 
 use crate::{
-    btree_page_header::BTreePageHeader,
-    cell::{
-        IndexBTreeInteriorCell, IndexBTreeLeafCell, TableBTreeInteriorCell, TableBTreeLeafCell,
-    },
-    common::{BTreePageType, Incrementer, Index},
+    aggregate::{AggregateFunction, AggregateRow, Aggregator},
+    ast::{Expr, OrderingTerm, ResultColumn},
+    ast::{JoinKind, SelectStmt},
+    btree_cursor::BTreeCursor,
+    common::{Error, Index, Table},
     database::Database,
-    query::{Query, QueryConditionOp, QueryField},
-    reader::Reader,
+    evaluator::RowContext,
+    planner::{
+        AccessPath, Binding, IndexRange, JoinLookup, QueryPlan, QueryPlanner, RowidRange, Source,
+    },
+    query::Query,
     record::Record,
+    schema::{Affinity, Collation, TableFieldKind, TableSchema},
 };
 
 pub(crate) struct QueryExecutor;
 
 impl QueryExecutor {
-    pub(crate) fn execute_query(query: &Query, db: &Database, reader: &Reader<'_, u8>) {
-        if query.conditions.len() == 1 {
-            if let Some(index) = db.indices.get(&query.source) {
-                // Assure we query by the index field.
-                if index.sql_schema.fields[0].field == query.conditions[0].lhs {
-                    Self::index_search(query, db, reader, index);
-                    return;
-                }
-            }
+    /// Starts a query, whose rows are then read one at a time as they are pulled.
+    pub(crate) fn execute_query<'a>(
+        query: &Query,
+        db: &'a Database,
+    ) -> Result<QueryRows<'a>, Error> {
+        let plan = QueryPlanner::plan(query, db)?;
+        let scope = Scope::new(db, None);
+        let output = QueryOutput::new(query, &plan, &scope)?;
+        Ok(QueryRows {
+            plan,
+            scope,
+            output,
+            failed: false,
+        })
+    }
+
+    /// Describes the plan of an `EXPLAIN QUERY PLAN` query, one line per row.
+    pub(crate) fn explain_query(query: &Query, db: &Database) -> Result<Vec<Vec<Record>>, Error> {
+        let plan = QueryPlanner::plan(query, db)?;
+        Ok(plan
+            .to_string()
+            .lines()
+            .map(|line| vec![Record::String(line.to_string())])
+            .collect())
+    }
+
+    /// Runs a query for its rows alone.
+    pub(crate) fn collect_rows(query: &Query, db: &Database) -> Result<Vec<Vec<Record>>, Error> {
+        let plan = QueryPlanner::plan(query, db)?;
+        Self::run(query, &plan, &Scope::new(db, None))
+    }
+
+    /// Runs a planned query to the end, collecting its rows.
+    fn run<'a>(
+        query: &Query,
+        plan: &QueryPlan<'a>,
+        scope: &Scope<'a>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        let mut output = QueryOutput::new(query, plan, scope)?;
+        let mut rows = vec![];
+        while let Some(row) = output.next_row(plan, scope)? {
+            rows.push(row);
         }
+        Ok(rows)
+    }
 
-        Self::full_table_scan(query, db, reader);
+    /// Looks up a row by rowid, with its rowid alias column filled in.
+    fn find_table_row(
+        db: &Database,
+        table: &Table,
+        rowid: i64,
+    ) -> Result<Option<Vec<Record>>, Error> {
+        let sql_schema = &table.sql_schema;
+        let Some(mut row) = Self::find_row(db, table.root_page, rowid, sql_schema)? else {
+            return Ok(None);
+        };
+        sql_schema.apply_rowid(rowid, &mut row);
+        Ok(Some(row))
     }
 
-    fn index_search(query: &Query, db: &Database, reader: &Reader<'_, u8>, index: &Index) {
-        let index_schema = &index.sql_schema;
-        let table = db.tables.get(&query.source).unwrap();
-        let table_schema = &table.sql_schema;
+    /// A table row holding the values of an index entry, leaving the columns outside the index
+    /// NULL.
+    fn index_entry_row(
+        sql_schema: &TableSchema,
+        index: &Index,
+        values: Vec<Record>,
+        rowid: i64,
+    ) -> Result<Vec<Record>, Error> {
+        let mut row = vec![Record::Null; sql_schema.fields.len()];
+        for (field, value) in index.sql_schema.fields.iter().zip(values) {
+            let i = sql_schema.field_index(&field.field)?;
+            // REAL columns may store integral values as integers on disk.
+            row[i] = match (&sql_schema.fields[i].kind, value.as_int()) {
+                (TableFieldKind::Real, Some(v)) => Record::Real(v as f64),
+                _ => value,
+            };
+        }
+        sql_schema.apply_rowid(rowid, &mut row);
+        Ok(row)
+    }
 
-        let mut offset_stack: VecDeque<usize> = VecDeque::new();
-        offset_stack.push_back(db.header.page_size * (index.root_page - 1));
+    /// Looks up a single row by rowid, seeking it from the root of the table b-tree.
+    fn find_row(
+        db: &Database,
+        root_page: usize,
+        rowid: i64,
+        sql_schema: &TableSchema,
+    ) -> Result<Option<Vec<Record>>, Error> {
+        let mut cursor = BTreeCursor::new(&db.pager, root_page);
+        if !cursor.seek_rowid(rowid)? || cursor.rowid()? != rowid {
+            return Ok(None);
+        }
+        Ok(Some(cursor.payload()?.read_as_table_row(sql_schema)?))
+    }
+}
 
-        assert_eq!(1, query.conditions.len());
-        assert_eq!(QueryConditionOp::Eq, query.conditions[0].op);
+/// The rows of a running query, each read when it is pulled, so that a LIMIT or a caller
+/// stopping early leaves the rest of the tables unread. The rows own their plan and scope,
+/// which the operators below are handed on every call rather than borrowing.
+pub(crate) struct QueryRows<'a> {
+    plan: QueryPlan<'a>,
+    scope: Scope<'a>,
+    output: QueryOutput<'a>,
+    /// Set once a row failed, which ends the rows.
+    failed: bool,
+}
 
-        let target = &query.conditions[0].rhs;
-        let mut rowids = vec![];
+impl QueryRows<'_> {
+    /// Names of the result columns.
+    pub(crate) fn columns(&self) -> &[String] {
+        &self.output.names
+    }
+}
 
-        while let Some(page_offset) = offset_stack.pop_front() {
-            let page_header = BTreePageHeader::from(&reader.at(page_offset));
-            // dbg!(&page_header);
+impl Iterator for QueryRows<'_> {
+    type Item = Result<Vec<Record>, Error>;
 
-            match page_header.kind {
-                BTreePageType::LeafIndex => {
-                    for cell_offset in page_header.cell_offsets {
-                        let cell = IndexBTreeLeafCell::from(&reader.at(page_offset + cell_offset));
-                        let (values, positions) = cell.payload.read_as_index_row(index_schema);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let row = self.output.next_row(&self.plan, &self.scope).transpose();
+        self.failed = matches!(row, Some(Err(_)));
+        row
+    }
+}
 
-                        if target == &values[0] {
-                            rowids.push(positions[0].as_int().unwrap());
-                        }
-                    }
-                }
-                BTreePageType::InteriorIndex => {
-                    // dbg!(&page_header);
-                    let mut can_be_last_child = true;
-
-                    for cell_offset in page_header.cell_offsets {
-                        let cell =
-                            IndexBTreeInteriorCell::from(&reader.at(page_offset + cell_offset));
-                        let (values, positions) = cell.payload.read_as_index_row(index_schema);
-                        assert_eq!(1, values.len());
-                        assert_eq!(1, positions.len());
-
-                        if target <= &values[0] {
-                            offset_stack
-                                .push_back((cell.left_child_pointer - 1) * db.header.page_size);
-                        }
-
-                        if target < &values[0] {
-                            can_be_last_child = false;
-                        }
-                    }
+/// In-order read of the table b-tree rows whose rowid lies in a range, seeking to the first of
+/// them.
+pub(crate) struct TableScan<'a> {
+    cursor: BTreeCursor<'a>,
+    table: &'a Table,
+    range: RowidRange,
+    started: bool,
+    valid: bool,
+}
+
+impl<'a> TableScan<'a> {
+    pub(crate) fn new(db: &'a Database, table: &'a Table, range: RowidRange) -> Self {
+        Self {
+            cursor: BTreeCursor::new(&db.pager, table.root_page),
+            table,
+            range,
+            started: false,
+            valid: false,
+        }
+    }
+
+    /// The rowid and values of the next row, without the rowid alias filled in.
+    pub(crate) fn next_row(&mut self) -> Result<Option<(i64, Vec<Record>)>, Error> {
+        self.valid = if !self.started {
+            self.started = true;
+            self.cursor.seek_rowid(self.range.start)?
+        } else {
+            self.valid && self.cursor.next()?
+        };
+        if !self.valid || self.cursor.rowid()? > self.range.end {
+            self.valid = false;
+            return Ok(None);
+        }
+
+        let row = self
+            .cursor
+            .payload()?
+            .read_as_table_row(&self.table.sql_schema)?;
+        Ok(Some((self.cursor.rowid()?, row)))
+    }
+}
+
+/// Read of an index b-tree in key order, or reverse key order, yielding the values and rowid of
+/// each entry. With a range, only the entries inside it are read, seeking to the first of them.
+struct IndexScan<'a> {
+    cursor: BTreeCursor<'a>,
+    index: &'a Index,
+    range: Option<IndexRange>,
+    reverse: bool,
+    started: bool,
+    valid: bool,
+}
+
+impl<'a> IndexScan<'a> {
+    fn new(db: &'a Database, index: &'a Index, range: Option<IndexRange>, reverse: bool) -> Self {
+        Self {
+            cursor: BTreeCursor::new(&db.pager, index.root_page),
+            index,
+            range,
+            reverse,
+            started: false,
+            valid: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(Vec<Record>, i64)>, Error> {
+        let index_schema = &self.index.sql_schema;
+        self.valid = if !self.started {
+            self.started = true;
+            match &self.range {
+                Some(range) => self.cursor.seek_key(&mut |payload| {
+                    Ok(range.position(index_schema, &payload.read_as_index_row(index_schema)?.0))
+                })?,
+                None if self.reverse => self.cursor.last()?,
+                None => self.cursor.first()?,
+            }
+        } else if !self.valid {
+            false
+        } else if self.reverse {
+            self.cursor.prev()?
+        } else {
+            self.cursor.next()?
+        };
+        if !self.valid {
+            return Ok(None);
+        }
+
+        let (values, rowid) = self.cursor.payload()?.read_as_index_row(index_schema)?;
+        let past_range = self
+            .range
+            .as_ref()
+            .is_some_and(|range| range.position(index_schema, &values).is_gt());
+        if past_range {
+            self.valid = false;
+            return Ok(None);
+        }
+        Ok(Some((values, rowid)))
+    }
+}
+
+/// Where the rows of the first table come from, in the order its access path reads them.
+enum SourceRows<'a> {
+    /// The rows of a derived table, read ahead.
+    Derived(vec::IntoIter<Vec<Record>>),
+    Table(TableScan<'a>),
+    /// Index entries in index order, turned into rows straight from a covering index or else
+    /// looked up in the table as the entries are read.
+    Index {
+        scan: IndexScan<'a>,
+        table: &'a Table,
+        covering: bool,
+    },
+}
 
-                    if can_be_last_child {
-                        offset_stack.push_back(
-                            (page_header.rightmost_pointer.unwrap() - 1) * db.header.page_size,
-                        );
+impl<'a> SourceRows<'a> {
+    fn new(
+        plan: &QueryPlan<'a>,
+        db: &'a Database,
+        derived: Vec<Vec<Record>>,
+    ) -> Result<Self, Error> {
+        let table = match &plan.bindings[0].source {
+            Source::Table(table) => *table,
+            Source::Derived { .. } => return Ok(Self::Derived(derived.into_iter())),
+        };
+
+        Ok(match &plan.path {
+            AccessPath::FullScan => Self::Table(TableScan::new(db, table, RowidRange::ALL)),
+            AccessPath::RowidRange(range) => Self::Table(TableScan::new(db, table, *range)),
+            AccessPath::IndexRange {
+                index,
+                range,
+                covering,
+            } => Self::Index {
+                scan: IndexScan::new(db, index, Some(range.clone()), false),
+                table,
+                covering: *covering,
+            },
+            AccessPath::IndexScan {
+                index,
+                reverse,
+                covering,
+            } => Self::Index {
+                scan: IndexScan::new(db, index, None, *reverse),
+                table,
+                covering: *covering,
+            },
+        })
+    }
+
+    fn next_row(&mut self, db: &Database) -> Result<Option<Vec<Record>>, Error> {
+        match self {
+            Self::Derived(rows) => Ok(rows.next()),
+            Self::Table(scan) => {
+                let Some((rowid, mut row)) = scan.next_row()? else {
+                    return Ok(None);
+                };
+                scan.table.sql_schema.apply_rowid(rowid, &mut row);
+                Ok(Some(row))
+            }
+            Self::Index {
+                scan,
+                table,
+                covering,
+            } => {
+                while let Some((values, rowid)) = scan.next_entry()? {
+                    if *covering {
+                        let row = QueryExecutor::index_entry_row(
+                            &table.sql_schema,
+                            scan.index,
+                            values,
+                            rowid,
+                        )?;
+                        return Ok(Some(row));
+                    }
+                    if let Some(row) = QueryExecutor::find_table_row(db, table, rowid)? {
+                        return Ok(Some(row));
                     }
                 }
-                other => panic!("Page type {:?} not expected", other),
+                Ok(None)
             }
         }
+    }
+}
+
+/// What a query runs against: the database and, for a subquery, the row of the enclosing query
+/// its correlated column references read from.
+struct Scope<'a> {
+    db: &'a Database,
+    outer: Option<&'a dyn RowContext>,
+    /// Whether a column of the enclosing row was read, making the results depend on that row.
+    read_outer: Cell<bool>,
+    /// Results of the subqueries that read nothing from the enclosing row, so that they run
+    /// once rather than for every row. Keyed by the address of their statement.
+    subquery_rows: RefCell<HashMap<*const SelectStmt, Vec<Vec<Record>>>>,
+    /// Plans of the subqueries, made once and run again for every row of a correlated one.
+    subquery_plans: RefCell<HashMap<SubqueryKey, Rc<Subquery<'a>>>>,
+}
+
+/// The address of the statement of a subquery and the number of rows it needs to return.
+type SubqueryKey = (*const SelectStmt, Option<usize>);
+
+/// A subquery and how it is run.
+struct Subquery<'a> {
+    query: Query,
+    plan: QueryPlan<'a>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(db: &'a Database, outer: Option<&'a dyn RowContext>) -> Self {
+        Self {
+            db,
+            outer,
+            read_outer: Cell::new(false),
+            subquery_rows: RefCell::new(HashMap::new()),
+            subquery_plans: RefCell::new(HashMap::new()),
+        }
+    }
 
-        Self::rowid_lookup_search(query, db, reader, rowids);
+    fn outer(&self, table: Option<&str>, name: &str) -> Result<&dyn RowContext, Error> {
+        let Some(outer) = self.outer else {
+            return Err(Error::NoSuchColumn(match table {
+                Some(table) => format!("{}.{}", table, name),
+                None => name.to_string(),
+            }));
+        };
+        self.read_outer.set(true);
+        Ok(outer)
     }
 
-    fn rowid_lookup_search(
-        query: &Query,
-        db: &Database,
-        reader: &Reader<'_, u8>,
-        rowids: Vec<i64>,
-    ) {
-        let table = db.tables.get(&query.source).unwrap();
-        let sql_schema = &table.sql_schema;
+    /// Runs a subquery of the query of this scope, for one of its rows.
+    fn subquery_rows(
+        &self,
+        select: &SelectStmt,
+        limit: Option<usize>,
+        row: &dyn RowContext,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        let key = select as *const SelectStmt;
+        if let Some(rows) = self.subquery_rows.borrow().get(&key) {
+            return Ok(rows.clone());
+        }
 
-        let mut query_visitor = match &query.fields {
-            QueryField::Count => QueryVisitor::Count(0),
-            QueryField::List(fields) => QueryVisitor::Fields(
-                fields
-                    .iter()
-                    .map(|name| sql_schema.field_index(name))
-                    .collect(),
-            ),
+        let subquery = self.subquery_plan(select, limit)?;
+        let scope = Scope::new(self.db, Some(row));
+        let rows = QueryExecutor::run(&subquery.query, &subquery.plan, &scope)?;
+
+        if !scope.read_outer.get() {
+            self.subquery_rows.borrow_mut().insert(key, rows.clone());
+        }
+        Ok(rows)
+    }
+
+    /// Plans a subquery the first time it runs, returning at most `limit` rows.
+    fn subquery_plan(
+        &self,
+        select: &SelectStmt,
+        limit: Option<usize>,
+    ) -> Result<Rc<Subquery<'a>>, Error> {
+        let key = (select as *const SelectStmt, limit);
+        if let Some(subquery) = self.subquery_plans.borrow().get(&key) {
+            return Ok(subquery.clone());
+        }
+
+        let mut query = Query::from_select(select.clone())?;
+        query.limit = match (query.limit, limit) {
+            (Some(limit), Some(needed)) => Some(limit.min(needed)),
+            (limit, needed) => limit.or(needed),
         };
+        let plan = QueryPlanner::plan(&query, self.db)?;
+        let subquery = Rc::new(Subquery { query, plan });
+        self.subquery_plans
+            .borrow_mut()
+            .insert(key, subquery.clone());
+        Ok(subquery)
+    }
 
-        let mut offset_stack: VecDeque<(usize, i64)> = VecDeque::new();
-        offset_stack.push_back((db.header.page_size * (table.root_page - 1), i64::MAX));
-
-        while let Some((offset, last_rowid)) = offset_stack.pop_front() {
-            let page_header = BTreePageHeader::from(&reader.at(offset));
-            // debug!("Page: {}", offset);
-
-            match page_header.kind {
-                BTreePageType::LeafTable => {
-                    let first_rowid =
-                        TableBTreeLeafCell::from(&reader.at(offset + page_header.cell_offsets[0]))
-                            .rowid;
-                    let last_rowid =
-                        TableBTreeLeafCell::from(&reader.at(offset
-                            + page_header.cell_offsets[(page_header.cell_count - 1) as usize]))
-                        .rowid;
-                    for rowid in &rowids {
-                        if rowid >= &first_rowid && rowid <= &last_rowid {
-                            let mut i = 0;
-                            let mut j = page_header.cell_count - 1;
-                            let mut mid = (i + j) / 2;
-                            let mut row = None;
-
-                            while i <= j {
-                                let mid_cell = TableBTreeLeafCell::from(
-                                    &reader.at(offset + page_header.cell_offsets[mid as usize]),
-                                );
-                                if &mid_cell.rowid == rowid {
-                                    row = Some(mid_cell.payload.read_as_table_row(&sql_schema));
-                                    break;
-                                } else if &mid_cell.rowid > rowid {
-                                    j = mid - 1;
-                                } else {
-                                    i = mid + 1;
-                                }
-                                mid = (i + j) / 2;
-                            }
-
-                            if let Some(mut row) = row {
-                                table.sql_schema.apply_rowid(*rowid, &mut row);
-                                query_visitor.signal_on_match(&row);
-                            }
-                        }
-                    }
+    /// Affinity of the first result column of a subquery, compared against by `IN (SELECT ...)`:
+    /// that of the column it reads, if it is one of the tables of the subquery.
+    fn subquery_affinity(&self, select: &SelectStmt) -> Result<Option<Affinity>, Error> {
+        let subquery = self.subquery_plan(select, None)?;
+        let bindings = &subquery.plan.bindings;
+        let position = match subquery.query.columns.first() {
+            Some(ResultColumn::Star) => Some(0),
+            Some(ResultColumn::TableStar(table)) => bindings
+                .iter()
+                .find(|binding| binding.name.eq_ignore_ascii_case(table))
+                .map(|binding| binding.offset),
+            Some(ResultColumn::Expr {
+                expr: Expr::Column { table, name },
+                ..
+            }) => Binding::resolve(bindings, table.as_deref(), name)?,
+            _ => None,
+        };
+        Ok(position.map(|position| Binding::affinity_at(bindings, position)))
+    }
+}
+
+/// Extends each row of the first table with the matching rows of the joined tables, one nested
+/// loop per join, and yields the complete rows matching the WHERE clause. The loops are kept as
+/// a stack of levels, so that they can stop after each row and pick up where they left off.
+struct NestedLoopJoin<'a> {
+    source: SourceRows<'a>,
+    /// The WHERE clause, left to check once every table is joined.
+    filter: Option<Expr>,
+    /// Per binding, the rows of a derived table, run once.
+    materialized: Vec<Vec<Vec<Record>>>,
+    /// The row being built, holding the values of the tables joined so far.
+    row: Vec<Record>,
+    /// Per join entered, how far its loop went through the rows of the joined table.
+    levels: Vec<JoinLevel<'a>>,
+}
+
+/// The loop of a join, over the rows of the joined table matching the row of the tables before
+/// it.
+struct JoinLevel<'a> {
+    rows: JoinRows<'a>,
+    next: usize,
+    matched: bool,
+}
+
+/// Where the loop of a join reads the rows of the joined table from.
+enum JoinRows<'a> {
+    /// The rows looked up by key.
+    Found(Vec<Vec<Record>>),
+    /// The materialized rows of a derived table.
+    Materialized,
+    /// A table joined without a usable key, scanned again for every row of the tables before
+    /// it, as far as the loop goes.
+    Scan(TableScan<'a>),
+}
+
+impl<'a> NestedLoopJoin<'a> {
+    fn new(query: &Query, plan: &QueryPlan<'a>, scope: &Scope<'a>) -> Result<Self, Error> {
+        let mut materialized = plan
+            .bindings
+            .iter()
+            .map(|binding| {
+                let Source::Derived { query, plan, .. } = &binding.source else {
+                    return Ok(vec![]);
+                };
+                let derived = Scope::new(scope.db, scope.outer);
+                let rows = QueryExecutor::run(query, plan, &derived)?;
+                if derived.read_outer.get() {
+                    scope.read_outer.set(true);
                 }
+                Ok(rows)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
 
-                BTreePageType::InteriorTable => {
-                    for cell_offset in page_header.cell_offsets {
-                        let cell = TableBTreeInteriorCell::from(&reader.at(offset + cell_offset));
-                        offset_stack.push_back((
-                            (cell.left_child_pointer - 1) * db.header.page_size,
-                            cell.rowid,
-                        ));
-                    }
+        Ok(Self {
+            source: SourceRows::new(plan, scope.db, std::mem::take(&mut materialized[0]))?,
+            filter: query.filter.clone().filter(|_| !plan.joins.is_empty()),
+            materialized,
+            row: vec![],
+            levels: vec![],
+        })
+    }
 
-                    offset_stack.push_back((
-                        (page_header.rightmost_pointer.unwrap() - 1) * db.header.page_size,
-                        last_rowid,
-                    ));
+    /// The next complete row matching the WHERE clause, or `None` once every row of the first
+    /// table was joined.
+    fn next_row(
+        &mut self,
+        plan: &QueryPlan<'a>,
+        scope: &Scope<'a>,
+    ) -> Result<Option<Vec<Record>>, Error> {
+        let bindings = &plan.bindings;
+        loop {
+            let Some(depth) = self.levels.len().checked_sub(1) else {
+                let Some(row) = self.source.next_row(scope.db)? else {
+                    return Ok(None);
+                };
+                if !Self::matches(plan.base_filter.as_ref(), &bindings[..1], &row, scope)? {
+                    continue;
+                }
+                if plan.joins.is_empty() {
+                    return Ok(Some(row));
                 }
-                other => unimplemented!("Page type {:?} not expected", other),
+                self.row = row;
+                self.enter(0, plan, scope)?;
+                continue;
+            };
+
+            // Move the innermost loop on to its next matching row, or to the row of NULLs a LEFT
+            // JOIN makes when none matched.
+            let join = &plan.joins[depth];
+            let binding = &bindings[depth + 1];
+            let level = &mut self.levels[depth];
+            let values = match &mut level.rows {
+                JoinRows::Found(rows) => rows.get(level.next).cloned(),
+                JoinRows::Materialized => self.materialized[depth + 1].get(level.next).cloned(),
+                JoinRows::Scan(scan) => scan.next_row()?.map(|(rowid, mut row)| {
+                    let table = binding.table().expect("Derived tables are materialized");
+                    table.sql_schema.apply_rowid(rowid, &mut row);
+                    row
+                }),
+            };
+            self.row.truncate(binding.offset);
+            if let Some(values) = values {
+                level.next += 1;
+                self.row.extend(values);
+                if !Self::matches(join.on.as_ref(), &bindings[..=depth + 1], &self.row, scope)? {
+                    continue;
+                }
+                level.matched = true;
+            } else if !level.matched && join.kind == JoinKind::Left {
+                level.matched = true;
+                self.row
+                    .resize(binding.offset + binding.width(), Record::Null);
+            } else {
+                self.levels.pop();
+                continue;
+            }
+
+            if depth + 1 < plan.joins.len() {
+                self.enter(depth + 1, plan, scope)?;
+            } else if Self::matches(self.filter.as_ref(), bindings, &self.row, scope)? {
+                return Ok(Some(self.row.clone()));
             }
         }
+    }
+
+    /// Starts the loop of join `depth`, looking up the rows of the joined table by key when the
+    /// join has one.
+    fn enter(
+        &mut self,
+        depth: usize,
+        plan: &QueryPlan<'a>,
+        scope: &Scope<'a>,
+    ) -> Result<(), Error> {
+        let join = &plan.joins[depth];
+        let binding = &plan.bindings[depth + 1];
+        let db = scope.db;
+        let before = TableRow::new(&plan.bindings[..=depth], &self.row, scope);
+        let rows = match &join.lookup {
+            JoinLookup::Scan => match binding.table() {
+                Some(table) => JoinRows::Scan(TableScan::new(db, table, RowidRange::ALL)),
+                None => JoinRows::Materialized,
+            },
+            JoinLookup::Rowid(key) => {
+                let table = binding.table().expect("Derived tables have no rowid");
+                JoinRows::Found(match Affinity::Integer.apply(key.eval(&before)?).as_int() {
+                    Some(rowid) => QueryExecutor::find_table_row(db, table, rowid)?
+                        .into_iter()
+                        .collect(),
+                    None => vec![],
+                })
+            }
+            JoinLookup::Index { index, key } => {
+                let table = binding.table().expect("Derived tables have no index");
+                let key = key.eval(&before)?;
+                JoinRows::Found(if key.is_null() {
+                    vec![]
+                } else {
+                    let field = table
+                        .sql_schema
+                        .field_index(&index.sql_schema.fields[0].field)?;
+                    let range = IndexRange {
+                        prefix: vec![table.sql_schema.fields[field].affinity().apply(key)],
+                        lower: None,
+                        upper: None,
+                    };
+                    let mut scan = IndexScan::new(db, index, Some(range), false);
+                    let mut rows = vec![];
+                    while let Some((_, rowid)) = scan.next_entry()? {
+                        rows.extend(QueryExecutor::find_table_row(db, table, rowid)?);
+                    }
+                    rows
+                })
+            }
+        };
 
-        query_visitor.signal_post_query();
+        self.levels.push(JoinLevel {
+            rows,
+            next: 0,
+            matched: false,
+        });
+        Ok(())
     }
 
-    fn full_table_scan(query: &Query, db: &Database, reader: &Reader<'_, u8>) {
-        let table = db.tables.get(&query.source).unwrap();
-        let sql_schema = &table.sql_schema;
+    fn matches(
+        filter: Option<&Expr>,
+        bindings: &[Binding],
+        row: &[Record],
+        scope: &Scope,
+    ) -> Result<bool, Error> {
+        let Some(filter) = filter else {
+            return Ok(true);
+        };
+        let row = TableRow::new(bindings, row, scope);
+        Ok(filter.eval(&row)?.truthiness() == Some(true))
+    }
+}
 
-        let mut query_visitor = match &query.fields {
-            QueryField::Count => QueryVisitor::Count(0),
-            QueryField::List(fields) => QueryVisitor::Fields(
-                fields
-                    .iter()
-                    .map(|name| sql_schema.field_index(name))
-                    .collect(),
-            ),
+/// A decoded row of the FROM clause tables, resolving column references through their bindings
+/// for expression evaluation, and the remaining ones through the enclosing query.
+struct TableRow<'a, 's> {
+    bindings: &'a [Binding<'a>],
+    values: &'a [Record],
+    scope: &'a Scope<'s>,
+}
+
+impl<'a, 's> TableRow<'a, 's> {
+    fn new(bindings: &'a [Binding<'a>], values: &'a [Record], scope: &'a Scope<'s>) -> Self {
+        Self {
+            bindings,
+            values,
+            scope,
+        }
+    }
+}
+
+impl RowContext for TableRow<'_, '_> {
+    fn column_value(&self, table: Option<&str>, name: &str) -> Result<Record, Error> {
+        match Binding::resolve(self.bindings, table, name)? {
+            Some(position) => Ok(self.values[position].clone()),
+            None => self.scope.outer(table, name)?.column_value(table, name),
+        }
+    }
+
+    fn column_affinity(&self, table: Option<&str>, name: &str) -> Result<Option<Affinity>, Error> {
+        match Binding::resolve(self.bindings, table, name)? {
+            Some(position) => Ok(Some(Binding::affinity_at(self.bindings, position))),
+            None => self.scope.outer(table, name)?.column_affinity(table, name),
+        }
+    }
+
+    fn column_collation(&self, table: Option<&str>, name: &str) -> Result<Collation, Error> {
+        match Binding::resolve(self.bindings, table, name)? {
+            Some(position) => Ok(Binding::collation_at(self.bindings, position)),
+            None => self.scope.outer(table, name)?.column_collation(table, name),
+        }
+    }
+
+    fn subquery_rows(
+        &self,
+        select: &SelectStmt,
+        limit: Option<usize>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        self.scope.subquery_rows(select, limit, self)
+    }
+
+    fn subquery_affinity(&self, select: &SelectStmt) -> Result<Option<Affinity>, Error> {
+        self.scope.subquery_affinity(select)
+    }
+}
+
+/// A finished group, resolving bare column references against the last row of the group and
+/// aggregate calls against their folded values.
+struct GroupRow<'a, 's> {
+    bindings: &'a [Binding<'a>],
+    row: &'a AggregateRow,
+    calls: &'a [Expr],
+    scope: &'a Scope<'s>,
+}
+
+impl RowContext for GroupRow<'_, '_> {
+    fn column_value(&self, table: Option<&str>, name: &str) -> Result<Record, Error> {
+        let Some(position) = Binding::resolve(self.bindings, table, name)? else {
+            return self.scope.outer(table, name)?.column_value(table, name);
         };
+        // A group without any rows only exists for aggregate queries without GROUP BY.
+        Ok(self
+            .row
+            .representative
+            .get(position)
+            .cloned()
+            .unwrap_or(Record::Null))
+    }
 
-        let mut incrementer_map = sql_schema.make_incrementer_map();
-        let mut offset_stack: VecDeque<usize> = VecDeque::new();
-        offset_stack.push_back(db.header.page_size * (table.root_page - 1));
-
-        while let Some(offset) = offset_stack.pop_front() {
-            let page_header = BTreePageHeader::from(&reader.at(offset));
-            // debug!("Page: {}", offset);
-
-            match page_header.kind {
-                BTreePageType::LeafTable => {
-                    for cell_offset in page_header.cell_offsets {
-                        let cell = TableBTreeLeafCell::from(&reader.at(offset + cell_offset));
-                        // debug!("RowID: {}", cell.rowid);
-                        let mut row = cell.payload.read_as_table_row(&sql_schema);
-                        Self::apply_incrementer(&mut row, &mut incrementer_map);
-
-                        let mut is_match = true;
-                        for cond in &query.conditions {
-                            let field_index = sql_schema.field_index(&cond.lhs);
-                            if !cond.op.eval(&row[field_index], &cond.rhs) {
-                                is_match = false;
-                                break;
-                            }
-                        }
-
-                        if is_match {
-                            query_visitor.signal_on_match(&row);
-                        }
-                    }
+    fn column_affinity(&self, table: Option<&str>, name: &str) -> Result<Option<Affinity>, Error> {
+        match Binding::resolve(self.bindings, table, name)? {
+            Some(position) => Ok(Some(Binding::affinity_at(self.bindings, position))),
+            None => self.scope.outer(table, name)?.column_affinity(table, name),
+        }
+    }
+
+    fn column_collation(&self, table: Option<&str>, name: &str) -> Result<Collation, Error> {
+        match Binding::resolve(self.bindings, table, name)? {
+            Some(position) => Ok(Binding::collation_at(self.bindings, position)),
+            None => self.scope.outer(table, name)?.column_collation(table, name),
+        }
+    }
+
+    fn function_value(&self, expr: &Expr) -> Option<Record> {
+        self.calls
+            .iter()
+            .position(|call| call == expr)
+            .map(|i| self.row.values[i].clone())
+    }
+
+    fn subquery_rows(
+        &self,
+        select: &SelectStmt,
+        limit: Option<usize>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        self.scope.subquery_rows(select, limit, self)
+    }
+
+    fn subquery_affinity(&self, select: &SelectStmt) -> Result<Option<Affinity>, Error> {
+        self.scope.subquery_affinity(select)
+    }
+}
+
+/// `1st`, `2nd`, `3rd`, `4th` and so on, as sqlite numbers terms in its messages.
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// What an ORDER BY term sorts on: one of the output columns (by position or alias) or an
+/// arbitrary expression over the source row.
+enum SortKey {
+    Output(usize),
+    Expr(Expr),
+}
+
+/// Turns the joined rows into result rows: evaluates the result columns of each, or folds the
+/// rows into groups first for aggregate queries, sorting the output when it doesn't already
+/// arrive in ORDER BY order, then applies OFFSET and LIMIT.
+struct QueryOutput<'a> {
+    input: NestedLoopJoin<'a>,
+    exprs: Vec<Expr>,
+    names: Vec<String>,
+    aggregator: Option<Aggregator>,
+    having: Option<Expr>,
+    sort_keys: Vec<SortKey>,
+    /// The collation each ORDER BY term sorts text by.
+    sort_collations: Vec<Collation>,
+    order_by: Vec<OrderingTerm>,
+    needs_sort: bool,
+    /// The output of an aggregate or sorted query, which only exists once every row was read.
+    buffered: Option<vec::IntoIter<Vec<Record>>>,
+    limit: Option<usize>,
+    offset: usize,
+    skipped: usize,
+    emitted: usize,
+}
+
+impl<'a> QueryOutput<'a> {
+    fn new(query: &Query, plan: &QueryPlan<'a>, scope: &Scope<'a>) -> Result<Self, Error> {
+        let bindings = &plan.bindings;
+        // Stars expand to the columns of every table, or of the named one, qualified so that
+        // columns of the same name in different tables stay apart.
+        let table_columns = |binding: &Binding| {
+            binding
+                .schema()
+                .fields
+                .iter()
+                .map(|field| {
+                    let column = Expr::Column {
+                        table: Some(binding.name.clone()),
+                        name: field.name.clone(),
+                    };
+                    (column, None)
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut columns = vec![];
+        for column in &query.columns {
+            match column {
+                ResultColumn::Star => columns.extend(bindings.iter().flat_map(table_columns)),
+                ResultColumn::TableStar(table) => {
+                    let binding = bindings
+                        .iter()
+                        .find(|binding| binding.name.eq_ignore_ascii_case(table))
+                        .ok_or_else(|| Error::NoSuchTable(table.clone()))?;
+                    columns.extend(table_columns(binding));
                 }
+                ResultColumn::Expr { expr, alias } => columns.push((expr.clone(), alias.clone())),
+            }
+        }
+        let (exprs, aliases): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+        // Result columns are named by their alias, else after the column they read, like the
+        // columns of a derived table.
+        let names = exprs
+            .iter()
+            .zip(&aliases)
+            .enumerate()
+            .map(|(i, (expr, alias))| match (alias, expr) {
+                (Some(alias), _) => alias.clone(),
+                (None, Expr::Column { name, .. }) => name.clone(),
+                (None, _) => format!("column{}", i + 1),
+            })
+            .collect();
 
-                BTreePageType::InteriorTable => {
-                    for cell_offset in page_header.cell_offsets {
-                        let cell = TableBTreeInteriorCell::from(&reader.at(offset + cell_offset));
-                        offset_stack.push_back((cell.left_child_pointer - 1) * db.header.page_size);
-                        // debug!(
-                        //     "Interior RowID: {} -> Offset: {}",
-                        //     cell.rowid,
-                        //     (cell.left_child_pointer - 1) * db.header.page_size
-                        // );
+        // A COLLATE on a position or alias only changes how the output column sorts.
+        let term_exprs = query.order_by.iter().map(|term| match &term.expr {
+            Expr::Collate { expr, .. } => expr.as_ref(),
+            expr => expr,
+        });
+        let sort_keys = term_exprs
+            .enumerate()
+            .map(|(i, expr)| match expr {
+                Expr::Literal(Record::I64(position)) => match usize::try_from(*position) {
+                    Ok(position @ 1..) if position <= exprs.len() => {
+                        Ok(SortKey::Output(position - 1))
                     }
+                    _ => Err(Error::from(format!(
+                        "{} ORDER BY term out of range - should be between 1 and {}",
+                        ordinal(i + 1),
+                        exprs.len()
+                    ))),
+                },
+                Expr::Column { table: None, name } => Ok(aliases
+                    .iter()
+                    .position(|alias| {
+                        alias
+                            .as_deref()
+                            .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+                    })
+                    .map_or_else(|| SortKey::Expr(expr.clone()), SortKey::Output)),
+                expr => Ok(SortKey::Expr(expr.clone())),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Collations only depend on the columns an expression reads, not on their values.
+        let columns = TableRow::new(bindings, &[], scope);
+        let sort_collations = query
+            .order_by
+            .iter()
+            .zip(&sort_keys)
+            .map(|(term, key)| match (&term.expr, key) {
+                (Expr::Collate { .. }, _) => term.expr.collation(&columns),
+                (_, SortKey::Output(i)) => exprs[*i].collation(&columns),
+                (expr, SortKey::Expr(_)) => expr.collation(&columns),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let aggregator = query.is_aggregate().then(|| {
+            let sort_exprs = sort_keys.iter().filter_map(|key| match key {
+                SortKey::Expr(expr) => Some(expr),
+                SortKey::Output(_) => None,
+            });
+            let calls = AggregateFunction::collect_calls(
+                exprs.iter().chain(query.having.as_ref()).chain(sort_exprs),
+            );
+            Aggregator::new(query.group_by.clone(), calls)
+        });
+
+        Ok(Self {
+            input: NestedLoopJoin::new(query, plan, scope)?,
+            needs_sort: (aggregator.is_some() || !plan.presorted) && !query.order_by.is_empty(),
+            exprs,
+            names,
+            aggregator,
+            having: query.having.clone(),
+            sort_keys,
+            sort_collations,
+            order_by: query.order_by.clone(),
+            buffered: None,
+            limit: query.limit,
+            offset: query.offset,
+            skipped: 0,
+            emitted: 0,
+        })
+    }
+
+    /// The next result row, or `None` once there are no more or LIMIT is reached.
+    fn next_row(
+        &mut self,
+        plan: &QueryPlan<'a>,
+        scope: &Scope<'a>,
+    ) -> Result<Option<Vec<Record>>, Error> {
+        if self.buffered.is_none() && (self.aggregator.is_some() || self.needs_sort) {
+            self.buffered = Some(self.read_all(plan, scope)?.into_iter());
+        }
+
+        loop {
+            if self.limit.is_some_and(|limit| self.emitted >= limit) {
+                return Ok(None);
+            }
+            let values = match &mut self.buffered {
+                Some(rows) => rows.next(),
+                None => match self.input.next_row(plan, scope)? {
+                    Some(row) => Some(self.output(&TableRow::new(&plan.bindings, &row, scope))?.1),
+                    None => None,
+                },
+            };
+            let Some(values) = values else {
+                return Ok(None);
+            };
+
+            if self.skipped < self.offset {
+                self.skipped += 1;
+                continue;
+            }
+            self.emitted += 1;
+            return Ok(Some(values));
+        }
+    }
 
-                    offset_stack.push_back(
-                        (page_header.rightmost_pointer.unwrap() - 1) * db.header.page_size,
-                    );
+    /// Reads every input row, folding them into groups for an aggregate query, and returns the
+    /// result rows in order.
+    fn read_all(
+        &mut self,
+        plan: &QueryPlan<'a>,
+        scope: &Scope<'a>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        let mut rows = vec![];
+        while let Some(values) = self.input.next_row(plan, scope)? {
+            let row = TableRow::new(&plan.bindings, &values, scope);
+            match &mut self.aggregator {
+                Some(aggregator) => aggregator.add_row(&row, &values)?,
+                None => rows.push(self.output(&row)?),
+            }
+        }
+
+        if let Some(aggregator) = self.aggregator.take() {
+            let calls = aggregator.calls().cloned().collect::<Vec<_>>();
+            for row in aggregator.finish()? {
+                let group = GroupRow {
+                    bindings: &plan.bindings,
+                    row: &row,
+                    calls: &calls,
+                    scope,
+                };
+                let keep = match &self.having {
+                    Some(having) => having.eval(&group)?.truthiness() == Some(true),
+                    None => true,
+                };
+                if keep {
+                    rows.push(self.output(&group)?);
                 }
-                other => unimplemented!("Page type {:?} not expected", other),
             }
         }
 
-        query_visitor.signal_post_query();
+        if self.needs_sort {
+            rows.sort_by(|(a, _), (b, _)| {
+                Self::compare_keys(&self.order_by, &self.sort_collations, a, b)
+            });
+        }
+        Ok(rows.into_iter().map(|(_, values)| values).collect())
     }
 
-    fn apply_incrementer(
-        rows: &mut Vec<Record>,
-        incrementer_map: &mut HashMap<usize, Incrementer>,
-    ) {
-        for (i, inc) in incrementer_map.iter_mut() {
-            rows[*i] = Record::I64(inc.next_value() as i64);
+    /// Evaluates the sort keys, when the output needs sorting, and the result columns for a
+    /// source row or group.
+    fn output(&self, row: &dyn RowContext) -> Result<(Vec<Record>, Vec<Record>), Error> {
+        let values = self
+            .exprs
+            .iter()
+            .map(|expr| expr.eval(row))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !self.needs_sort {
+            return Ok((vec![], values));
         }
+
+        let keys = self
+            .sort_keys
+            .iter()
+            .map(|key| match key {
+                SortKey::Output(i) => Ok(values[*i].clone()),
+                SortKey::Expr(expr) => expr.eval(row),
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok((keys, values))
     }
-}
 
-enum QueryVisitor {
-    Count(usize),
-    Fields(Vec<usize>),
+    fn compare_keys(
+        order_by: &[OrderingTerm],
+        collations: &[Collation],
+        a: &[Record],
+        b: &[Record],
+    ) -> Ordering {
+        for (((term, collation), a), b) in order_by.iter().zip(collations).zip(a).zip(b) {
+            let ordering = match (a.is_null(), b.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) if term.nulls_first() => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, true) if term.nulls_first() => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => {
+                    let ordering = collation.compare(a, b);
+                    if term.descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                }
+            };
+
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+
+        Ordering::Equal
+    }
 }
 
-impl QueryVisitor {
-    fn signal_on_match(&mut self, row: &Vec<Record>) {
-        match self {
-            Self::Count(n) => *n += 1,
-            Self::Fields(field_indices) => println!(
-                "{}",
-                field_indices
-                    .iter()
-                    .map(|i| row[*i].to_string())
-                    .collect::<Vec<_>>()
-                    .join("|")
-            ),
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        btree_cursor::BTreeCursor,
+        common::sqlite3,
+        database::Database,
+        pager::Pager,
+        parser::Parser,
+        planner::QueryPlanner,
+        query::Query,
+        query_executor::{QueryExecutor, Scope},
+        record::Record,
+    };
+
+    #[test]
+    fn test_correlated_subquery_is_planned_once() {
+        let db = Database::from(Pager::open("sample.db").unwrap()).unwrap();
+        let select = Parser::parse_select(
+            "SELECT a.name FROM apples a \
+             WHERE EXISTS (SELECT 1 FROM apples b WHERE b.id = a.id + 1) ORDER BY a.id",
+        )
+        .unwrap();
+        let query = Query::from_select(select).unwrap();
+        let plan = QueryPlanner::plan(&query, &db).unwrap();
+        let scope = Scope::new(&db, None);
+
+        let rows = QueryExecutor::run(&query, &plan, &scope).unwrap();
+        let names = rows.iter().map(|row| row[0].clone()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Record::String(String::from("Granny Smith")),
+                Record::String(String::from("Fuji")),
+                Record::String(String::from("Honeycrisp")),
+            ],
+            names
+        );
+        assert_eq!(1, scope.subquery_plans.borrow().len());
+        assert!(scope.subquery_rows.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_index_range_rows_in_index_order() {
+        let path = std::env::temp_dir().join(format!("range-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, k, v); CREATE INDEX t_k ON t(k); \
+                      WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000) \
+                      INSERT INTO t SELECT i, i % 100, printf('%.*c', 300, 'v') FROM n;";
+        if sqlite3(&path, schema).is_none() {
+            return;
         }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let sql = "SELECT id FROM t WHERE k > 40 AND k < 43 AND v <> ''";
+        let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+        let plan = QueryExecutor::explain_query(&query, &db).unwrap();
+        assert_eq!(
+            Record::String(String::from("`--SEARCH t USING INDEX t_k (k>? AND k<?)")),
+            plan[1][0]
+        );
+
+        // Rows come in the order of the index entries they are looked up from, as in sqlite.
+        let ids = QueryExecutor::execute_query(&query, &db)
+            .unwrap()
+            .map(|row| format!("{}\n", row.unwrap()[0]))
+            .collect::<String>();
+        assert_eq!(sqlite3(&path, sql), Some(ids));
+        fs::remove_file(&path).unwrap();
     }
 
-    fn signal_post_query(&self) {
-        match self {
-            Self::Count(n) => println!("{}", n),
-            Self::Fields(_) => {}
+    #[test]
+    fn test_collated_column_comparisons() {
+        let path = std::env::temp_dir().join(format!("collate-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, v); \
+                      CREATE INDEX t_name ON t(name); \
+                      WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000) \
+                      INSERT INTO t SELECT i, substr('abcABC', i % 6 + 1, 1) || (i % 50), \
+                      printf('%.*c', 300, 'v') FROM n;";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let sql = "SELECT id FROM t WHERE name = 'A7' AND v <> ''";
+        let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+        let plan = QueryExecutor::explain_query(&query, &db).unwrap();
+        assert_eq!(
+            Record::String(String::from("`--SEARCH t USING INDEX t_name (name=?)")),
+            plan[1][0]
+        );
+
+        for sql in [
+            sql,
+            "SELECT id FROM t WHERE name IN ('b3', 'C4') OR name BETWEEN 'a10' AND 'A11' ORDER BY id",
+            "SELECT id FROM t WHERE name = 'a7' COLLATE BINARY",
+            "SELECT name, COUNT(*), MIN(name), MAX(name) FROM t GROUP BY name",
+            "SELECT COUNT(DISTINCT name) FROM t",
+        ] {
+            let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+            let rows = QueryExecutor::execute_query(&query, &db)
+                .unwrap()
+                .map(|row| {
+                    let row = row.unwrap();
+                    let values = row
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect::<Vec<_>>();
+                    format!("{}\n", values.join("|"))
+                })
+                .collect::<String>();
+            assert_eq!(sqlite3(&path, sql), Some(rows), "{}", sql);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_order_by_collation() {
+        let path = std::env::temp_dir().join(format!("order-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(name TEXT COLLATE NOCASE, b TEXT); \
+                      INSERT INTO t VALUES ('eve', 'eve'), ('Bob', 'Bob'), ('alice', 'alice'), \
+                      ('Dave', 'Dave'), ('carol', 'carol');";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let sorted = |sql: &str| {
+            let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+            QueryExecutor::execute_query(&query, &db)
+                .unwrap()
+                .map(|row| row.unwrap()[0].to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let nocase = "alice Bob carol Dave eve";
+        assert_eq!(nocase, sorted("SELECT name FROM t ORDER BY name"));
+        assert_eq!(nocase, sorted("SELECT b FROM t ORDER BY b COLLATE NOCASE"));
+        assert_eq!(
+            nocase,
+            sorted("SELECT b AS x FROM t ORDER BY X COLLATE nocase")
+        );
+        assert_eq!(nocase, sorted("SELECT name AS N FROM t ORDER BY n"));
+        let binary = "Bob Dave alice carol eve";
+        assert_eq!(binary, sorted("SELECT b FROM t ORDER BY b"));
+        assert_eq!(
+            binary,
+            sorted("SELECT name FROM t ORDER BY 1 COLLATE BINARY")
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_join_lookup_by_collated_index() {
+        let path = std::env::temp_dir().join(format!("join-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE a(name TEXT); CREATE TABLE b(name TEXT COLLATE NOCASE, v); \
+                      CREATE INDEX b_name ON b(name); CREATE TABLE c(name TEXT, v); \
+                      CREATE INDEX c_name ON c(name); \
+                      INSERT INTO a VALUES ('alice'), ('BOB'), ('Carol'), ('dave'), ('Eve'); \
+                      INSERT INTO b VALUES ('ALICE', 1), ('bob', 2), ('carol', 3), ('DAVE', 4), \
+                      ('eve', 5), ('alice', 6); \
+                      INSERT INTO c SELECT * FROM b;";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let sql = "SELECT a.name, b.v FROM a JOIN b ON b.name = a.name ORDER BY 2";
+        let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+        let plan = QueryExecutor::explain_query(&query, &db).unwrap();
+        assert_eq!(
+            Record::String(String::from("|--SEARCH b USING INDEX b_name (name=?)")),
+            plan[2][0]
+        );
+
+        // The BINARY index on c can't find the rows a NOCASE comparison matches.
+        for sql in [
+            sql,
+            "SELECT a.name, c.v FROM a JOIN c ON c.name = a.name COLLATE NOCASE ORDER BY 2",
+            "SELECT a.name, c.v FROM a JOIN c ON c.name = a.name ORDER BY 2",
+        ] {
+            let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+            let rows = QueryExecutor::execute_query(&query, &db)
+                .unwrap()
+                .map(|row| {
+                    let row = row.unwrap();
+                    format!("{}|{}\n", row[0], row[1])
+                })
+                .collect::<String>();
+            assert_eq!(sqlite3(&path, sql), Some(rows), "{}", sql);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// The leaf pages of a b-tree in key order, each with the keys of its entries.
+    type Leaves = Vec<(usize, Vec<Record>)>;
+
+    /// Walks a b-tree, telling the leaf pages apart by the entries on them.
+    fn leaves(db: &Database, root_page: usize, key: impl Fn(&BTreeCursor) -> Record) -> Leaves {
+        let mut leaves: Leaves = vec![];
+        let mut cursor = BTreeCursor::new(&db.pager, root_page);
+        let mut more = cursor.first().unwrap();
+        while more {
+            if let Some(page) = cursor.leaf_page() {
+                match leaves.last_mut() {
+                    Some((last, keys)) if *last == page => keys.push(key(&cursor)),
+                    _ => leaves.push((page, vec![key(&cursor)])),
+                }
+            }
+            more = cursor.next().unwrap();
+        }
+        leaves
+    }
+
+    #[test]
+    fn test_range_scans_visit_overlapping_pages() {
+        let path = std::env::temp_dir().join(format!("scan-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, k TEXT, w TEXT, v TEXT); \
+                      CREATE INDEX t_k ON t(k, w); \
+                      WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000) \
+                      INSERT INTO t SELECT i, printf('%05d', i), printf('%.*c', 80, 'w'), \
+                      printf('%.*c', 300, 'v') FROM n;";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let table_leaves = leaves(&db, db.schema().tables["t"].root_page, |cursor| {
+            Record::I64(cursor.rowid().unwrap())
+        });
+        let index_leaves = leaves(&db, db.schema().indices["t_k"].root_page, |cursor| {
+            cursor.payload().unwrap().read_record().unwrap()[0].clone()
+        });
+        let text = |key: &Record| key.as_str().unwrap().to_string();
+
+        let check =
+            |sql: &str, plan_line: &str, leaves: &Leaves, in_range: &dyn Fn(&Record) -> bool| {
+                let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+                let plan = QueryExecutor::explain_query(&query, &db).unwrap();
+                assert_eq!(
+                    Record::String(String::from(plan_line)),
+                    plan[1][0],
+                    "{}",
+                    sql
+                );
+
+                db.pager.take_read_pages();
+                let rows = QueryExecutor::execute_query(&query, &db)
+                    .unwrap()
+                    .map(|row| {
+                        let row = row.unwrap();
+                        format!("{}|{}\n", row[0], row[1])
+                    })
+                    .collect::<String>();
+                assert_eq!(sqlite3(&path, sql), Some(rows), "{}", sql);
+
+                // The leaves holding keys in the range are read, and at most the one on either
+                // side of them, where the seek lands or the walk finds the end of the range.
+                let read = db.pager.take_read_pages();
+                let overlapping = leaves
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, keys))| keys.iter().any(in_range))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                let (first, last) = (overlapping[0], overlapping[overlapping.len() - 1]);
+                let allowed = &leaves[first.saturating_sub(1)..leaves.len().min(last + 2)];
+                assert!(allowed.len() * 4 < leaves.len(), "{}", sql);
+                for (i, (page, _)) in leaves.iter().enumerate() {
+                    let allowed = allowed.iter().any(|(allowed, _)| allowed == page);
+                    assert!(!read.contains(page) || allowed, "{}: read leaf {}", sql, i);
+                    assert!(read.contains(page) || !overlapping.contains(&i), "{}", sql);
+                }
+            };
+
+        check(
+            "SELECT id, k FROM t WHERE id > 1900",
+            "`--SEARCH t USING INTEGER PRIMARY KEY (rowid>?)",
+            &table_leaves,
+            &|key| key.as_int().unwrap() > 1900,
+        );
+        check(
+            "SELECT id, k FROM t WHERE k >= '01000' AND k <= '01100'",
+            "`--SEARCH t USING COVERING INDEX t_k (k>? AND k<?)",
+            &index_leaves,
+            &|key| ("01000".."01101").contains(&text(key).as_str()),
+        );
+        check(
+            "SELECT id, k FROM t WHERE k > '01000' AND k < '01100'",
+            "`--SEARCH t USING COVERING INDEX t_k (k>? AND k<?)",
+            &index_leaves,
+            &|key| ("01001".."01100").contains(&text(key).as_str()),
+        );
+        check(
+            "SELECT id, k FROM t WHERE k BETWEEN '00500' AND '00600'",
+            "`--SEARCH t USING COVERING INDEX t_k (k>? AND k<?)",
+            &index_leaves,
+            &|key| ("00500".."00601").contains(&text(key).as_str()),
+        );
+        check(
+            "SELECT id, k FROM t WHERE k LIKE '012%'",
+            "`--SEARCH t USING COVERING INDEX t_k (k>? AND k<?)",
+            &index_leaves,
+            &|key| text(key).starts_with("012"),
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_join_reads_inner_table_as_far_as_the_limit() {
+        let path = std::env::temp_dir().join(format!("join-limit-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(x); INSERT INTO t VALUES (1), (2), (3); \
+                      CREATE TABLE big(i, y); \
+                      WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000) \
+                      INSERT INTO big SELECT i, printf('%.*c', 300, 'y') FROM n;";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let big_leaves = leaves(&db, db.schema().tables["big"].root_page, |cursor| {
+            Record::I64(cursor.rowid().unwrap())
+        });
+        // The inner table is scanned again for every row of the outer one, only as far as the
+        // rows pulled.
+        for sql in [
+            "SELECT x, i FROM t CROSS JOIN big LIMIT 1",
+            "SELECT x, i FROM t CROSS JOIN big WHERE x > 1 LIMIT 3",
+        ] {
+            let query = Query::from_select(Parser::parse_select(sql).unwrap()).unwrap();
+            db.pager.take_read_pages();
+            let rows = QueryExecutor::execute_query(&query, &db)
+                .unwrap()
+                .map(|row| {
+                    let row = row.unwrap();
+                    format!("{}|{}\n", row[0], row[1])
+                })
+                .collect::<String>();
+            assert_eq!(sqlite3(&path, sql), Some(rows), "{}", sql);
+            let read = db.pager.take_read_pages();
+            let read_leaves = big_leaves
+                .iter()
+                .filter(|(page, _)| read.contains(page))
+                .count();
+            assert_eq!(1, read_leaves, "{}", sql);
         }
+        fs::remove_file(&path).unwrap();
     }
 }
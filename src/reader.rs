@@ -1,3 +1,5 @@
+use crate::common::Error;
+
 #[derive(Debug, Clone)]
 pub(crate) struct Reader<'a, T> {
     slice: &'a [T],
@@ -8,64 +10,114 @@ impl<'a, T> Reader<'a, T> {
         Self { slice }
     }
 
-    pub(crate) fn peek(&self, len: usize) -> &[T] {
-        &self.slice[..len]
+    pub(crate) fn peek(&self, len: usize) -> Result<&'a [T], Error> {
+        self.slice.get(..len).ok_or_else(|| self.overrun(len))
     }
 
-    pub(crate) fn pop(&mut self, len: usize) -> &[T] {
-        let out = &self.slice[..len];
+    pub(crate) fn pop(&mut self, len: usize) -> Result<&'a [T], Error> {
+        let out = self.peek(len)?;
         self.slice = &self.slice[len..];
-        out
+        Ok(out)
     }
 
-    pub(crate) fn at(&self, at: usize) -> Self {
-        Self {
-            slice: &self.slice[at..],
-        }
+    pub(crate) fn at(&self, at: usize) -> Result<Self, Error> {
+        Ok(Self {
+            slice: self.slice.get(at..).ok_or_else(|| self.overrun(at))?,
+        })
     }
 
     pub(crate) fn len(&self) -> usize {
         self.slice.len()
     }
+
+    /// A file that ends a value or a cell early is corrupt, as is an offset past its page.
+    fn overrun(&self, len: usize) -> Error {
+        Error::corrupt(format!(
+            "{} bytes needed where {} are left",
+            len,
+            self.slice.len()
+        ))
+    }
 }
 
 impl<'a> Reader<'a, u8> {
-    pub(crate) fn peek_u16(&self) -> u16 {
-        u16::from_be_bytes(self.slice[..2].try_into().expect("Casting to 2 bytes"))
+    fn peek_array<const N: usize>(&self) -> Result<[u8; N], Error> {
+        Ok(self.peek(N)?.try_into().expect("Casting to N bytes"))
+    }
+
+    fn pop_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.pop(N)?.try_into().expect("Casting to N bytes"))
+    }
+
+    pub(crate) fn peek_u16(&self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.peek_array()?))
+    }
+
+    pub(crate) fn pop_i16(&mut self) -> Result<i16, Error> {
+        Ok(i16::from_be_bytes(self.pop_array()?))
+    }
+
+    pub(crate) fn pop_i24(&mut self) -> Result<i32, Error> {
+        let bytes = self.pop(3)?;
+        // Sign-extend from the most significant byte.
+        let mut buf = if bytes[0] & 0x80 != 0 {
+            [0xff; 4]
+        } else {
+            [0; 4]
+        };
+        buf[1..].copy_from_slice(bytes);
+        Ok(i32::from_be_bytes(buf))
+    }
+
+    pub(crate) fn pop_i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.pop_array()?))
     }
 
-    pub(crate) fn peek_i16(&self) -> i16 {
-        i16::from_be_bytes(self.slice[..2].try_into().expect("Casting to 2 bytes"))
+    pub(crate) fn pop_i48(&mut self) -> Result<i64, Error> {
+        let bytes = self.pop(6)?;
+        // Sign-extend from the most significant byte.
+        let mut buf = if bytes[0] & 0x80 != 0 {
+            [0xff; 8]
+        } else {
+            [0; 8]
+        };
+        buf[2..].copy_from_slice(bytes);
+        Ok(i64::from_be_bytes(buf))
     }
 
-    pub(crate) fn pop_i16(&mut self) -> i16 {
-        i16::from_be_bytes(self.pop(2).try_into().expect("Casting to 2 bytes"))
+    pub(crate) fn pop_i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_be_bytes(self.pop_array()?))
     }
 
-    pub(crate) fn pop_i24(&mut self) -> i32 {
-        let mut bytes = self.pop(3).to_vec();
-        bytes.insert(0, 0);
-        i32::from_be_bytes(bytes[..].try_into().expect("Casting to 2 bytes"))
+    pub(crate) fn pop_f64(&mut self) -> Result<f64, Error> {
+        Ok(f64::from_be_bytes(self.pop_array()?))
     }
 
-    pub(crate) fn pop_i32(&mut self) -> i32 {
-        i32::from_be_bytes(self.pop(4).try_into().expect("Casting to 4 bytes"))
+    pub(crate) fn peek_i32(&self) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.peek_array()?))
     }
 
-    pub(crate) fn peek_i32(&self) -> i32 {
-        i32::from_be_bytes(self.slice[..4].try_into().expect("Casting to 4 bytes"))
+    pub(crate) fn peek_i8(&self) -> Result<i8, Error> {
+        Ok(self.peek_u8()? as i8)
     }
 
-    pub(crate) fn peek_i8(&self) -> i8 {
-        self.slice[0] as i8
+    pub(crate) fn peek_u8(&self) -> Result<u8, Error> {
+        Ok(self.peek(1)?[0])
     }
 
-    pub(crate) fn pop_varint(&mut self) -> i64 {
+    pub(crate) fn pop_varint(&mut self) -> Result<i64, Error> {
         let mut out = 0;
 
         let mut i = 0;
         loop {
-            let byte = self.pop(1)[0];
+            let byte = self.pop(1)?[0];
+
+            // The ninth byte contributes all of its eight bits.
+            if i == 8 {
+                out <<= 8;
+                out |= byte as i64;
+                break;
+            }
 
             out <<= 7;
             out |= (byte & 0b0111_1111) as i64;
@@ -75,16 +127,13 @@ impl<'a> Reader<'a, u8> {
             }
 
             i += 1;
-            if i >= 9 {
-                panic!("Varint overflow");
-            }
         }
 
-        out
+        Ok(out)
     }
 
-    pub(crate) fn pop_str(&mut self, len: usize) -> String {
-        let bytes = self.pop(len);
-        String::from_utf8_lossy(bytes).to_string()
+    pub(crate) fn pop_str(&mut self, len: usize) -> Result<String, Error> {
+        let bytes = self.pop(len)?;
+        Ok(String::from_utf8_lossy(bytes).to_string())
     }
 }
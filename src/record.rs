@@ -1,19 +1,30 @@
-use regex::Regex;
+use crate::{common::Error, evaluator::ConstantContext, parser::Parser, reader::Reader};
 
-use crate::reader::Reader;
-
-#[derive(Debug)]
-pub(crate) enum Record {
+/// A value as stored in a record, integers keeping the width they were stored with.
+#[derive(Debug, Clone)]
+pub enum Record {
     String(String),
     I8(i8),
     I16(i16),
     I24(i32),
     I32(i32),
     I64(i64),
+    Real(f64),
+    Blob(Vec<u8>),
     Null,
 }
 
 impl Record {
+    /// Reads a value the way the sqlite3 shell reads `.parameter set` values: as a constant
+    /// SQL expression such as `42`, `'text'`, `x'00ff'` or `NULL`, and as text when it isn't
+    /// one.
+    pub fn parse(text: &str) -> Self {
+        Parser::parse_expr(text)
+            .and_then(|expr| expr.eval(&ConstantContext))
+            .unwrap_or_else(|_| Self::String(text.to_string()))
+    }
+
+    #[cfg(test)]
     pub(crate) fn unwrap_string(&self) -> &String {
         match self {
             Self::String(s) => s,
@@ -21,29 +32,6 @@ impl Record {
         }
     }
 
-    pub(crate) fn unwrap_usize(&self) -> usize {
-        match self {
-            Self::I8(v) => *v as usize,
-            Self::I16(v) => *v as usize,
-            Self::I24(v) => *v as usize,
-            Self::I32(v) => *v as usize,
-            Self::I64(v) => *v as usize,
-            _ => panic!("Expected numeric field"),
-        }
-    }
-
-    pub(crate) fn parse(raw: &str) -> Self {
-        if Regex::new(r#""[^"]*""#).unwrap().is_match(raw) {
-            Record::String(raw[1..raw.len() - 1].to_string())
-        } else if Regex::new(r#"'[^']*'"#).unwrap().is_match(raw) {
-            Record::String(raw[1..raw.len() - 1].to_string())
-        } else if Regex::new(r"\d+").unwrap().is_match(raw) {
-            Record::I64(i64::from_str_radix(raw, 10).unwrap())
-        } else {
-            panic!("Unrecognized value: {}", raw);
-        }
-    }
-
     pub(crate) fn as_int(&self) -> Option<i64> {
         match self {
             Self::I8(v) => Some(*v as i64),
@@ -55,6 +43,14 @@ impl Record {
         }
     }
 
+    /// Numeric value of integer and real records.
+    pub(crate) fn as_real(&self) -> Option<f64> {
+        match self {
+            Self::Real(v) => Some(*v),
+            other => other.as_int().map(|v| v as f64),
+        }
+    }
+
     pub(crate) fn as_str(&self) -> Option<&str> {
         match self {
             Self::String(s) => Some(s),
@@ -62,48 +58,314 @@ impl Record {
         }
     }
 
-    pub(crate) fn to_string(&self) -> String {
+    pub(crate) fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            Self::Blob(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+
+    /// The storage class of the value, as `typeof()` names it.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Real(_) => "real",
+            Self::String(_) => "text",
+            Self::Blob(_) => "blob",
+            _ => "integer",
+        }
+    }
+
+    /// Truth value of the record in a boolean context, `None` for NULL.
+    pub(crate) fn truthiness(&self) -> Option<bool> {
+        match self {
+            Self::Null => None,
+            Self::Real(v) => Some(*v != 0.0),
+            other => match other.to_numeric() {
+                Self::Real(v) => Some(v != 0.0),
+                numeric => Some(numeric.as_int() != Some(0)),
+            },
+        }
+    }
+
+    /// Numeric value as used by arithmetic: text and blobs are converted from their longest
+    /// numeric prefix, defaulting to 0.
+    pub(crate) fn to_numeric(&self) -> Record {
+        match self {
+            Self::String(_) | Self::Blob(_) => {
+                let text = self.to_string();
+                let text = text.trim_start();
+                let prefix_len = numeric_prefix_len(text);
+                Self::parse_number(&text[..prefix_len]).unwrap_or(Record::I64(0))
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Applies NUMERIC affinity: text that looks like a number is converted, anything else is
+    /// kept as-is.
+    pub(crate) fn with_numeric_affinity(self) -> Record {
+        match &self {
+            Self::String(s) => Self::parse_number(s.trim()).unwrap_or(self),
+            _ => self,
+        }
+    }
+
+    /// Applies TEXT affinity: numbers are rendered to their text representation.
+    pub(crate) fn with_text_affinity(self) -> Record {
+        match &self {
+            Self::String(_) | Self::Blob(_) | Self::Null => self,
+            other => Self::String(other.to_string()),
+        }
+    }
+
+    fn parse_number(text: &str) -> Option<Record> {
+        if let Ok(v) = text.parse::<i64>() {
+            return Some(Record::I64(v));
+        }
+        if text.is_empty()
+            || !text
+                .chars()
+                .all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+        {
+            return None;
+        }
+        text.parse::<f64>().ok().map(Record::Real)
+    }
+
+    /// The serial type describing how the value is stored in a record. Integers take the
+    /// smallest size that holds them.
+    fn serial_type(&self) -> i64 {
         match self {
-            Self::String(v) => v.clone(),
-            Self::I8(v) => v.to_string(),
-            Self::I16(v) => v.to_string(),
-            Self::I24(v) => v.to_string(),
-            Self::I32(v) => v.to_string(),
-            Self::I64(v) => v.to_string(),
-            Self::Null => String::from("NULL"),
+            Self::Null => 0,
+            Self::Real(_) => 7,
+            Self::String(s) => s.len() as i64 * 2 + 13,
+            Self::Blob(v) => v.len() as i64 * 2 + 12,
+            other => {
+                let v = other.as_int().unwrap();
+                match v {
+                    -0x80..0x80 => 1,
+                    -0x8000..0x8000 => 2,
+                    -0x80_0000..0x80_0000 => 3,
+                    -0x8000_0000..0x8000_0000 => 4,
+                    -0x8000_0000_0000..0x8000_0000_0000 => 5,
+                    _ => 6,
+                }
+            }
+        }
+    }
+
+    /// Appends the value as stored in the body of a record, the inverse of
+    /// `RecordFormat::pop_value`.
+    fn write_body(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Null => {}
+            Self::Real(v) => out.extend_from_slice(&v.to_be_bytes()),
+            Self::String(s) => out.extend_from_slice(s.as_bytes()),
+            Self::Blob(v) => out.extend_from_slice(v),
+            other => {
+                let len = RecordFormat::from(self.serial_type())
+                    .expect("Serial type of an integer")
+                    .byte_len();
+                let bytes = other.as_int().unwrap().to_be_bytes();
+                out.extend_from_slice(&bytes[8 - len..]);
+            }
+        }
+    }
+
+    /// Rank of the storage class in SQLite's sort order: NULL < INTEGER/REAL < TEXT < BLOB.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Self::Null => 0,
+            Self::I8(_) | Self::I16(_) | Self::I24(_) | Self::I32(_) | Self::I64(_) => 1,
+            Self::Real(_) => 1,
+            Self::String(_) => 2,
+            Self::Blob(_) => 3,
         }
     }
 }
 
-impl PartialEq for Record {
-    fn eq(&self, other: &Self) -> bool {
-        match (self.as_int(), other.as_int()) {
-            (Some(a), Some(b)) => return a.eq(&b),
-            _ => {}
+/// Length of the longest prefix of `text` that forms a number, e.g. `12.5e3` in `12.5e3abc`.
+fn numeric_prefix_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+        i += 1;
+    }
+    let digits_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    if i < bytes.len() && bytes[i] == b'.' {
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
         }
+    }
+    if i == digits_start || (i == digits_start + 1 && bytes[digits_start] == b'.') {
+        return 0;
+    }
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+            j += 1;
+        }
+        if j < bytes.len() && bytes[j].is_ascii_digit() {
+            while j < bytes.len() && bytes[j].is_ascii_digit() {
+                j += 1;
+            }
+            i = j;
+        }
+    }
+    i
+}
+
+/// Formats a REAL the way sqlite3 does (`%!.15g`): 15 significant digits, always with a decimal
+/// point, switching to exponent notation for very large or very small magnitudes.
+fn format_real(v: f64) -> String {
+    if v.is_nan() {
+        return String::from("NaN");
+    }
+    if v.is_infinite() {
+        return String::from(if v > 0.0 { "Inf" } else { "-Inf" });
+    }
+
+    let scientific = format!("{:.14e}", v);
+    let (mantissa, exp) = scientific
+        .split_once('e')
+        .expect("Exponent in scientific format");
+    let exp = exp.parse::<i32>().expect("Numeric exponent");
 
-        match (self.as_str(), other.as_str()) {
-            (Some(a), Some(b)) => return a.eq(b),
-            _ => {}
+    let trim = |digits: &str| -> String {
+        if digits.contains('.') {
+            let digits = digits.trim_end_matches('0');
+            if let Some(digits) = digits.strip_suffix('.') {
+                format!("{}.0", digits)
+            } else {
+                digits.to_string()
+            }
+        } else {
+            format!("{}.0", digits)
         }
+    };
 
-        false
+    if !(-4..15).contains(&exp) {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exp.abs())
+    } else {
+        trim(&format!("{:.*}", (14 - exp) as usize, v))
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(v) => write!(f, "{}", v),
+            Self::I8(v) => write!(f, "{}", v),
+            Self::I16(v) => write!(f, "{}", v),
+            Self::I24(v) => write!(f, "{}", v),
+            Self::I32(v) => write!(f, "{}", v),
+            Self::I64(v) => write!(f, "{}", v),
+            Self::Real(v) => write!(f, "{}", format_real(*v)),
+            Self::Blob(v) => write!(f, "{}", String::from_utf8_lossy(v)),
+            Self::Null => write!(f, "NULL"),
+        }
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(std::cmp::Ordering::Equal)
     }
 }
 
 impl PartialOrd for Record {
+    /// Orders values following SQLite's rules: NULLs first, then numbers (integers and reals
+    /// compared by value), then text (byte-wise), then blobs (`memcmp`).
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.as_int(), other.as_int()) {
-            (Some(a), Some(b)) => return a.partial_cmp(&b),
-            _ => {}
+        let rank_order = self.type_rank().cmp(&other.type_rank());
+        if rank_order.is_ne() {
+            return Some(rank_order);
+        }
+
+        if let (Some(a), Some(b)) = (self.as_int(), other.as_int()) {
+            return a.partial_cmp(&b);
         }
 
-        match (self.as_str(), other.as_str()) {
-            (Some(a), Some(b)) => return a.partial_cmp(b),
-            _ => {}
+        if let (Some(a), Some(b)) = (self.as_real(), other.as_real()) {
+            return a.partial_cmp(&b);
         }
 
-        None
+        if let (Some(a), Some(b)) = (self.as_str(), other.as_str()) {
+            return a.partial_cmp(b);
+        }
+
+        if let (Some(a), Some(b)) = (self.as_blob(), other.as_blob()) {
+            return a.partial_cmp(b);
+        }
+
+        // Both NULL.
+        Some(std::cmp::Ordering::Equal)
+    }
+}
+
+/// Serializes values into a record: a header of serial types, itself prefixed by the header
+/// size, followed by the values.
+///
+/// See "Record Format" in https://www.sqlite.org/fileformat.html.
+pub(crate) fn encode_record(values: &[Record]) -> Vec<u8> {
+    let mut types = vec![];
+    let mut body = vec![];
+    for value in values {
+        encode_varint(value.serial_type() as u64, &mut types);
+        value.write_body(&mut body);
+    }
+
+    // The header size counts the bytes of its own varint.
+    let mut header_size = types.len() + 1;
+    while varint_len(header_size as u64) + types.len() > header_size {
+        header_size += 1;
+    }
+
+    let mut record = Vec::with_capacity(header_size + body.len());
+    encode_varint(header_size as u64, &mut record);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+/// Appends a big-endian varint of 1 to 9 bytes: seven bits per byte with the high bit set on
+/// all but the last, and a ninth byte holding eight bits.
+pub(crate) fn encode_varint(v: u64, out: &mut Vec<u8>) {
+    if v >> 56 != 0 {
+        let mut v = v;
+        let mut bytes = [0; 9];
+        bytes[8] = v as u8;
+        v >>= 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (v & 0x7f) as u8 | 0x80;
+            v >>= 7;
+        }
+        out.extend_from_slice(&bytes);
+        return;
+    }
+
+    let len = varint_len(v);
+    for i in (0..len).rev() {
+        let byte = ((v >> (7 * i)) & 0x7f) as u8;
+        out.push(if i == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+pub(crate) fn varint_len(v: u64) -> usize {
+    if v >> 56 != 0 {
+        9
+    } else {
+        (64 - v.leading_zeros() as usize).div_ceil(7).max(1)
     }
 }
 
@@ -114,13 +376,14 @@ pub(crate) enum RecordFormat {
     Float64,
     Zero,
     One,
+    Reserved,
     Blob(usize),
     String(usize),
 }
 
 impl RecordFormat {
-    pub(crate) fn from(v: i64) -> Self {
-        match v {
+    pub(crate) fn from(v: i64) -> Result<Self, Error> {
+        Ok(match v {
             0 => Self::Null,
             1 => Self::TwoCompInt(1),
             2 => Self::TwoCompInt(2),
@@ -131,7 +394,9 @@ impl RecordFormat {
             7 => Self::Float64,
             8 => Self::Zero,
             9 => Self::One,
-            10 | 11 => panic!("Not supported"),
+            // Reserved for internal use, never appears in a well-formed database file.
+            10 | 11 => Self::Reserved,
+            ..0 => return Err(Error::corrupt(format!("invalid serial type {}", v))),
             other => {
                 if other % 2 == 0 {
                     Self::Blob((other as usize - 12) / 2)
@@ -139,31 +404,136 @@ impl RecordFormat {
                     Self::String((other as usize - 13) / 2)
                 }
             }
-        }
+        })
     }
 
     pub(crate) fn byte_len(&self) -> usize {
         match self {
             Self::Blob(len) | Self::String(len) => *len,
             Self::Float64 => 8,
-            Self::Null | Self::Zero | Self::One => 0,
+            Self::Null | Self::Zero | Self::One | Self::Reserved => 0,
             Self::TwoCompInt(n) => *n as usize,
         }
     }
 
-    pub(crate) fn pop_value(&self, reader: &mut Reader<'_, u8>) -> Record {
-        match self {
-            Self::String(len) => Record::String(reader.pop_str(*len)),
-            Self::Null => Record::Null,
+    pub(crate) fn pop_value(&self, reader: &mut Reader<'_, u8>) -> Result<Record, Error> {
+        Ok(match self {
+            Self::String(len) => Record::String(reader.pop_str(*len)?),
+            Self::Blob(len) => Record::Blob(reader.pop(*len)?.to_vec()),
+            Self::Null | Self::Reserved => Record::Null,
             Self::One => Record::I8(1),
             Self::Zero => Record::I8(0),
+            Self::Float64 => Record::Real(reader.pop_f64()?),
             Self::TwoCompInt(byte_len) => match byte_len {
-                1 => Record::I8(reader.pop(1)[0] as i8),
-                2 => Record::I16(reader.pop_i16()),
-                3 => Record::I24(reader.pop_i24()),
-                other => unimplemented!("Two comp int fetch for size {} not implemented", other),
+                1 => Record::I8(reader.pop(1)?[0] as i8),
+                2 => Record::I16(reader.pop_i16()?),
+                3 => Record::I24(reader.pop_i24()?),
+                4 => Record::I32(reader.pop_i32()?),
+                6 => Record::I64(reader.pop_i48()?),
+                8 => Record::I64(reader.pop_i64()?),
+                other => unreachable!("Invalid two comp int size: {}", other),
             },
-            other => unimplemented!("Record fetch not implemented for: {:?}", other),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        reader::Reader,
+        record::{Record, RecordFormat, encode_record, encode_varint, varint_len},
+    };
+
+    #[test]
+    fn test_pop_value_all_serial_types() {
+        let bytes = [
+            0xff, 0xff, 0xfe, // 24-bit -2
+            0x80, 0x00, 0x00, 0x00, 0x00, 0x00, // 48-bit min
+            0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // 64-bit
+            0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 1.5
+            0x01, 0x02, 0x03, // blob
+        ];
+        let mut reader = Reader::new(&bytes[..]);
+
+        let values = [3, 5, 6, 7, 18, 10]
+            .into_iter()
+            .map(|serial_type| {
+                RecordFormat::from(serial_type)
+                    .unwrap()
+                    .pop_value(&mut reader)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(Some(-2), values[0].as_int());
+        assert_eq!(Some(-(1 << 47)), values[1].as_int());
+        assert_eq!(Some(9007199254740993), values[2].as_int());
+        assert_eq!(Some(1.5), values[3].as_real());
+        assert_eq!(Some(&[1u8, 2, 3][..]), values[4].as_blob());
+        assert!(values[5].is_null());
+    }
+
+    #[test]
+    fn test_encode_record_round_trip() {
+        let values = vec![
+            Record::Null,
+            Record::I64(100),
+            Record::I64(-40_000),
+            Record::I64(1 << 40),
+            Record::I64(i64::MIN),
+            Record::Real(-2.5),
+            Record::String("x".repeat(200)),
+            Record::Blob(vec![7, 8]),
+        ];
+        let bytes = encode_record(&values);
+        // The header size counts itself, and the 200-byte string takes a two-byte serial type.
+        assert_eq!(&[10, 0, 1, 3, 5, 6, 7, 0x83, 0x1d, 16][..], &bytes[..10]);
+
+        let mut reader = Reader::new(&bytes[..]);
+        let header_size = reader.pop_varint().unwrap() as usize;
+        let formats = (0..values.len())
+            .map(|_| RecordFormat::from(reader.pop_varint().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(bytes.len() - reader.len(), header_size);
+        let decoded = formats
+            .iter()
+            .map(|format| format.pop_value(&mut reader).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, decoded);
+
+        for v in [0, 127, 128, 1 << 56, u64::MAX] {
+            let mut out = vec![];
+            encode_varint(v, &mut out);
+            assert_eq!(varint_len(v), out.len());
+            assert_eq!(v as i64, Reader::new(&out[..]).pop_varint().unwrap());
         }
     }
+
+    #[test]
+    fn test_record_ordering() {
+        let sorted = [
+            Record::Null,
+            Record::I64(-5),
+            Record::Real(1.5),
+            Record::I8(2),
+            Record::String(String::from("10")),
+            Record::String(String::from("abc")),
+            Record::Blob(vec![0]),
+        ];
+
+        for pair in sorted.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} < {:?}", pair[0], pair[1]);
+        }
+        assert_eq!(Record::I8(3), Record::Real(3.0));
+        assert_ne!(Record::I8(3), Record::String(String::from("3")));
+    }
+
+    #[test]
+    fn test_real_to_string() {
+        assert_eq!("1.0", Record::Real(1.0).to_string());
+        assert_eq!("-3.25", Record::Real(-3.25).to_string());
+        assert_eq!("0.1", Record::Real(0.1).to_string());
+        assert_eq!("1.0e+20", Record::Real(1e20).to_string());
+        assert_eq!("1.2345e-05", Record::Real(0.000012345).to_string());
+    }
 }
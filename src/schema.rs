@@ -1,31 +1,137 @@
-use std::{collections::HashMap, panic};
+use std::{cmp::Ordering, collections::HashMap};
 
-use log::debug;
 use regex::Regex;
 
-use crate::{common::Incrementer, record::Record};
+use crate::{ast::Expr, common::Error, parser::Parser, record::Record};
+
+/// Names the rowid of a table can be read by, unless a column of the table has that name.
+pub(crate) const ROWID_NAMES: [&str; 3] = ["rowid", "_rowid_", "oid"];
+
+/// The keywords starting a column constraint, which can't start a type name.
+const COLUMN_CONSTRAINTS: [&str; 10] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+];
 
 #[derive(Debug)]
 pub(crate) enum TableFieldKind {
     Int { auto_increment: bool },
     Text,
+    Real,
+    Blob,
+    Numeric,
 }
 
 impl TableFieldKind {
+    /// Determines the column affinity from the declared type name.
+    /// See "Determination Of Column Affinity" in https://www.sqlite.org/datatype3.html.
     fn from(raw: &str) -> Self {
-        match raw.to_lowercase().as_str() {
-            "integer" => Self::Int {
+        let raw = raw.to_uppercase();
+        if raw.contains("INT") {
+            Self::Int {
                 auto_increment: false,
-            },
-            "text" | "varchar" => Self::Text,
-            other => unimplemented!("Field type {} not recognized", other),
+            }
+        } else if raw.contains("CHAR") || raw.contains("CLOB") || raw.contains("TEXT") {
+            Self::Text
+        } else if raw.contains("BLOB") || raw.is_empty() {
+            Self::Blob
+        } else if raw.contains("REAL") || raw.contains("FLOA") || raw.contains("DOUB") {
+            Self::Real
+        } else {
+            Self::Numeric
         }
     }
 
-    fn to_auto_increment(&mut self) {
+    fn set_auto_increment(&mut self) -> Result<(), Error> {
         match self {
             Self::Int { auto_increment } => *auto_increment = true,
-            _ => panic!("Auto incement can only be set on integer field"),
+            _ => return Err("AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY".into()),
+        }
+        Ok(())
+    }
+}
+
+/// Column type affinity, see https://www.sqlite.org/datatype3.html.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Affinity {
+    Integer,
+    Text,
+    Real,
+    Blob,
+    Numeric,
+}
+
+impl Affinity {
+    /// Converts a value compared against a column of this affinity, as done for the operand
+    /// without affinity in a comparison.
+    pub(crate) fn apply(self, value: Record) -> Record {
+        match self {
+            Affinity::Integer | Affinity::Real | Affinity::Numeric => value.with_numeric_affinity(),
+            Affinity::Text => value.with_text_affinity(),
+            Affinity::Blob => value,
+        }
+    }
+
+    /// Converts a value stored in a column of this affinity. Numeric columns turn reals with
+    /// no fractional part into integers, and REAL columns integers into reals.
+    pub(crate) fn coerce(self, value: Record) -> Record {
+        match (self, self.apply(value)) {
+            (Affinity::Integer | Affinity::Numeric, Record::Real(v))
+                if v.fract() == 0.0 && (-9.2e18..9.2e18).contains(&v) =>
+            {
+                Record::I64(v as i64)
+            }
+            (Affinity::Real, value) => match value.as_int() {
+                Some(v) => Record::Real(v as f64),
+                None => value,
+            },
+            (_, value) => value,
+        }
+    }
+}
+
+/// How text values are compared, named by `COLLATE`. See "Collating Sequences" in
+/// https://www.sqlite.org/datatype3.html.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Collation {
+    Binary,
+    /// Folds ASCII letters to lowercase.
+    NoCase,
+    /// Ignores trailing spaces.
+    RTrim,
+    /// A collation an application registered with sqlite, which can't be compared by.
+    Unknown(String),
+}
+
+impl Collation {
+    pub(crate) fn from(name: &str) -> Self {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Self::Binary,
+            "NOCASE" => Self::NoCase,
+            "RTRIM" => Self::RTrim,
+            _ => Self::Unknown(name.to_string()),
+        }
+    }
+
+    /// Orders two values, comparing text by the collation and anything else as usual.
+    pub(crate) fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        match (self, a, b) {
+            (Self::NoCase, Record::String(a), Record::String(b)) => a
+                .bytes()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(b.bytes().map(|c| c.to_ascii_lowercase())),
+            (Self::RTrim, Record::String(a), Record::String(b)) => {
+                a.trim_end_matches(' ').cmp(b.trim_end_matches(' '))
+            }
+            _ => a.partial_cmp(b).unwrap_or(Ordering::Equal),
         }
     }
 }
@@ -36,9 +142,47 @@ pub(crate) struct TableField {
     pub(crate) kind: TableFieldKind,
     pub(crate) primary_key: bool,
     pub(crate) allow_null: bool,
+    /// An `INTEGER PRIMARY KEY` column, holding the rowid rather than a stored value.
+    pub(crate) rowid_alias: bool,
+    /// The `DEFAULT` of the column, NULL when not given.
+    pub(crate) default: Option<Expr>,
+    pub(crate) collation: Collation,
 }
 
 impl TableField {
+    pub(crate) fn affinity(&self) -> Affinity {
+        match self.kind {
+            TableFieldKind::Int { .. } => Affinity::Integer,
+            TableFieldKind::Text => Affinity::Text,
+            TableFieldKind::Real => Affinity::Real,
+            TableFieldKind::Blob => Affinity::Blob,
+            TableFieldKind::Numeric => Affinity::Numeric,
+        }
+    }
+
+    /// A column of a derived table, holding values of the given affinity.
+    pub(crate) fn with_affinity(name: String, affinity: Affinity) -> Self {
+        let kind = match affinity {
+            Affinity::Integer => TableFieldKind::Int {
+                auto_increment: false,
+            },
+            Affinity::Text => TableFieldKind::Text,
+            Affinity::Real => TableFieldKind::Real,
+            Affinity::Blob => TableFieldKind::Blob,
+            Affinity::Numeric => TableFieldKind::Numeric,
+        };
+
+        Self {
+            name,
+            kind,
+            primary_key: false,
+            allow_null: true,
+            rowid_alias: false,
+            default: None,
+            collation: Collation::Binary,
+        }
+    }
+
     pub(crate) fn is_autoincrement(&self) -> bool {
         match self.kind {
             TableFieldKind::Int { auto_increment } => auto_increment,
@@ -49,186 +193,578 @@ impl TableField {
 
 #[derive(Debug)]
 pub(crate) struct TableSchema {
-    pub(crate) name: String,
     pub(crate) fields: Vec<TableField>,
+    /// The columns of the UNIQUE and PRIMARY KEY constraints, other than the rowid alias, in
+    /// the order sqlite numbers the indexes it creates for them: `sqlite_autoindex_<table>_1`
+    /// backs the first.
+    pub(crate) unique_constraints: Vec<Vec<String>>,
     field_index_cache: HashMap<String, usize>,
 }
 
 impl TableSchema {
-    fn new(name: String, fields: Vec<TableField>) -> Self {
+    pub(crate) fn new(fields: Vec<TableField>) -> Self {
         let mut field_index_cache = HashMap::new();
         for (i, field) in fields.iter().enumerate() {
-            field_index_cache.insert(field.name.clone(), i);
+            field_index_cache.insert(field.name.to_ascii_lowercase(), i);
         }
 
         Self {
-            name,
             fields,
+            unique_constraints: vec![],
             field_index_cache,
         }
     }
 
-    pub(crate) fn from(raw: &str) -> Self {
+    pub(crate) fn from(raw: &str) -> Result<Self, Error> {
         let table_regex = Regex::new(r#"(?is)CREATE\s+TABLE\s+"?(\w+)"?\s*\((.*)\)"#).unwrap();
 
         let caps = table_regex
             .captures(raw)
-            .expect(format!("Failed capturing schema def: {}", raw).as_str())
-            .iter()
-            .map(|elem| elem.unwrap())
-            .collect::<Vec<_>>();
-        if caps.len() != 3 {
-            panic!("Cannot find name and fields");
-        }
+            .ok_or_else(|| unsupported_sql(raw))?;
 
-        let name = caps[1].as_str();
+        let name = &caps[1];
         if name == "sqlite_sequence" {
-            return TableSchema::new(name.to_string(), vec![]);
+            return Ok(TableSchema::new(vec![]));
         }
 
-        let raw_fields_str = caps[2].as_str();
-        let raw_field_list = raw_fields_str
-            .split(',')
-            .map(|s| s.trim())
-            .collect::<Vec<_>>();
+        let raw_fields_str = &caps[2];
         let mut fields = vec![];
+        let mut primary_key_columns = vec![];
+        let mut integer_columns = vec![];
+        let mut unique_constraints = vec![];
+        let mut table_unique_constraints = vec![];
+
+        // The declared type is optional, as in `CREATE TABLE sqlite_stat1(tbl,idx,stat)`.
+        let field_re =
+            Regex::new(r#"^\s*((?:\")[^"]+(?:\")|[^ ]+)(?:\s+([^ ]+))?($|\s+.*)"#).unwrap();
+        let constraint_re = Regex::new(
+            r#"(?i)^(?:CONSTRAINT\s+\S+\s+)?(PRIMARY\s+KEY|UNIQUE|CHECK|FOREIGN\s+KEY)\b"#,
+        )
+        .unwrap();
+        let default_re = Regex::new(r#"(?i)\bDEFAULT\s+"#).unwrap();
+        let collate_re = Regex::new(r#"(?i)\bCOLLATE\s+"?(\w+)"?"#).unwrap();
+        for raw_field in Self::split_definitions(raw_fields_str) {
+            if let Some(constraint) = constraint_re.captures(raw_field) {
+                let kind = constraint[1].to_uppercase();
+                if kind.starts_with("PRIMARY") || kind == "UNIQUE" {
+                    let columns = Self::constraint_columns(raw_field);
+                    // A `PRIMARY KEY (column)` table constraint makes that column the primary key.
+                    if kind.starts_with("PRIMARY") {
+                        primary_key_columns = columns.clone();
+                    }
+                    table_unique_constraints.push(columns);
+                }
+                continue;
+            }
 
-        for raw_field in raw_field_list {
-            let field_re =
-                Regex::new(r#"^\s*((?:\")[^"]+(?:\")|[^ ]+)\s+([^ ]+)($|\s+.*)"#).unwrap();
             let caps = field_re
                 .captures(raw_field)
-                .expect("Failed capturing")
-                .iter()
-                .map(|elem| elem.unwrap())
-                .collect::<Vec<_>>();
+                .ok_or_else(|| unsupported_sql(raw_field))?;
 
-            let name = caps[1].as_str();
+            let name = &caps[1];
             let name = if name.starts_with('"') {
                 name[1..name.len() - 1].to_string()
             } else {
                 name.to_string()
             };
-            let mut kind = TableFieldKind::from(caps[2].as_str());
-
-            let suffix = caps[3].as_str();
+            // Without a declared type, as in `a UNIQUE`, the constraints follow the name.
+            let (declared_type, rest) = match caps.get(2) {
+                Some(m) if !COLUMN_CONSTRAINTS.contains(&m.as_str().to_uppercase().as_str()) => {
+                    (m.as_str(), &caps[3])
+                }
+                Some(m) => ("", &raw_field[m.start()..]),
+                None => ("", &caps[3]),
+            };
+            let mut kind = TableFieldKind::from(declared_type);
+
+            let default = default_re
+                .find(rest)
+                .map(|default| Self::parse_default(&rest[default.end()..]))
+                .transpose()?;
+            let collation = collate_re
+                .captures(rest)
+                .map_or(Collation::Binary, |caps| Collation::from(&caps[1]));
+            let suffix = rest.to_lowercase();
             let primary_key = suffix.contains("primary key");
             if suffix.contains("autoincrement") {
-                kind.to_auto_increment();
+                kind.set_auto_increment()?;
             }
             let allow_null = !suffix.contains("not null");
+            // Only the exact type name `INTEGER` makes the rowid alias, and, for compatibility,
+            // not when declared `PRIMARY KEY DESC`.
+            let integer = declared_type.eq_ignore_ascii_case("INTEGER");
+            let rowid_alias = primary_key && integer && !suffix.contains("primary key desc");
+            if integer {
+                integer_columns.push(name.clone());
+            }
+
+            let constraints = [
+                suffix.find("primary key").filter(|_| !rowid_alias),
+                suffix.find("unique"),
+            ];
+            for _ in constraints.iter().flatten() {
+                unique_constraints.push(vec![name.clone()]);
+            }
 
             fields.push(TableField {
                 name,
                 kind,
                 primary_key,
                 allow_null,
+                rowid_alias,
+                default,
+                collation,
             });
         }
 
-        TableSchema::new(name.to_string(), fields)
+        let mut table_rowid_alias = None;
+        if let [column] = primary_key_columns.as_slice()
+            && let Some(field) = fields
+                .iter_mut()
+                .find(|field| field.name.eq_ignore_ascii_case(column))
+        {
+            field.primary_key = true;
+            field.rowid_alias = integer_columns
+                .iter()
+                .any(|name: &String| name.eq_ignore_ascii_case(column));
+            if field.rowid_alias {
+                table_rowid_alias = Some(primary_key_columns.clone());
+            }
+        }
+        for columns in table_unique_constraints {
+            if Some(&columns) != table_rowid_alias.as_ref() {
+                unique_constraints.push(columns);
+            }
+        }
+        // A constraint on the same columns as an earlier one shares its index.
+        let mut distinct: Vec<Vec<String>> = vec![];
+        for columns in unique_constraints {
+            if !distinct.contains(&columns) {
+                distinct.push(columns);
+            }
+        }
+
+        Ok(TableSchema {
+            unique_constraints: distinct,
+            ..TableSchema::new(fields)
+        })
     }
 
-    pub(crate) fn apply_rowid(&self, rowid: i64, row: &mut Vec<Record>) {
-        for (i, field) in self.fields.iter().enumerate() {
-            if field.is_autoincrement() && field.primary_key {
-                row[i] = Record::I64(rowid);
+    /// The column names listed in parentheses by a table constraint.
+    fn constraint_columns(raw: &str) -> Vec<String> {
+        let start = raw.find('(').map_or(0, |i| i + 1);
+        let end = raw.rfind(')').unwrap_or(raw.len()).max(start);
+        raw[start..end]
+            .split(',')
+            .filter_map(|column| column.split_whitespace().next())
+            .map(|column| column.trim_matches('"').to_string())
+            .collect()
+    }
+
+    /// Parses the expression following `DEFAULT`: a literal, possibly signed, or an expression
+    /// in parentheses.
+    fn parse_default(raw: &str) -> Result<Expr, Error> {
+        let raw = raw.trim_start();
+        let mut end = raw.len();
+        let mut depth = 0;
+        let mut quote = None;
+        for (i, c) in raw.char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => depth -= 1,
+                (None, c) if depth == 0 && c.is_whitespace() => {
+                    end = i;
+                    break;
+                }
+                _ => {}
+            }
+            if depth == 0 && quote.is_none() && c == ')' {
+                end = i + 1;
+                break;
+            }
+        }
+        Parser::parse_expr(&raw[..end])
+    }
+
+    /// Splits the column definitions and table constraints of `CREATE TABLE`, at the commas
+    /// outside parentheses.
+    fn split_definitions(raw: &str) -> Vec<&str> {
+        let mut definitions = vec![];
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in raw.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    definitions.push(raw[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
             }
         }
+        definitions.push(raw[start..].trim());
+        definitions
+    }
+
+    /// Completes a row read from the table b-tree: fills in the rowid alias column, whose
+    /// value isn't stored in the record, and appends the rowid itself.
+    pub(crate) fn apply_rowid(&self, rowid: i64, row: &mut Vec<Record>) {
+        if let Some(i) = self.rowid_alias() {
+            row[i] = Record::I64(rowid);
+        }
+        row.push(Record::I64(rowid));
     }
 
-    pub(crate) fn field_index(&self, name: &str) -> usize {
-        self.field_index_cache[name]
+    /// Index of the column aliasing the rowid.
+    pub(crate) fn rowid_alias(&self) -> Option<usize> {
+        self.fields.iter().position(|field| field.rowid_alias)
     }
 
-    pub(crate) fn make_incrementer_map(&self) -> HashMap<usize, Incrementer> {
-        let mut map = HashMap::new();
-        for (i, field) in self.fields.iter().enumerate() {
-            if field.is_autoincrement() {
-                map.insert(i, Incrementer::new(1));
-            }
+    /// Whether a column name refers to the rowid, either through the rowid alias column or one
+    /// of the rowid names no column has taken.
+    pub(crate) fn is_rowid_name(&self, name: &str) -> bool {
+        match self.find_field(name) {
+            Some(i) => self.rowid_alias() == Some(i),
+            None => ROWID_NAMES
+                .iter()
+                .any(|rowid_name| rowid_name.eq_ignore_ascii_case(name)),
         }
-        map
+    }
+
+    pub(crate) fn field_index(&self, name: &str) -> Result<usize, Error> {
+        self.find_field(name)
+            .ok_or_else(|| Error::NoSuchColumn(name.to_string()))
+    }
+
+    pub(crate) fn find_field(&self, name: &str) -> Option<usize> {
+        self.field_index_cache
+            .get(&name.to_ascii_lowercase())
+            .copied()
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct IndexField {
     pub(crate) field: String,
-    pub(super) ascending: bool,
+    pub(crate) ascending: bool,
+    /// The `COLLATE` of the index column, else that of the table column, filled in by
+    /// `inherit_collations` once the table is known.
+    pub(crate) collation: Option<Collation>,
+}
+
+impl IndexField {
+    pub(crate) fn collation(&self) -> &Collation {
+        self.collation.as_ref().unwrap_or(&Collation::Binary)
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct IndexSchema {
     pub(crate) name: String,
-    pub(crate) table: String,
     pub(crate) fields: Vec<IndexField>,
+    /// No two entries may have the same values, unless one of them is NULL.
+    pub(crate) unique: bool,
+    /// The WHERE clause of a partial index, which only has entries for the rows it holds for.
+    pub(crate) filter: Option<Expr>,
 }
 
 impl IndexSchema {
-    pub(crate) fn from(raw: &str) -> Self {
-        let table_regex =
-            Regex::new(r#"(?is)CREATE\s+INDEX\s+(\w+)\s+ON\s+(\w+)\s*\((.*)\)"#).unwrap();
+    pub(crate) fn from(raw: &str) -> Result<Self, Error> {
+        let table_regex = Regex::new(
+            r#"(?is)CREATE\s+(UNIQUE\s+)?INDEX\s+(?:IF\s+NOT\s+EXISTS\s+)?"?(\w+)"?\s+ON\s+"?(\w+)"?\s*\((.*)"#,
+        )
+        .unwrap();
 
         let caps = table_regex
             .captures(raw)
-            .expect(format!("Failed capturing schema def: {}", raw).as_str())
-            .iter()
-            .map(|elem| elem.unwrap())
-            .collect::<Vec<_>>();
-        if caps.len() != 4 {
-            panic!("Cannot find name, table and fields");
-        }
-
-        let name = caps[1].as_str().to_string();
-        let table = caps[2].as_str().to_string();
+            .ok_or_else(|| unsupported_sql(raw))?;
+
+        let unique = caps.get(1).is_some();
+        let name = caps[2].to_string();
+
+        // The column list ends at its closing parenthesis, which a WHERE clause may follow.
+        let rest = &caps[4];
+        let mut depth = 0;
+        let end = rest
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => return Some(i),
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                None
+            })
+            .ok_or_else(|| unsupported_sql(raw))?;
+        let (raw_fields_str, rest) = (&rest[..end], rest[end + 1..].trim());
+        let filter = match rest.get(..5) {
+            None if rest.is_empty() => None,
+            Some(keyword) if keyword.eq_ignore_ascii_case("WHERE") => {
+                Some(Parser::parse_expr(&rest[5..])?)
+            }
+            _ => return Err(unsupported_sql(raw)),
+        };
 
-        let raw_fields_str = caps[3].as_str();
-        let raw_field_list = raw_fields_str
-            .split(',')
-            .map(|s| s.trim())
-            .collect::<Vec<_>>();
+        let raw_field_list = TableSchema::split_definitions(raw_fields_str);
         let mut fields = vec![];
 
         for raw_field in raw_field_list {
-            let raw_field_parts = raw_field.split_whitespace().collect::<Vec<_>>();
+            let mut raw_field_parts = raw_field.split_whitespace();
 
-            let field = raw_field_parts[0].to_string();
-            let ascending = if raw_field_parts.len() > 1 {
-                raw_field_parts[1] == "ASC"
-            } else {
-                true
+            let Some(field) = raw_field_parts.next() else {
+                return Err(unsupported_sql(raw));
             };
+            let field = field.to_string();
+            let mut ascending = true;
+            let mut collation = None;
+            while let Some(part) = raw_field_parts.next() {
+                if part.eq_ignore_ascii_case("COLLATE") {
+                    let name = raw_field_parts.next().ok_or_else(|| unsupported_sql(raw))?;
+                    collation = Some(Collation::from(name.trim_matches('"')));
+                } else if part.eq_ignore_ascii_case("DESC") {
+                    ascending = false;
+                }
+            }
+
+            fields.push(IndexField {
+                field,
+                ascending,
+                collation,
+            });
+        }
+
+        Ok(Self {
+            name,
+            fields,
+            unique,
+            filter,
+        })
+    }
+
+    /// Orders the values of two entries in index order, as far as both have values.
+    pub(crate) fn compare(&self, a: &[Record], b: &[Record]) -> Ordering {
+        for ((field, a), b) in self.fields.iter().zip(a).zip(b) {
+            let ordering = field.collation().compare(a, b);
+            if ordering.is_ne() {
+                return if field.ascending {
+                    ordering
+                } else {
+                    ordering.reverse()
+                };
+            }
+        }
+        Ordering::Equal
+    }
 
-            fields.push(IndexField { field, ascending });
+    /// Gives the columns without a `COLLATE` of their own the collation of the table column.
+    pub(crate) fn inherit_collations(&mut self, table: &TableSchema) {
+        for field in &mut self.fields {
+            if field.collation.is_none() {
+                field.collation = table
+                    .find_field(&field.field)
+                    .map(|i| table.fields[i].collation.clone());
+            }
         }
+    }
+
+    /// The name of a collation the entries are ordered by that isn't known here.
+    pub(crate) fn unknown_collation(&self) -> Option<&str> {
+        self.fields
+            .iter()
+            .find_map(|field| match field.collation() {
+                Collation::Unknown(name) => Some(name.as_str()),
+                _ => None,
+            })
+    }
+
+    /// The index sqlite creates for a UNIQUE or PRIMARY KEY constraint, which has no SQL of its
+    /// own. Its columns are those of the constraint, in ascending order.
+    pub(crate) fn automatic(name: String, columns: &[String]) -> Self {
+        let fields = columns
+            .iter()
+            .map(|field| IndexField {
+                field: field.clone(),
+                ascending: true,
+                collation: None,
+            })
+            .collect();
 
         Self {
             name,
-            table,
             fields,
+            unique: true,
+            filter: None,
         }
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct ViewSchema {
+    /// Column names given after the view name, otherwise the columns are named by the select.
+    pub(crate) columns: Vec<String>,
+    /// The `SELECT` statement the view reads.
+    pub(crate) select: String,
+}
+
+impl ViewSchema {
+    pub(crate) fn from(raw: &str) -> Result<Self, Error> {
+        let view_regex = Regex::new(
+            r#"(?is)CREATE\s+(?:TEMP\s+|TEMPORARY\s+)?VIEW\s+(?:IF\s+NOT\s+EXISTS\s+)?(?:"[^"]+"|[^\s(]+)\s*(?:\(([^)]*)\))?\s*AS\s+(.*)"#,
+        )
+        .unwrap();
+
+        let caps = view_regex
+            .captures(raw)
+            .ok_or_else(|| unsupported_sql(raw))?;
+
+        let columns = caps.get(1).map_or(vec![], |columns| {
+            columns
+                .as_str()
+                .split(',')
+                .map(|column| column.trim().trim_matches('"').to_string())
+                .collect()
+        });
+
+        Ok(Self {
+            columns,
+            select: caps[2].to_string(),
+        })
+    }
+}
+
+/// The error for schema SQL outside of the subset the regular expressions here understand.
+fn unsupported_sql(raw: &str) -> Error {
+    Error::Unsupported(format!("the schema definition `{}`", raw))
+}
+
 #[cfg(test)]
 mod test {
-    use crate::schema::TableSchema;
+    use std::cmp::Ordering;
+
+    use crate::{
+        record::Record,
+        schema::{Collation, IndexSchema, TableFieldKind, TableSchema, ViewSchema},
+    };
 
     #[test]
     fn test_schema_from() {
         dbg!(TableSchema::from(
             "CREATE TABLE apples\n(\n\tid integer primary key autoincrement,\n\tname text,\n\tcolor text\n)"
-        ));
+        )
+        .unwrap());
 
-        dbg!(TableSchema::from("CREATE TABLE sqlite_sequence(name,seq)"));
+        dbg!(TableSchema::from("CREATE TABLE sqlite_sequence(name,seq)").unwrap());
 
         dbg!(TableSchema::from(
             "CREATE TABLE oranges\n(\n\tid integer primary key autoincrement,\n\tname text,\n\tdescription text\n)"
-        ));
+        )
+        .unwrap());
 
         dbg!(TableSchema::from(
             "CREATE TABLE oranges\n(\n\t\"id multiple words\" integer primary key autoincrement,\n\tname text,\n\tdescription text\n)"
-        ));
+        )
+        .unwrap());
+
+        let err = TableSchema::from("CREATE TABLE t(id text primary key autoincrement)");
+        assert_eq!(
+            "AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY",
+            err.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_rowid_alias() {
+        let alias = |sql| TableSchema::from(sql).unwrap().rowid_alias();
+
+        assert_eq!(
+            alias("CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT)"),
+            Some(0)
+        );
+        assert_eq!(
+            alias("CREATE TABLE t(name, id integer Primary Key)"),
+            Some(1)
+        );
+        assert_eq!(
+            alias("CREATE TABLE t(a text, k integer, PRIMARY KEY (k))"),
+            Some(1)
+        );
+        assert_eq!(
+            alias("CREATE TABLE t(a text, k bigint, PRIMARY KEY (k))"),
+            None
+        );
+        assert_eq!(alias("CREATE TABLE t(id INT PRIMARY KEY)"), None);
+        assert_eq!(alias("CREATE TABLE t(id INTEGER PRIMARY KEY DESC)"), None);
+        assert_eq!(
+            alias("CREATE TABLE t(a integer, b integer, PRIMARY KEY (a, b))"),
+            None
+        );
+
+        let schema = TableSchema::from("CREATE TABLE t(id integer primary key, oid text)").unwrap();
+        assert!(schema.is_rowid_name("id"));
+        assert!(schema.is_rowid_name("ROWID"));
+        assert!(!schema.is_rowid_name("oid"));
+    }
+
+    #[test]
+    fn test_unique_constraints() {
+        let schema = TableSchema::from(
+            "CREATE TABLE t(id INTEGER PRIMARY KEY, a UNIQUE, b TEXT NOT NULL UNIQUE, c, \
+             CONSTRAINT pair UNIQUE (b, c), UNIQUE (a))",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![vec!["a"], vec!["b"], vec!["b", "c"]],
+            schema.unique_constraints
+        );
+        // A constraint without a declared type isn't taken for one.
+        assert!(matches!(schema.fields[1].kind, TableFieldKind::Blob));
+        assert!(!schema.fields[2].allow_null);
+    }
+
+    #[test]
+    fn test_index_collations() {
+        let table = TableSchema::from("CREATE TABLE t(a TEXT COLLATE NOCASE, b, c)").unwrap();
+        assert_eq!(Collation::NoCase, table.fields[0].collation);
+        assert_eq!(Collation::Binary, table.fields[1].collation);
+
+        let mut index = IndexSchema::from(
+            "CREATE INDEX t_abc ON t(a, b COLLATE RTRIM DESC, c COLLATE \"rev\")",
+        )
+        .unwrap();
+        index.inherit_collations(&table);
+        assert_eq!(Collation::NoCase, *index.fields[0].collation());
+        assert_eq!(Collation::RTrim, *index.fields[1].collation());
+        assert!(!index.fields[1].ascending);
+        assert_eq!(Some("rev"), index.unknown_collation());
+
+        let text = |s: &str| Record::String(s.to_string());
+        let compare = |a: &[&str], b: &[&str]| {
+            let a = a.iter().map(|s| text(s)).collect::<Vec<_>>();
+            let b = b.iter().map(|s| text(s)).collect::<Vec<_>>();
+            index.compare(&a, &b)
+        };
+        assert_eq!(Ordering::Equal, compare(&["Apple"], &["aPPLE"]));
+        assert_eq!(Ordering::Equal, compare(&["a", "x  "], &["A", "x"]));
+        assert_eq!(Ordering::Greater, compare(&["a", "x"], &["a", "y"]));
+        assert_eq!(Ordering::Less, compare(&["B"], &["c"]));
+    }
+
+    #[test]
+    fn test_view_schema_from() {
+        let view =
+            ViewSchema::from("CREATE VIEW adults AS SELECT name FROM people WHERE age >= 18")
+                .unwrap();
+        assert!(view.columns.is_empty());
+        assert_eq!(view.select, "SELECT name FROM people WHERE age >= 18");
+
+        let view =
+            ViewSchema::from("create view \"big orders\"(who, total) as\nselect a, b from t")
+                .unwrap();
+        assert_eq!(view.columns, ["who", "total"]);
+        assert_eq!(view.select, "select a, b from t");
     }
 }
@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use codecrafters_sqlite::{Connection, Error, ListWriter, is_complete};
+use log::warn;
+use rustyline::{DefaultEditor, error::ReadlineError};
+
+const PROMPT: &str = "sqlite> ";
+const CONTINUATION_PROMPT: &str = "   ...> ";
+
+/// How many scripts `.read` can read from one another, as in sqlite3, which stops a script
+/// reading itself.
+const MAX_READ_DEPTH: usize = 25;
+
+const HELP: &str = "\
+.dbinfo                  Show status information about the database
+.exit                    Exit this program
+.help                    Show this message
+.quit                    Exit this program
+.read FILE               Read input from FILE
+.tables                  List names of tables and views
+";
+
+/// Whether the shell goes on reading input after a line.
+#[derive(Debug)]
+pub(crate) enum Flow {
+    Continue,
+    Quit,
+}
+
+/// A session on an open database, reading SQL statements, spread over as many lines as it takes
+/// until a `;` ends them, and dot-commands. Statements share the connection, so a transaction
+/// begun by one stays open for the next. Output is written to `out`, while errors are left to
+/// the caller to report.
+pub(crate) struct Shell<'conn, W> {
+    connection: &'conn Connection,
+    out: W,
+    /// Lines of a statement not yet ended by `;`.
+    pending: String,
+    /// Number of scripts being read by `.read`, each from the one before.
+    read_depth: usize,
+}
+
+impl<'conn, W: Write> Shell<'conn, W> {
+    pub(crate) fn new(connection: &'conn Connection, out: W) -> Self {
+        Self {
+            connection,
+            out,
+            pending: String::new(),
+            read_depth: 0,
+        }
+    }
+
+    /// Reads lines from the terminal, with line editing and a history kept across sessions,
+    /// until end of input or `.quit`. Errors are reported and reading goes on.
+    pub(crate) fn run_interactive(&mut self) -> Result<(), Error> {
+        let mut editor = DefaultEditor::new().map_err(io::Error::other)?;
+        let history = Self::history_path();
+        if let Some(path) = &history {
+            // There is no history file before the first session.
+            let _ = editor.load_history(path);
+        }
+        if io::stdin().is_terminal() {
+            writeln!(self.out, "Enter \".help\" for usage hints.")?;
+        }
+
+        loop {
+            let prompt = if self.pending.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor
+                            .add_history_entry(line.as_str())
+                            .map_err(io::Error::other)?;
+                    }
+                    match self.process_line(&line) {
+                        Ok(Flow::Continue) => {}
+                        Ok(Flow::Quit) => break,
+                        Err(err) => eprintln!("Error: {}", err),
+                    }
+                }
+                // Ctrl-C drops the statement being typed.
+                Err(ReadlineError::Interrupted) => self.pending.clear(),
+                Err(ReadlineError::Eof) => {
+                    if let Err(err) = self.finish() {
+                        eprintln!("Error: {}", err);
+                    }
+                    break;
+                }
+                Err(err) => return Err(io::Error::other(err).into()),
+            }
+        }
+
+        if let Some(path) = &history
+            && let Err(err) = editor.save_history(path)
+        {
+            warn!("Cannot save history to {}: {}", path.display(), err);
+        }
+        Ok(())
+    }
+
+    /// Runs a dot-command, given as its whole line.
+    pub(crate) fn dot_command(&mut self, line: &str) -> Result<Flow, Error> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        match (command, words.collect::<Vec<_>>().as_slice()) {
+            (".dbinfo", []) => {
+                writeln!(
+                    self.out,
+                    "database page size: {}",
+                    self.connection.page_size()
+                )?;
+                writeln!(
+                    self.out,
+                    "number of tables: {}",
+                    self.connection.table_count()
+                )?;
+            }
+            (".tables", []) => {
+                writeln!(self.out, "{}", self.connection.table_names().join(" "))?;
+            }
+            (".read", [path]) => self.read_script(path)?,
+            (".read", _) => return Err(Error::from("usage: .read FILE")),
+            (".help", []) => write!(self.out, "{}", HELP)?,
+            (".quit" | ".exit", []) => return Ok(Flow::Quit),
+            _ => {
+                return Err(format!(
+                    "unknown command or invalid arguments: \"{}\". Enter \".help\" for help",
+                    command.trim_start_matches('.')
+                )
+                .into());
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Handles a line of input: a dot-command, or part of the statements run once a `;` ends
+    /// them.
+    fn process_line(&mut self, line: &str) -> Result<Flow, Error> {
+        if self.pending.is_empty() {
+            if line.trim().is_empty() {
+                return Ok(Flow::Continue);
+            }
+            if line.trim_start().starts_with('.') {
+                return self.dot_command(line.trim());
+            }
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        if is_complete(&self.pending) {
+            self.finish()?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Runs the statements read so far, an unfinished one being reported by the parser.
+    fn finish(&mut self) -> Result<(), Error> {
+        let sql = std::mem::take(&mut self.pending);
+        if sql.trim().is_empty() {
+            return Ok(());
+        }
+
+        let mut writer = ListWriter::new(&mut self.out);
+        for stmt in self.connection.prepare_all(&sql)? {
+            writer.write_rows(stmt.query(&[])?)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the statements and dot-commands of a script, stopping at its first error. A `.quit`
+    /// in the script ends the script only.
+    fn read_script(&mut self, path: &str) -> Result<(), Error> {
+        if self.read_depth == MAX_READ_DEPTH {
+            return Err(format!("input nesting limit ({}) reached", MAX_READ_DEPTH).into());
+        }
+        let script =
+            fs::read_to_string(path).map_err(|err| format!("cannot open \"{}\": {}", path, err))?;
+
+        self.read_depth += 1;
+        let result = self.read_lines(&script);
+        self.read_depth -= 1;
+        result
+    }
+
+    fn read_lines(&mut self, script: &str) -> Result<(), Error> {
+        let mut result = Ok(());
+        for line in script.lines() {
+            match self.process_line(line) {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Quit) => break,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        match result {
+            Ok(()) => self.finish(),
+            Err(err) => {
+                self.pending.clear();
+                Err(err)
+            }
+        }
+    }
+
+    fn history_path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME")?;
+        Some(PathBuf::from(home).join(".peter_sqlite_history"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use codecrafters_sqlite::Connection;
+
+    use crate::shell::{Flow, Shell};
+
+    #[test]
+    fn test_process_line() {
+        let connection = Connection::open("sample.db").unwrap();
+        let mut shell = Shell::new(&connection, vec![]);
+
+        // A statement runs once a `;` ends it.
+        shell.process_line("SELECT name FROM apples").unwrap();
+        assert!(shell.out.is_empty());
+        shell.process_line("WHERE id < 3 ORDER BY id;").unwrap();
+        shell.process_line("").unwrap();
+        shell.process_line(".tables").unwrap();
+        assert_eq!(
+            "Granny Smith\nFuji\napples oranges sqlite_sequence\n",
+            String::from_utf8(shell.out.split_off(0)).unwrap()
+        );
+
+        let err = shell.process_line("SELECT * FROM pears;").unwrap_err();
+        assert_eq!("no such table: pears", err.to_string());
+        assert!(shell.pending.is_empty());
+        let err = shell.process_line(".tables apples").unwrap_err();
+        assert_eq!(
+            "unknown command or invalid arguments: \"tables\". Enter \".help\" for help",
+            err.to_string()
+        );
+        assert!(matches!(shell.process_line(".quit"), Ok(Flow::Quit)));
+        assert!(shell.out.is_empty());
+    }
+
+    #[test]
+    fn test_read_script() {
+        let connection = Connection::open("sample.db").unwrap();
+        let mut shell = Shell::new(&connection, vec![]);
+        let dir = std::env::temp_dir();
+        let script = dir.join(format!("shell-test-{}.sql", std::process::id()));
+        let looping = dir.join(format!("shell-loop-test-{}.sql", std::process::id()));
+
+        // `.quit` ends the script, not the shell.
+        let lines = "SELECT count(*)\n  FROM apples;\n.quit\nSELECT 1;\n";
+        fs::write(&script, lines).unwrap();
+        let read = format!(".read {}", script.display());
+        assert!(matches!(shell.process_line(&read), Ok(Flow::Continue)));
+        assert_eq!(b"4\n", shell.out.as_slice());
+
+        // A script reading itself stops at the nesting limit.
+        fs::write(
+            &looping,
+            format!(
+                "SELECT id FROM apples WHERE id = 1;\n.read {}\n",
+                looping.display()
+            ),
+        )
+        .unwrap();
+        let err = shell
+            .process_line(&format!(".read {}", looping.display()))
+            .unwrap_err();
+        assert_eq!("input nesting limit (25) reached", err.to_string());
+        assert_eq!(0, shell.read_depth);
+        assert_eq!(
+            "4\n".to_string() + &"1\n".repeat(25),
+            String::from_utf8(shell.out).unwrap()
+        );
+        fs::remove_file(&script).unwrap();
+        fs::remove_file(&looping).unwrap();
+    }
+}
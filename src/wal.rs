@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{FileExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::Error,
+    pager::{set_lock, wait_for_lock},
+    reader::Reader,
+};
+
+const WAL_HEADER_SIZE: usize = 32;
+const FRAME_HEADER_SIZE: usize = 24;
+
+/// The magic number starting a WAL, its lowest bit telling the byte order of the checksums:
+/// big-endian when set.
+const WAL_MAGIC: u32 = 0x377f_0682;
+
+/// Offsets in the `-shm` file, sqlite's index of the log, of the two copies of its header, of
+/// the number of frames checkpointed, and of the read marks, the number of frames each reader
+/// holding the lock on one sees. Numbers in it are in the byte order of the machine.
+const INDEX_HEADER_SIZE: usize = 48;
+const MAX_FRAME_OFFSET: usize = 16;
+const INDEX_SALTS_OFFSET: usize = 32;
+const BACKFILLED_OFFSET: usize = 96;
+const READ_MARKS_OFFSET: usize = 100;
+const READ_MARKS: usize = 5;
+const READ_MARK_NOT_USED: u32 = 0xffff_ffff;
+
+/// Offsets in the `-shm` file of the bytes sqlite locks: the read locks, the first of which is
+/// held by readers ignoring the log, and the byte every connection using the index holds a
+/// shared lock on, which the first one to open it locks exclusively to start the index over.
+const READ_LOCK_BYTE: u64 = 123;
+const DMS_BYTE: u64 = 128;
+
+/// The write-ahead log of a database in WAL mode, the `-wal` file next to it, holding the pages
+/// committed since the last checkpoint copied them back to the database file.
+///
+/// A header is followed by frames of a header and a page each. A frame belongs to the log while
+/// it carries the salts of the log header and its checksum, running over the log header and
+/// every frame up to it, checks out. A frame giving the database size ends a commit: the frames
+/// after the last one belong to a transaction still being written and aren't read.
+///
+/// Each read sees the log as of when it began, under a read lock in the `-shm` file keeping
+/// sqlite from checkpointing the frames past it or starting the log over in the meantime. The
+/// frames committed since the previous read are read then, from where it stopped. Without
+/// another connection keeping the index up to date, the index is locked for the read instead,
+/// keeping sqlite's connections from using the log until it ends.
+pub(crate) struct Wal {
+    path: PathBuf,
+    index_path: PathBuf,
+    page_size: usize,
+    file: Option<File>,
+    index: Option<File>,
+    /// Whether the index was opened for writing, to set read marks.
+    index_writable: bool,
+    /// Set while holding a read lock in the index.
+    read_lock: bool,
+    /// The header of the log the frames were read from, a new one starting the log over.
+    header: [u8; WAL_HEADER_SIZE],
+    /// The offset of the page of the latest committed frame of each page.
+    frames: HashMap<usize, u64>,
+    /// The size of the database in pages as of the last commit.
+    database_size: Option<usize>,
+    /// Number of frames up to the last commit, and the checksum as of its end, for the next
+    /// read to carry on from.
+    committed: (u32, (u32, u32)),
+    /// Whether a read began before, which the next one compares its snapshot to.
+    read_before: bool,
+}
+
+impl Wal {
+    /// The log of the database at `path`, read once a read begins.
+    pub(crate) fn new(path: &Path, page_size: usize) -> Self {
+        let with_suffix = |suffix| {
+            let mut path = path.as_os_str().to_owned();
+            path.push(suffix);
+            PathBuf::from(path)
+        };
+        Self {
+            path: with_suffix("-wal"),
+            index_path: with_suffix("-shm"),
+            page_size,
+            file: None,
+            index: None,
+            index_writable: false,
+            read_lock: false,
+            header: [0; WAL_HEADER_SIZE],
+            frames: HashMap::new(),
+            database_size: None,
+            committed: (0, (0, 0)),
+            read_before: false,
+        }
+    }
+
+    /// The size of the database in pages as of the last commit, unless no commit is in the log.
+    pub(crate) fn database_size(&self) -> Option<usize> {
+        self.database_size
+    }
+
+    /// Reads the latest committed version of a page, unless the log has none.
+    pub(crate) fn read_page(&self, number: usize) -> Result<Option<Vec<u8>>, Error> {
+        let (Some(file), Some(&offset)) = (&self.file, self.frames.get(&number)) else {
+            return Ok(None);
+        };
+        let mut page = vec![0; self.page_size];
+        file.read_exact_at(&mut page, offset)?;
+        Ok(Some(page))
+    }
+
+    /// Takes a read lock and reads the frames committed since the last read, until `end_read`.
+    /// Tells whether what the log holds changed since the last read.
+    pub(crate) fn begin_read(&mut self) -> Result<bool, Error> {
+        self.end_read();
+        let before = (self.header, self.committed.0);
+        // sqlite deletes the log and its index once the last of its connections checkpoints it.
+        if reopen(&self.path, &mut self.file, false)? {
+            self.frames.clear();
+            self.database_size = None;
+            self.committed = (0, (0, 0));
+            self.header = [0; WAL_HEADER_SIZE];
+        }
+        if reopen(&self.index_path, &mut self.index, true)? {
+            self.index_writable = self.index.as_ref().is_some_and(opened_for_writing);
+        }
+
+        let mut max_frame = u32::MAX;
+        wait_for_lock(|| {
+            let Some(locked) = self.lock_read_mark()? else {
+                return Ok(false);
+            };
+            max_frame = locked;
+            self.read_lock = self.index.is_some();
+            Ok(true)
+        })?;
+        let read = self.read_frames(max_frame);
+        if read.is_err() {
+            self.end_read();
+        }
+        read?;
+        let changed = !self.read_before || before != (self.header, self.committed.0);
+        self.read_before = true;
+        Ok(changed)
+    }
+
+    /// Releases the locks taken by `begin_read`. Unlocking fails only on a file that isn't
+    /// open, so errors are ignored.
+    pub(crate) fn end_read(&mut self) {
+        if let Some(index) = &self.index
+            && std::mem::take(&mut self.read_lock)
+        {
+            let _ = unlock_index(index);
+        }
+    }
+
+    /// Takes a read lock in the index, giving the number of frames the read sees, or `None`
+    /// while another connection holds the locks needed.
+    ///
+    /// Like sqlite, a read of a log fully checkpointed ignores it under the first read lock,
+    /// and otherwise takes the lock of a read mark of the last frame committed, setting one if
+    /// none is, or the latest mark before if it can't. The index is read again once locked, in
+    /// case a commit or checkpoint changed it in the meantime.
+    fn lock_read_mark(&self) -> io::Result<Option<u32>> {
+        let Some(index) = &self.index else {
+            return Ok(Some(u32::MAX));
+        };
+        // Taking the lock every connection takes shared exclusively tells that none is using
+        // the index, which is then out of date.
+        if self.index_writable && set_lock(index, libc::F_WRLCK, DMS_BYTE, 1)? {
+            return Ok(Some(u32::MAX));
+        }
+        if !set_lock(index, libc::F_RDLCK, DMS_BYTE, 1)? {
+            return Ok(None);
+        }
+        let Some(header) = read_index(index)? else {
+            return unlock_index(index);
+        };
+
+        let max_frame = header.max_frame;
+        let (slot, mark) = if max_frame == header.backfilled {
+            (0, 0)
+        } else {
+            let mut latest = (1..READ_MARKS)
+                .map(|slot| (slot, header.read_marks[slot]))
+                .filter(|&(_, mark)| mark != READ_MARK_NOT_USED && mark <= max_frame)
+                .max_by_key(|&(_, mark)| mark);
+            if latest.is_none_or(|(_, mark)| mark < max_frame) && self.index_writable {
+                for slot in 1..READ_MARKS {
+                    if set_lock(index, libc::F_WRLCK, READ_LOCK_BYTE + slot as u64, 1)? {
+                        let offset = READ_MARKS_OFFSET + slot * 4;
+                        index.write_at(&max_frame.to_ne_bytes(), offset as u64)?;
+                        latest = Some((slot, max_frame));
+                        break;
+                    }
+                }
+            }
+            match latest {
+                Some(latest) => latest,
+                None => return unlock_index(index),
+            }
+        };
+        // Setting a shared lock on the byte locked exclusively to set the mark lowers it.
+        if !set_lock(index, libc::F_RDLCK, READ_LOCK_BYTE + slot as u64, 1)? {
+            return unlock_index(index);
+        }
+
+        let unchanged = read_index(index)?.is_some_and(|locked| {
+            locked.header == header.header && (slot == 0 || locked.read_marks[slot] == mark)
+        });
+        if !unchanged || (mark > 0 && self.log_salts()? != Some(header.salts)) {
+            return unlock_index(index);
+        }
+        Ok(Some(mark))
+    }
+
+    fn log_salts(&self) -> io::Result<Option<[u8; 8]>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        let mut salts = [0; 8];
+        match file.read_exact_at(&mut salts, 16) {
+            Ok(()) => Ok(Some(salts)),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reads the frames committed up to `max_frame`, carrying on from the last commit read
+    /// unless the log started over.
+    fn read_frames(&mut self, max_frame: u32) -> Result<(), Error> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let len = file.metadata()?.len();
+        let mut header = [0; WAL_HEADER_SIZE];
+        if len >= WAL_HEADER_SIZE as u64 {
+            file.read_exact_at(&mut header, 0)?;
+        }
+        if header != self.header || max_frame < self.committed.0 {
+            self.header = header;
+            self.frames.clear();
+            self.database_size = None;
+            self.committed = (0, (0, 0));
+        }
+        let field = |bytes: &[u8], offset: usize| {
+            Ok::<_, Error>(Reader::new(&bytes[offset..]).peek_i32()? as u32)
+        };
+
+        // A log with an invalid header is empty, as sqlite starts it over.
+        if len < WAL_HEADER_SIZE as u64 {
+            return Ok(());
+        }
+        let magic = field(&header, 0)?;
+        if magic & !1 != WAL_MAGIC || field(&header, 8)? as usize != self.page_size {
+            return Ok(());
+        }
+        let big_endian = magic & 1 == 1;
+        if self.committed.0 == 0 {
+            let checksum = wal_checksum(big_endian, &header[..24], (0, 0));
+            if checksum != (field(&header, 24)?, field(&header, 28)?) {
+                return Ok(());
+            }
+            self.committed.1 = checksum;
+        }
+
+        let (mut count, mut checksum) = self.committed;
+        let mut uncommitted = HashMap::new();
+        let mut frame = vec![0; FRAME_HEADER_SIZE + self.page_size];
+        let mut offset = WAL_HEADER_SIZE as u64 + count as u64 * frame.len() as u64;
+        while count < max_frame && offset + frame.len() as u64 <= len {
+            file.read_exact_at(&mut frame, offset)?;
+            if frame[8..16] != header[16..24] {
+                break;
+            }
+            checksum = wal_checksum(big_endian, &frame[..8], checksum);
+            checksum = wal_checksum(big_endian, &frame[FRAME_HEADER_SIZE..], checksum);
+            if checksum != (field(&frame, 16)?, field(&frame, 20)?) {
+                break;
+            }
+
+            let number = field(&frame, 0)? as usize;
+            uncommitted.insert(number, offset + FRAME_HEADER_SIZE as u64);
+            count += 1;
+            let database_size = field(&frame, 4)? as usize;
+            if database_size != 0 {
+                self.frames.extend(uncommitted.drain());
+                self.database_size = Some(database_size);
+                self.committed = (count, checksum);
+            }
+            offset += frame.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// The header of the index of the log, as far as reading it goes.
+struct IndexHeader {
+    header: [u8; INDEX_HEADER_SIZE],
+    max_frame: u32,
+    salts: [u8; 8],
+    backfilled: u32,
+    read_marks: [u32; READ_MARKS],
+}
+
+/// Reads the header of the index, unless no connection set it up yet or a commit is writing it,
+/// its two copies differing then.
+fn read_index(index: &File) -> io::Result<Option<IndexHeader>> {
+    let mut bytes = [0; READ_MARKS_OFFSET + READ_MARKS * 4];
+    match index.read_exact_at(&mut bytes, 0) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let word = |offset: usize| u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let header: [u8; INDEX_HEADER_SIZE] = bytes[..INDEX_HEADER_SIZE].try_into().unwrap();
+    // The byte after the version and change counters tells whether the index is set up.
+    if header[..] != bytes[INDEX_HEADER_SIZE..2 * INDEX_HEADER_SIZE] || header[12] == 0 {
+        return Ok(None);
+    }
+    Ok(Some(IndexHeader {
+        header,
+        max_frame: word(MAX_FRAME_OFFSET),
+        salts: header[INDEX_SALTS_OFFSET..INDEX_SALTS_OFFSET + 8]
+            .try_into()
+            .unwrap(),
+        backfilled: word(BACKFILLED_OFFSET),
+        read_marks: std::array::from_fn(|slot| word(READ_MARKS_OFFSET + slot * 4)),
+    }))
+}
+
+/// Releases the locks taken in the index, for `lock_read_mark` to try again.
+fn unlock_index(index: &File) -> io::Result<Option<u32>> {
+    set_lock(
+        index,
+        libc::F_UNLCK,
+        READ_LOCK_BYTE,
+        DMS_BYTE + 1 - READ_LOCK_BYTE,
+    )?;
+    Ok(None)
+}
+
+fn opened_for_writing(file: &File) -> bool {
+    // SAFETY: the descriptor is open as long as `file` is.
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    flags >= 0 && flags & libc::O_ACCMODE == libc::O_RDWR
+}
+
+/// Opens the file at `path` again unless `held` still is it, telling whether it changed. The
+/// index is created when missing, as sqlite does, for its lock to keep sqlite out of the log.
+fn reopen(path: &Path, held: &mut Option<File>, index: bool) -> Result<bool, Error> {
+    let id = |metadata: fs::Metadata| (metadata.dev(), metadata.ino());
+    let current = match fs::metadata(path) {
+        Ok(metadata) => Some(id(metadata)),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    let held_id = match held {
+        Some(file) => Some(id(file.metadata()?)),
+        None => None,
+    };
+    if held.is_some() && held_id == current {
+        return Ok(false);
+    }
+
+    let opened = if index {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .or_else(|_| File::open(path))
+    } else {
+        File::open(path)
+    };
+    *held = match opened {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    Ok(true)
+}
+
+/// The running checksum of the log, adding the 32-bit words of `data` two at a time to the
+/// checksum so far.
+pub(crate) fn wal_checksum(big_endian: bool, data: &[u8], checksum: (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let (mut s0, mut s1) = checksum;
+    for pair in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{pager::Pager, wal::wal_checksum};
+
+    #[test]
+    fn test_pages_committed_to_the_wal_are_read_from_it() {
+        let path = std::env::temp_dir().join(format!("wal-test-{}.db", std::process::id()));
+        let wal_path = path.with_extension("db-wal");
+        let mut db = fs::read("sample.db").unwrap();
+        db[18] = 2;
+        db[19] = 2;
+        fs::write(&path, &db).unwrap();
+        let page_size = 4096;
+        let page_count = db.len() / page_size;
+
+        let mut wal = vec![];
+        for field in [0x377f_0683, 3_007_000, page_size as u32, 0, 7, 9] {
+            wal.extend_from_slice(&u32::to_be_bytes(field));
+        }
+        let mut checksum = wal_checksum(true, &wal, (0, 0));
+        wal.extend_from_slice(&checksum.0.to_be_bytes());
+        wal.extend_from_slice(&checksum.1.to_be_bytes());
+        // Page 2 committed, then page 3 in a transaction not committed yet, then a frame of an
+        // older log, with other salts.
+        for (number, database_size, fill, salts) in [
+            (2, page_count, 0xaa, [7, 9]),
+            (3, 0, 0xbb, [7, 9]),
+            (4, page_count, 0xcc, [6, 9]),
+        ] {
+            let mut frame = vec![];
+            for field in [number as u32, database_size as u32, salts[0], salts[1]] {
+                frame.extend_from_slice(&field.to_be_bytes());
+            }
+            let page = vec![fill; page_size];
+            checksum = wal_checksum(true, &frame[..8], checksum);
+            checksum = wal_checksum(true, &page, checksum);
+            frame.extend_from_slice(&checksum.0.to_be_bytes());
+            frame.extend_from_slice(&checksum.1.to_be_bytes());
+            wal.extend(frame);
+            wal.extend(page);
+        }
+        fs::write(&wal_path, &wal).unwrap();
+
+        let pager = Pager::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&wal_path).unwrap();
+        fs::remove_file(path.with_extension("db-shm")).unwrap();
+        assert_eq!(page_count, pager.page_count());
+        assert!(pager.page(2).unwrap().iter().all(|&byte| byte == 0xaa));
+        assert_eq!(db[2 * page_size..3 * page_size], pager.page(3).unwrap()[..]);
+        assert_eq!(db[3 * page_size..4 * page_size], pager.page(4).unwrap()[..]);
+    }
+}
@@ -0,0 +1,781 @@
+use crate::{
+    ast::{
+        DeleteStmt, Expr, InsertSource, InsertStmt, ResultColumn, SelectStmt, TableRef, UpdateStmt,
+    },
+    btree_cursor::BTreeCursor,
+    btree_writer::BTreeWriter,
+    cell::CellPayload,
+    common::{Error, Index, Table},
+    database::Database,
+    evaluator::{ConstantContext, RowContext},
+    query::Query,
+    query_executor::QueryExecutor,
+    record::{Record, encode_record},
+    schema::{Affinity, Collation, IndexSchema, ROWID_NAMES, TableSchema},
+};
+
+const SEQUENCE_TABLE: &str = "sqlite_sequence";
+
+/// Runs the statements that modify the database, each as a transaction of its own unless one
+/// was started by `BEGIN`: committed once every row is written, and rolled back on the first
+/// error, leaving the database as it was before the statement.
+pub(crate) struct WriteExecutor;
+
+impl WriteExecutor {
+    pub(crate) fn execute_insert(stmt: &InsertStmt, db: &Database) -> Result<(), Error> {
+        Self::in_transaction(db, || Self::insert(stmt, db))
+    }
+
+    pub(crate) fn execute_update(stmt: &UpdateStmt, db: &Database) -> Result<(), Error> {
+        Self::in_transaction(db, || Self::update(stmt, db))
+    }
+
+    pub(crate) fn execute_delete(stmt: &DeleteStmt, db: &Database) -> Result<(), Error> {
+        Self::in_transaction(db, || Self::delete(stmt, db))
+    }
+
+    pub(crate) fn execute_begin(db: &Database) -> Result<(), Error> {
+        db.pager.begin()
+    }
+
+    pub(crate) fn execute_commit(db: &Database) -> Result<(), Error> {
+        if !db.pager.in_transaction() {
+            return Err("cannot commit - no transaction is active".into());
+        }
+        db.pager.commit()
+    }
+
+    pub(crate) fn execute_rollback(db: &Database) -> Result<(), Error> {
+        if !db.pager.in_transaction() {
+            return Err("cannot rollback - no transaction is active".into());
+        }
+        db.pager.rollback();
+        Ok(())
+    }
+
+    fn in_transaction(db: &Database, run: impl FnOnce() -> Result<(), Error>) -> Result<(), Error> {
+        db.pager.reserve()?;
+        let savepoint = db.pager.savepoint();
+        match run() {
+            Ok(()) if db.pager.in_transaction() => Ok(()),
+            // A statement that can't commit on its own is undone, unlike an explicit `COMMIT`.
+            Ok(()) => db.pager.commit().inspect_err(|_| db.pager.rollback()),
+            Err(err) => {
+                db.pager.restore(savepoint);
+                Err(err)
+            }
+        }
+    }
+
+    /// The table a statement modifies, which can't be a view, nor have a trigger the statement
+    /// would fire, nor an index ordered by a collation the entries can't be compared by.
+    fn target_table<'a>(db: &'a Database, name: &str, event: &str) -> Result<&'a Table, Error> {
+        let Some(table) = db.table(name) else {
+            if db.view(name).is_some() {
+                return Err(format!("cannot modify {} because it is a view", name).into());
+            }
+            return Err(Error::NoSuchTable(name.to_string()));
+        };
+        if let Some(trigger) = db.schema().triggers.values().find(|trigger| {
+            trigger.table_name.eq_ignore_ascii_case(&table.table_name) && trigger.fires_on(event)
+        }) {
+            return Err(format!(
+                "cannot run {} on {}: trigger {} is not supported",
+                event, table.table_name, trigger.trigger_name
+            )
+            .into());
+        }
+        for index in db.table_indices(&table.table_name) {
+            if let Some(collation) = index.sql_schema.unknown_collation() {
+                return Err(format!("no such collation sequence: {}", collation).into());
+            }
+        }
+        Ok(table)
+    }
+
+    /// The column a statement names, as `Some(None)` for the rowid through one of its names.
+    fn column_target(schema: &TableSchema, name: &str) -> Option<Option<usize>> {
+        let field = schema
+            .fields
+            .iter()
+            .position(|field| field.name.eq_ignore_ascii_case(name));
+        match field {
+            Some(i) => Some(Some(i)),
+            None if schema.is_rowid_name(name) => Some(None),
+            None => None,
+        }
+    }
+
+    fn insert(stmt: &InsertStmt, db: &Database) -> Result<(), Error> {
+        let table = Self::target_table(db, &stmt.table, "INSERT")?;
+        let schema = &table.sql_schema;
+
+        // The column each value goes to, None for a value given to the rowid by name.
+        let targets = if stmt.columns.is_empty() {
+            (0..schema.fields.len()).map(Some).collect::<Vec<_>>()
+        } else {
+            stmt.columns
+                .iter()
+                .map(|name| {
+                    Self::column_target(schema, name).ok_or_else(|| {
+                        format!("table {} has no column named {}", table.table_name, name)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // Rows are all read before any is written, so that a SELECT from the same table doesn't
+        // see the rows inserted.
+        let rows = match &stmt.source {
+            InsertSource::Values(rows) => rows
+                .iter()
+                .map(|row| row.iter().map(|expr| expr.eval(&ConstantContext)).collect())
+                .collect::<Result<Vec<Vec<_>>, _>>()?,
+            InsertSource::Select(select) => {
+                let query = Query::from_select(select.as_ref().clone())?;
+                QueryExecutor::collect_rows(&query, db)?
+            }
+        };
+
+        for values in rows {
+            if values.len() != targets.len() {
+                let msg = if stmt.columns.is_empty() {
+                    format!(
+                        "table {} has {} columns but {} values were supplied",
+                        table.table_name,
+                        targets.len(),
+                        values.len()
+                    )
+                } else {
+                    format!("{} values for {} columns", values.len(), targets.len())
+                };
+                return Err(msg.into());
+            }
+            Self::insert_row(db, table, &targets, values)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the values given to some of the columns into a row, with the defaults of the
+    /// others, and inserts it.
+    fn insert_row(
+        db: &Database,
+        table: &Table,
+        targets: &[Option<usize>],
+        values: Vec<Record>,
+    ) -> Result<(), Error> {
+        let schema = &table.sql_schema;
+        let mut given = vec![None; schema.fields.len()];
+        let mut given_rowid = None;
+        for (target, value) in targets.iter().zip(values) {
+            match target {
+                Some(i) => given[*i] = Some(value),
+                None => given_rowid = Some(value),
+            }
+        }
+
+        let row = schema
+            .fields
+            .iter()
+            .zip(given)
+            .map(|(field, value)| {
+                let value = match (value, &field.default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => default.eval(&ConstantContext)?,
+                    (None, None) => Record::Null,
+                };
+                Ok(field.affinity().coerce(value))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Self::write_row(db, table, row, given_rowid, None)
+    }
+
+    fn update(stmt: &UpdateStmt, db: &Database) -> Result<(), Error> {
+        let table = Self::target_table(db, &stmt.table, "UPDATE")?;
+        let schema = &table.sql_schema;
+        let targets = stmt
+            .assignments
+            .iter()
+            .map(|(name, _)| {
+                Self::column_target(schema, name).ok_or_else(|| Error::NoSuchColumn(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let exprs = stmt
+            .assignments
+            .iter()
+            .map(|(_, expr)| expr.clone())
+            .collect::<Vec<_>>();
+        for values in Self::matching_rows(db, table, &stmt.where_clause, exprs)? {
+            let rowid = values[0].as_int().expect("Integer rowid");
+            let old_row = Self::read_row(db, table, rowid)?;
+
+            let mut row = old_row.clone();
+            let mut new_rowid = Record::I64(rowid);
+            for (target, value) in targets.iter().zip(values.into_iter().skip(1)) {
+                match target {
+                    Some(i) => row[*i] = schema.fields[*i].affinity().coerce(value),
+                    None => new_rowid = Affinity::Integer.coerce(value),
+                }
+            }
+            // Unlike on insert, a NULL rowid doesn't pick the next one.
+            if let Some(i) = schema.rowid_alias() {
+                if targets.contains(&Some(i)) {
+                    new_rowid = row[i].clone();
+                }
+                row[i] = new_rowid.clone();
+            }
+            let Some(new_rowid) = new_rowid.as_int() else {
+                return Err("datatype mismatch".into());
+            };
+
+            // The cell of a row keeping its rowid is rewritten in place.
+            Self::delete_index_entries(db, table, rowid, &old_row)?;
+            if new_rowid != rowid {
+                BTreeWriter::new(&db.pager).delete_row(table.root_page, rowid)?;
+            }
+            Self::write_row(db, table, row, Some(Record::I64(new_rowid)), Some(rowid))?;
+        }
+        Ok(())
+    }
+
+    fn delete(stmt: &DeleteStmt, db: &Database) -> Result<(), Error> {
+        let table = Self::target_table(db, &stmt.table, "DELETE")?;
+        for values in Self::matching_rows(db, table, &stmt.where_clause, vec![])? {
+            let rowid = values[0].as_int().expect("Integer rowid");
+            let row = Self::read_row(db, table, rowid)?;
+            Self::delete_index_entries(db, table, rowid, &row)?;
+            BTreeWriter::new(&db.pager).delete_row(table.root_page, rowid)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the rows of a table the WHERE clause of a statement picks, by running the query
+    /// `SELECT rowid, exprs... FROM table WHERE ...`, which can use the indexes of the table.
+    /// The rows are all found before any is changed.
+    fn matching_rows(
+        db: &Database,
+        table: &Table,
+        where_clause: &Option<Expr>,
+        exprs: Vec<Expr>,
+    ) -> Result<Vec<Vec<Record>>, Error> {
+        let schema = &table.sql_schema;
+        let rowid_name = schema
+            .rowid_alias()
+            .map(|i| schema.fields[i].name.as_str())
+            .or_else(|| {
+                ROWID_NAMES
+                    .into_iter()
+                    .find(|name| schema.is_rowid_name(name))
+            })
+            .ok_or_else(|| format!("no name left for the rowid of {}", table.table_name))?;
+
+        let rowid = Expr::Column {
+            table: None,
+            name: rowid_name.to_string(),
+        };
+        let columns = std::iter::once(rowid)
+            .chain(exprs)
+            .map(|expr| ResultColumn::Expr { expr, alias: None })
+            .collect();
+        let select = SelectStmt {
+            columns,
+            from: TableRef {
+                name: table.table_name.clone(),
+                alias: None,
+                select: None,
+            },
+            joins: vec![],
+            where_clause: where_clause.clone(),
+            group_by: vec![],
+            having: None,
+            order_by: vec![],
+            limit: None,
+            offset: None,
+        };
+        let query = Query::from_select(select)?;
+        QueryExecutor::collect_rows(&query, db)
+    }
+
+    /// Reads a row of the table, with the rowid alias column holding the rowid.
+    fn read_row(db: &Database, table: &Table, rowid: i64) -> Result<Vec<Record>, Error> {
+        let mut cursor = BTreeCursor::new(&db.pager, table.root_page);
+        if !cursor.seek_rowid(rowid)? || cursor.rowid()? != rowid {
+            return Err(Error::corrupt(format!(
+                "row {} missing from {}",
+                rowid, table.table_name
+            )));
+        }
+
+        let schema = &table.sql_schema;
+        let mut row = cursor.payload()?.read_as_table_row(schema)?;
+        if let Some(i) = schema.rowid_alias() {
+            row[i] = Record::I64(rowid);
+        }
+        Ok(row)
+    }
+
+    /// Writes a row to the table b-tree and an entry for it to every index of the table, after
+    /// checking the constraints of the table. The rowid comes from the rowid alias column, or
+    /// else `given_rowid`, the next one being picked when both are NULL. A row with the rowid of
+    /// the one `replacing` (whose index entries are already removed) takes the place of its cell.
+    fn write_row(
+        db: &Database,
+        table: &Table,
+        mut row: Vec<Record>,
+        given_rowid: Option<Record>,
+        replacing: Option<i64>,
+    ) -> Result<(), Error> {
+        let schema = &table.sql_schema;
+        for (field, value) in schema.fields.iter().zip(&row) {
+            if !field.allow_null && !field.rowid_alias && value.is_null() {
+                return Err(format!(
+                    "NOT NULL constraint failed: {}.{}",
+                    table.table_name, field.name
+                )
+                .into());
+            }
+        }
+
+        // The rowid alias column holds the rowid, picking the next one when left NULL.
+        let alias = schema.rowid_alias();
+        let given_rowid = alias
+            .map(|i| row[i].clone())
+            .into_iter()
+            .chain(given_rowid.map(|value| Affinity::Integer.coerce(value)))
+            .find(|value| !value.is_null());
+        let rowid = match given_rowid {
+            Some(value) => value.as_int().ok_or("datatype mismatch")?,
+            None => Self::next_rowid(db, table)?,
+        };
+        if let Some(i) = alias {
+            row[i] = Record::Null;
+        }
+
+        let mut cursor = BTreeCursor::new(&db.pager, table.root_page);
+        if replacing != Some(rowid) && cursor.seek_rowid(rowid)? && cursor.rowid()? == rowid {
+            let column = alias.map_or("rowid", |i| &schema.fields[i].name);
+            return Err(
+                format!("UNIQUE constraint failed: {}.{}", table.table_name, column).into(),
+            );
+        }
+
+        for index in db.table_indices(&table.table_name) {
+            if Self::indexes_row(table, index, &row, rowid)? {
+                Self::insert_index_entry(db, table, index, &row, rowid)?;
+            }
+        }
+        BTreeWriter::new(&db.pager).insert_row(table.root_page, rowid, &encode_record(&row))?;
+
+        if alias.is_some_and(|i| schema.fields[i].is_autoincrement()) {
+            Self::update_sequence(db, &table.table_name, rowid)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the entries of a row from the indexes of the table.
+    fn delete_index_entries(
+        db: &Database,
+        table: &Table,
+        rowid: i64,
+        row: &[Record],
+    ) -> Result<(), Error> {
+        let writer = BTreeWriter::new(&db.pager);
+        for index in db.table_indices(&table.table_name) {
+            if !Self::indexes_row(table, index, row, rowid)? {
+                continue;
+            }
+            let entry = Self::index_entry(table, index, row, rowid)?;
+            let index_schema = &index.sql_schema;
+            let mut compare = |payload: &CellPayload| {
+                Ok(Self::compare_entries(
+                    index_schema,
+                    &payload.read_record()?,
+                    &entry,
+                    rowid,
+                ))
+            };
+            if !writer.delete_entry(index.root_page, &mut compare)? {
+                return Err(Error::corrupt(format!(
+                    "row {} missing from index {}",
+                    rowid, index.index_name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// One more than the largest rowid of the table, or for an `AUTOINCREMENT` table, than the
+    /// largest it ever had.
+    fn next_rowid(db: &Database, table: &Table) -> Result<i64, Error> {
+        let mut cursor = BTreeCursor::new(&db.pager, table.root_page);
+        let mut last = if cursor.last()? { cursor.rowid()? } else { 0 };
+
+        let schema = &table.sql_schema;
+        if schema
+            .rowid_alias()
+            .is_some_and(|i| schema.fields[i].is_autoincrement())
+            && let Some((_, sequence)) = Self::find_sequence(db, &table.table_name)?
+        {
+            last = last.max(sequence);
+        }
+
+        last.checked_add(1)
+            .ok_or_else(|| "database or disk is full".into())
+    }
+
+    /// The row of `sqlite_sequence` holding the largest rowid an `AUTOINCREMENT` table had, as
+    /// its rowid and the sequence value.
+    fn find_sequence(db: &Database, table_name: &str) -> Result<Option<(i64, i64)>, Error> {
+        let sequence_table = db
+            .table(SEQUENCE_TABLE)
+            .ok_or("malformed database schema (sqlite_sequence missing)")?;
+
+        let mut cursor = BTreeCursor::new(&db.pager, sequence_table.root_page);
+        let mut valid = cursor.first()?;
+        while valid {
+            let values = cursor.payload()?.read_record()?;
+            if values.first().and_then(Record::as_str) == Some(table_name) {
+                let sequence = values.get(1).and_then(Record::as_int).unwrap_or(0);
+                return Ok(Some((cursor.rowid()?, sequence)));
+            }
+            valid = cursor.next()?;
+        }
+        Ok(None)
+    }
+
+    /// Raises the sequence of an `AUTOINCREMENT` table to `rowid`, adding its row to
+    /// `sqlite_sequence` on the first insert.
+    fn update_sequence(db: &Database, table_name: &str, rowid: i64) -> Result<(), Error> {
+        let sequence_table = &db.schema().tables[SEQUENCE_TABLE];
+        let sequence_rowid = match Self::find_sequence(db, table_name)? {
+            Some((_, sequence)) if sequence >= rowid => return Ok(()),
+            Some((sequence_rowid, _)) => sequence_rowid,
+            None => Self::next_rowid(db, sequence_table)?,
+        };
+
+        let record = encode_record(&[Record::String(table_name.to_string()), Record::I64(rowid)]);
+        BTreeWriter::new(&db.pager).insert_row(sequence_table.root_page, sequence_rowid, &record)
+    }
+
+    /// Whether a row has an entry in an index: always, unless the WHERE clause of a partial
+    /// index doesn't hold for it.
+    fn indexes_row(
+        table: &Table,
+        index: &Index,
+        row: &[Record],
+        rowid: i64,
+    ) -> Result<bool, Error> {
+        let Some(filter) = &index.sql_schema.filter else {
+            return Ok(true);
+        };
+        let row = WrittenRow {
+            schema: &table.sql_schema,
+            row,
+            rowid,
+        };
+        Ok(filter.eval(&row)?.truthiness() == Some(true))
+    }
+
+    /// The values a row has in the columns of an index, the rowid following them in its entry.
+    fn index_entry(
+        table: &Table,
+        index: &Index,
+        row: &[Record],
+        rowid: i64,
+    ) -> Result<Vec<Record>, Error> {
+        let schema = &table.sql_schema;
+        let entry = index
+            .sql_schema
+            .fields
+            .iter()
+            .map(|field| match schema.find_field(&field.field) {
+                Some(i) if schema.fields[i].rowid_alias => Ok(Record::I64(rowid)),
+                Some(i) => Ok(row[i].clone()),
+                None => Err(format!(
+                    "unsupported index {}: no column {}",
+                    index.index_name, field.field
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entry)
+    }
+
+    /// Orders an entry of an index against the values and rowid of another, as the index
+    /// orders its entries.
+    fn compare_entries(
+        index_schema: &IndexSchema,
+        existing: &[Record],
+        values: &[Record],
+        rowid: i64,
+    ) -> std::cmp::Ordering {
+        let existing_rowid = existing.last().and_then(Record::as_int).unwrap_or(0);
+        index_schema
+            .compare(existing, values)
+            .then_with(|| existing_rowid.cmp(&rowid))
+    }
+
+    /// Adds the entry of a row to an index, refused by a UNIQUE index already holding the same
+    /// values.
+    fn insert_index_entry(
+        db: &Database,
+        table: &Table,
+        index: &Index,
+        row: &[Record],
+        rowid: i64,
+    ) -> Result<(), Error> {
+        let index_schema = &index.sql_schema;
+        let entry = Self::index_entry(table, index, row, rowid)?;
+
+        // NULLs are distinct from each other, so entries holding one never conflict.
+        if index_schema.unique && entry.iter().all(|value| !value.is_null()) {
+            let mut cursor = BTreeCursor::new(&db.pager, index.root_page);
+            let mut position =
+                |payload: &CellPayload| Ok(index_schema.compare(&payload.read_record()?, &entry));
+            if cursor.seek_key(&mut position)? && position(&cursor.payload()?)?.is_eq() {
+                let columns = index_schema
+                    .fields
+                    .iter()
+                    .map(|field| format!("{}.{}", table.table_name, field.field))
+                    .collect::<Vec<_>>();
+                return Err(format!("UNIQUE constraint failed: {}", columns.join(", ")).into());
+            }
+        }
+
+        let mut compare = |payload: &CellPayload| {
+            Ok(Self::compare_entries(
+                index_schema,
+                &payload.read_record()?,
+                &entry,
+                rowid,
+            ))
+        };
+        let mut record = entry.clone();
+        record.push(Record::I64(rowid));
+        BTreeWriter::new(&db.pager).insert_entry(
+            index.root_page,
+            &encode_record(&record),
+            &mut compare,
+        )
+    }
+}
+
+/// A row of a table as it is written, to evaluate the WHERE clause of a partial index against.
+struct WrittenRow<'a> {
+    schema: &'a TableSchema,
+    row: &'a [Record],
+    rowid: i64,
+}
+
+impl RowContext for WrittenRow<'_> {
+    fn column_value(&self, _table: Option<&str>, name: &str) -> Result<Record, Error> {
+        match self.schema.find_field(name) {
+            Some(i) if !self.schema.fields[i].rowid_alias => Ok(self.row[i].clone()),
+            _ if self.schema.is_rowid_name(name) => Ok(Record::I64(self.rowid)),
+            _ => Err(Error::NoSuchColumn(name.to_string())),
+        }
+    }
+
+    fn column_affinity(&self, _table: Option<&str>, name: &str) -> Result<Option<Affinity>, Error> {
+        Ok(self
+            .schema
+            .find_field(name)
+            .map(|i| self.schema.fields[i].affinity()))
+    }
+
+    fn column_collation(&self, _table: Option<&str>, name: &str) -> Result<Collation, Error> {
+        match self.schema.find_field(name) {
+            Some(i) => Ok(self.schema.fields[i].collation.clone()),
+            None if self.schema.is_rowid_name(name) => Ok(Collation::Binary),
+            None => Err(Error::NoSuchColumn(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        ast::Statement, btree_cursor::BTreeCursor, common::sqlite3, database::Database,
+        pager::Pager, parser::Parser, write_executor::WriteExecutor,
+    };
+
+    #[test]
+    fn test_insert_splits_pages_and_maintains_sequence() {
+        let path = std::env::temp_dir().join(format!("insert-test-{}.db", std::process::id()));
+        fs::copy("sample.db", &path).unwrap();
+        let open = || Database::from(Pager::open(&path).unwrap()).unwrap();
+        let insert = |db: &Database, sql: &str| {
+            let Statement::Insert(insert) = Parser::parse_statement(sql).unwrap() else {
+                unreachable!()
+            };
+            WriteExecutor::execute_insert(&insert, db)
+        };
+
+        let db = open();
+        // Enough rows to fill several pages, some with payloads spilling to overflow pages.
+        for i in 0..40 {
+            let name = "x".repeat(i * 150);
+            let sql = format!(
+                "INSERT INTO apples (name, color) VALUES ('{}', 'c{}')",
+                name, i
+            );
+            insert(&db, &sql).unwrap();
+        }
+        let err = insert(&db, "INSERT INTO apples (id, name) VALUES (3, 'again')").unwrap_err();
+        assert_eq!("UNIQUE constraint failed: apples.id", err.to_string());
+        drop(db);
+
+        let db = open();
+        fs::remove_file(&path).unwrap();
+        let table = &db.schema().tables["apples"];
+        let mut cursor = BTreeCursor::new(&db.pager, table.root_page);
+        let mut rows = vec![];
+        let mut valid = cursor.first().unwrap();
+        while valid {
+            rows.push((
+                cursor.rowid().unwrap(),
+                cursor
+                    .payload()
+                    .unwrap()
+                    .read_as_table_row(&table.sql_schema)
+                    .unwrap(),
+            ));
+            valid = cursor.next().unwrap();
+        }
+        assert_eq!(
+            (1..=44).collect::<Vec<_>>(),
+            rows.iter().map(|row| row.0).collect::<Vec<_>>()
+        );
+        assert_eq!(39 * 150, rows[43].1[1].unwrap_string().len());
+        assert!(db.pager.page_count() > 4);
+
+        let sequence = &db.schema().tables["sqlite_sequence"];
+        let mut cursor = BTreeCursor::new(&db.pager, sequence.root_page);
+        assert!(cursor.first().unwrap());
+        let sequence = cursor.payload().unwrap().read_record().unwrap();
+        assert_eq!(Some(44), sequence[1].as_int());
+    }
+
+    #[test]
+    fn test_update_and_delete_reuse_freed_pages() {
+        let path = std::env::temp_dir().join(format!("delete-test-{}.db", std::process::id()));
+        fs::copy("sample.db", &path).unwrap();
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let run = |sql: &str| match Parser::parse_statement(sql).unwrap() {
+            Statement::Insert(insert) => WriteExecutor::execute_insert(&insert, &db),
+            Statement::Update(update) => WriteExecutor::execute_update(&update, &db),
+            Statement::Delete(delete) => WriteExecutor::execute_delete(&delete, &db),
+            _ => unreachable!(),
+        };
+        let rowids = || {
+            let mut cursor = BTreeCursor::new(&db.pager, db.schema().tables["apples"].root_page);
+            let mut rowids = vec![];
+            let mut valid = cursor.first().unwrap();
+            while valid {
+                rowids.push(cursor.rowid().unwrap());
+                valid = cursor.next().unwrap();
+            }
+            rowids
+        };
+
+        for i in 0..30 {
+            let sql = format!(
+                "INSERT INTO apples (name) VALUES ('{}')",
+                "x".repeat(i * 200)
+            );
+            run(&sql).unwrap();
+        }
+        let page_count = db.pager.page_count();
+
+        run("DELETE FROM apples WHERE id > 4 AND id % 3 > 0").unwrap();
+        run("UPDATE apples SET id = id + 100, color = 'red' WHERE id > 30").unwrap();
+        assert_eq!(
+            vec![1, 2, 3, 4, 6, 9, 12, 15, 18, 21, 24, 27, 30, 133],
+            rowids()
+        );
+        assert!(db.pager.free_page_count().unwrap() > 0);
+
+        let err = run("UPDATE apples SET id = NULL WHERE id = 1").unwrap_err();
+        assert_eq!("datatype mismatch", err.to_string());
+        let err = run("UPDATE apples SET size = 1").unwrap_err();
+        assert_eq!("no such column: size", err.to_string());
+
+        // The rows inserted again fit in the pages freed.
+        for i in 0..20 {
+            let sql = format!(
+                "INSERT INTO apples (name) VALUES ('{}')",
+                "y".repeat(i * 200)
+            );
+            run(&sql).unwrap();
+        }
+        assert_eq!(page_count, db.pager.page_count());
+
+        run("DELETE FROM apples").unwrap();
+        assert!(rowids().is_empty());
+    }
+
+    #[test]
+    fn test_partial_index_holds_matching_rows() {
+        let path = std::env::temp_dir().join(format!("partial-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, x, y); \
+                      CREATE INDEX t_x ON t(x) WHERE y > 0; \
+                      CREATE UNIQUE INDEX t_y ON t(y) WHERE (x IS NOT NULL);";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let run = |sql: &str| match Parser::parse_statement(sql).unwrap() {
+            Statement::Insert(insert) => WriteExecutor::execute_insert(&insert, &db),
+            Statement::Update(update) => WriteExecutor::execute_update(&update, &db),
+            Statement::Delete(delete) => WriteExecutor::execute_delete(&delete, &db),
+            _ => unreachable!(),
+        };
+        run("INSERT INTO t (x, y) VALUES (1, 1), (2, -1), (3, 5), (NULL, 5), (4, NULL)").unwrap();
+        run("UPDATE t SET y = 2 WHERE x = 2").unwrap();
+        run("UPDATE t SET y = -3 WHERE x = 3").unwrap();
+        run("DELETE FROM t WHERE x = 1").unwrap();
+        // Rows outside of the unique index don't conflict with those in it.
+        run("INSERT INTO t (x, y) VALUES (NULL, 2)").unwrap();
+        let err = run("INSERT INTO t (x, y) VALUES (9, 2)").unwrap_err();
+        assert_eq!("UNIQUE constraint failed: t.y", err.to_string());
+
+        let check = "PRAGMA integrity_check; SELECT id FROM t INDEXED BY t_x WHERE y > 0;";
+        assert_eq!(Some(String::from("ok\n4\n6\n2\n")), sqlite3(&path, check));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_collated_index_entries() {
+        let path = std::env::temp_dir().join(format!("collate-test-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let schema = "CREATE TABLE t(id INTEGER PRIMARY KEY, a TEXT COLLATE NOCASE UNIQUE, b); \
+                      CREATE INDEX t_b ON t(b COLLATE RTRIM DESC);";
+        if sqlite3(&path, schema).is_none() {
+            return;
+        }
+
+        let db = Database::from(Pager::open(&path).unwrap()).unwrap();
+        let run = |sql: &str| match Parser::parse_statement(sql).unwrap() {
+            Statement::Insert(insert) => WriteExecutor::execute_insert(&insert, &db),
+            Statement::Update(update) => WriteExecutor::execute_update(&update, &db),
+            Statement::Delete(delete) => WriteExecutor::execute_delete(&delete, &db),
+            _ => unreachable!(),
+        };
+        run("INSERT INTO t (a, b) VALUES ('Apple', 'x  '), ('banana', 'x'), ('Cherry', 'X')")
+            .unwrap();
+        let err = run("INSERT INTO t (a) VALUES ('APPLE')").unwrap_err();
+        assert_eq!("UNIQUE constraint failed: t.a", err.to_string());
+        run("UPDATE t SET b = 'x ' WHERE a = 'Cherry'").unwrap();
+        run("DELETE FROM t WHERE b = 'x'").unwrap();
+
+        let check = "PRAGMA integrity_check; SELECT id FROM t INDEXED BY t_b ORDER BY b DESC;";
+        assert_eq!(Some(String::from("ok\n1\n3\n")), sqlite3(&path, check));
+        fs::remove_file(&path).unwrap();
+    }
+}